@@ -4,6 +4,7 @@
 extern crate bit_field;
 
 use bit_field::BitField;
+use core::mem;
 use core::ops::Range;
 
 /// Allocator of a bitmap, able to allocate / free bits.
@@ -18,6 +19,12 @@ use core::ops::Range;
 ///
 /// any: whether there are free bits remaining
 /// test: whether a specific bit is free
+/// next: find the first free bit no less than key
+/// free_run: the number of contiguous free bits from key, up to limit
+/// count: the number of free bits, in O(1) time
+///
+/// alloc_contiguous: allocate `size` contiguous free bits, the first one aligned to `1 << align_log2`
+/// dealloc_contiguous: free `size` contiguous bits allocated by `alloc_contiguous`
 pub trait BitAlloc: Default {
     const CAP: usize;
     fn alloc(&mut self) -> Option<usize>;
@@ -26,6 +33,20 @@ pub trait BitAlloc: Default {
     fn remove(&mut self, range: Range<usize>);
     fn any(&self) -> bool;
     fn test(&self, key: usize) -> bool;
+    fn next(&self, key: usize) -> Option<usize>;
+    fn free_run(&self, key: usize, limit: usize) -> usize;
+    fn count(&self) -> usize;
+
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        let base = find_contiguous(self, size, align_log2)?;
+        self.remove(base..base + size);
+        Some(base)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        assert!(base + size <= Self::CAP);
+        debug_assert!((base..base + size).all(|key| !self.test(key)), "double free");
+        self.insert(base..base + size);
+    }
 }
 
 pub type BitAlloc256 = BitAllocCascade16<BitAlloc16>;
@@ -39,6 +60,7 @@ pub type BitAlloc256M = BitAllocCascade16<BitAlloc16M>;
 #[derive(Default)]
 pub struct BitAllocCascade16<T: BitAlloc> {
     bitset: u16, // for each bit, 1 indicates available, 0 indicates inavailable
+    count: usize, // the number of available bits in all sub trees
     sub: [T; 16],
 }
 
@@ -50,6 +72,7 @@ impl<T: BitAlloc> BitAlloc for BitAllocCascade16<T> {
             let i = log2(self.bitset);
             let res = self.sub[i].alloc().unwrap() + i * T::CAP;
             self.bitset.set_bit(i, self.sub[i].any());
+            self.count -= 1;
             Some(res)
         } else {
             None
@@ -59,6 +82,7 @@ impl<T: BitAlloc> BitAlloc for BitAllocCascade16<T> {
         let i = key / T::CAP;
         self.sub[i].dealloc(key % T::CAP);
         self.bitset.set_bit(i, true);
+        self.count += 1;
     }
     fn insert(&mut self, range: Range<usize>) {
         self.for_range(range, |sub: &mut T, range| sub.insert(range));
@@ -72,6 +96,47 @@ impl<T: BitAlloc> BitAlloc for BitAllocCascade16<T> {
     fn test(&self, key: usize) -> bool {
         self.sub[key / T::CAP].test(key % T::CAP)
     }
+    fn next(&self, key: usize) -> Option<usize> {
+        let idx = key / T::CAP;
+        for i in idx..16 {
+            // skip the sub trees without any free bit
+            if !self.bitset.get_bit(i) {
+                continue;
+            }
+            let begin = if i == idx { key % T::CAP } else { 0 };
+            if let Some(x) = self.sub[i].next(begin) {
+                return Some(x + i * T::CAP);
+            }
+        }
+        None
+    }
+    fn free_run(&self, key: usize, limit: usize) -> usize {
+        let mut run = 0;
+        let mut offset = key % T::CAP;
+        for i in key / T::CAP..16 {
+            if run == limit {
+                break;
+            }
+            let sub_run = if !self.bitset.get_bit(i) {
+                // a sub tree without any free bit ends the run
+                0
+            } else if offset == 0 && self.sub[i].count() == T::CAP {
+                // a sub tree with all bits free is taken as a whole
+                T::CAP.min(limit - run)
+            } else {
+                self.sub[i].free_run(offset, limit - run)
+            };
+            run += sub_run;
+            if offset + sub_run != T::CAP {
+                break;
+            }
+            offset = 0;
+        }
+        run
+    }
+    fn count(&self) -> usize {
+        self.count
+    }
 }
 
 impl<T: BitAlloc> BitAllocCascade16<T> {
@@ -81,7 +146,9 @@ impl<T: BitAlloc> BitAllocCascade16<T> {
         for i in start / T::CAP..=(end - 1) / T::CAP {
             let begin = if start / T::CAP == i { start % T::CAP } else { 0 };
             let end = if end / T::CAP == i { end % T::CAP } else { T::CAP };
+            self.count -= self.sub[i].count();
             f(&mut self.sub[i], begin..end);
+            self.count += self.sub[i].count();
             self.bitset.set_bit(i, self.sub[i].any());
         }
     }
@@ -120,6 +187,41 @@ impl BitAlloc for BitAlloc16 {
     fn test(&self, key: usize) -> bool {
         self.0.get_bit(key)
     }
+    fn next(&self, key: usize) -> Option<usize> {
+        (key..16).find(|&i| self.0.get_bit(i))
+    }
+    fn free_run(&self, key: usize, limit: usize) -> usize {
+        // the bits shifted in are 0, so the run stops at the end
+        ((!(self.0 >> key)).trailing_zeros() as usize).min(limit)
+    }
+    fn count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+}
+
+/// Find `size` contiguous free bits, the first one aligned to `1 << align_log2`.
+///
+/// Jump over the allocated bits using `next()`, and measure the free bits from an aligned
+/// position using `free_run()`, level by level, so that the sub trees of a `BitAllocCascade16`
+/// with no free bit are skipped as a whole, and those with all bits free are taken as a whole.
+fn find_contiguous<T: BitAlloc>(ba: &T, size: usize, align_log2: usize) -> Option<usize> {
+    if size == 0 || size > T::CAP || align_log2 >= mem::size_of::<usize>() * 8 || !ba.any() {
+        return None;
+    }
+    let align_mask = (1usize << align_log2) - 1;
+    let mut base = 0;
+    loop {
+        base = (ba.next(base)? + align_mask) & !align_mask;
+        if base + size > T::CAP {
+            return None;
+        }
+        let run = ba.free_run(base, size);
+        if run == size {
+            return Some(base);
+        }
+        // the bit at base + run is allocated, restart after it
+        base += run + 1;
+    }
 }
 
 #[inline(always)]
@@ -206,4 +308,98 @@ mod tests {
         }
         assert!(ba.alloc().is_none());
     }
+
+    #[test]
+    fn next() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(10..20);
+        ba.insert(1000..1001);
+        assert_eq!(ba.next(0), Some(10));
+        assert_eq!(ba.next(15), Some(15));
+        assert_eq!(ba.next(20), Some(1000));
+        assert_eq!(ba.next(1001), None);
+    }
+
+    #[test]
+    fn alloc_contiguous() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(1..4096);
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(1));
+        assert_eq!(ba.alloc_contiguous(4, 2), Some(8));
+        assert_eq!(ba.alloc_contiguous(3, 4), Some(16));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(5));
+        for i in 1..19 {
+            assert_eq!(ba.test(i), i == 7 || (i >= 12 && i < 16));
+        }
+        ba.remove(100..200);
+        assert_eq!(ba.alloc_contiguous(100, 6), Some(256));
+        assert_eq!(ba.alloc_contiguous(4096, 0), None);
+        assert_eq!(ba.alloc_contiguous(16, 12), None);
+        assert_eq!(ba.alloc_contiguous(0, 0), None);
+        assert_eq!(ba.alloc_contiguous(1, 64), None);
+        assert_eq!(ba.alloc_contiguous(1, 200), None);
+
+        ba.dealloc_contiguous(8, 4);
+        ba.dealloc_contiguous(256, 100);
+        assert_eq!(ba.alloc_contiguous(4, 3), Some(8));
+        assert_eq!(ba.alloc_contiguous(128, 7), Some(256));
+    }
+
+    #[test]
+    fn free_run() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(10..5000);
+        assert_eq!(ba.free_run(0, 100), 0);
+        assert_eq!(ba.free_run(10, 100), 100);
+        assert_eq!(ba.free_run(10, 10000), 4990);
+        assert_eq!(ba.free_run(4999, 10), 1);
+        assert_eq!(ba.free_run(5000, 10), 0);
+    }
+
+    #[test]
+    fn alloc_contiguous_across_sub_trees() {
+        let mut ba = BitAlloc4K::default();
+        ba.insert(200..600);
+        // [256, 556) crosses the sub trees of 256 bits at 512
+        assert_eq!(ba.alloc_contiguous(300, 6), Some(256));
+        assert_eq!(ba.alloc_contiguous(40, 6), None);
+        assert_eq!(ba.alloc_contiguous(40, 0), Some(200));
+
+        let mut ba = BitAlloc64K::default();
+        ba.insert(4000..12300);
+        // the sub tree [4096, 8192) is all free, and taken as a whole
+        assert_eq!(ba.alloc_contiguous(8192, 5), Some(4000));
+        assert_eq!(ba.count(), 12300 - 4000 - 8192);
+        assert_eq!(ba.alloc_contiguous(100, 3), Some(12192));
+    }
+
+    #[test]
+    fn count() {
+        let mut ba = BitAlloc64K::default();
+        assert_eq!(ba.count(), 0);
+        ba.insert(100..10000);
+        ba.insert(5000..20000);
+        assert_eq!(ba.count(), 19900);
+        ba.remove(0..200);
+        assert_eq!(ba.count(), 19800);
+        let a = ba.alloc().unwrap();
+        let b = ba.alloc_contiguous(100, 4).unwrap();
+        assert_eq!(ba.count(), 19699);
+        ba.dealloc(a);
+        ba.dealloc_contiguous(b, 100);
+        assert_eq!(ba.count(), 19800);
+    }
+
+    #[test]
+    fn alloc_contiguous_all() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(0..BitAlloc64K::CAP);
+        assert_eq!(ba.alloc_contiguous(BitAlloc64K::CAP, 16), Some(0));
+        assert!(!ba.any());
+        ba.dealloc_contiguous(0, BitAlloc64K::CAP);
+        for _ in 0..16 {
+            assert!(ba.alloc_contiguous(4096, 12).is_some());
+        }
+        assert!(ba.alloc_contiguous(1, 0).is_none());
+    }
 }
@@ -0,0 +1,236 @@
+//! Buddy system
+//!
+//! Free memory is kept as blocks of `2^order` frames, each aligned to its own size.
+//! A free block of order `k` starting at frame `base` is recorded as bit `base`
+//! in the bitmap `free_area[k]`.
+//!
+//! To allocate a block of order `k`, take any free block of the smallest order `>= k`,
+//! and split it in halves until it has order `k`. The unused halves are put back.
+//!
+//! To free a block, check whether its buddy (the other half of their parent) is free.
+//! If so, merge them and continue with the parent.
+
+use super::*;
+
+/// The largest block has `2^MAX_ORDER` frames
+pub const MAX_ORDER: usize = 16;
+
+#[derive(Default)]
+pub struct BuddyAlloc<B: BitAlloc> {
+    free_area: [B; MAX_ORDER + 1],
+}
+
+impl<B: BitAlloc> FrameAllocator for BuddyAlloc<B> {
+    const CAP: usize = B::CAP;
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_block(0)
+    }
+    fn dealloc(&mut self, key: usize) {
+        self.free_block(key, 0);
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        assert_ne!(size, 0);
+        let order = log2_ceil(size).max(align_log2);
+        if order > MAX_ORDER {
+            return None;
+        }
+        let base = self.alloc_block(order)?;
+        // give back the unused tail of the block
+        self.insert(base + size..base + (1 << order));
+        Some(base)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        self.insert(base..base + size);
+    }
+    fn insert(&mut self, range: Range<usize>) {
+        let Range { mut start, end } = range;
+        assert!(end <= B::CAP);
+        // split the range into blocks aligned to their own size
+        while start < end {
+            let order = (start.trailing_zeros() as usize)
+                .min(log2_floor(end - start))
+                .min(MAX_ORDER);
+            self.free_block(start, order);
+            start += 1 << order;
+        }
+    }
+    fn remove(&mut self, range: Range<usize>) {
+        let Range { start, end } = range;
+        assert!(end <= B::CAP);
+        let mut key = start;
+        while key < end {
+            match self.find_block(key) {
+                Some((base, order)) => {
+                    // take the whole block, then give back the parts outside the range
+                    self.free_area[order].remove(base..base + 1);
+                    let block_end = base + (1 << order);
+                    self.insert(base..key);
+                    if end < block_end {
+                        self.insert(end..block_end);
+                    }
+                    key = block_end;
+                }
+                None => key += 1,
+            }
+        }
+    }
+    fn count(&self) -> usize {
+        (0..=MAX_ORDER).map(|order| self.free_area[order].count() << order).sum()
+    }
+}
+
+impl<B: BitAlloc> BuddyAlloc<B> {
+    /// Allocate a block of `2^order` frames
+    fn alloc_block(&mut self, order: usize) -> Option<usize> {
+        let mut k = (order..=MAX_ORDER).find(|&k| self.free_area[k].any())?;
+        let base = self.free_area[k].alloc().unwrap();
+        // split it, put back the higher halves
+        while k > order {
+            k -= 1;
+            self.free_area[k].insert(base + (1 << k)..base + (1 << k) + 1);
+        }
+        Some(base)
+    }
+    /// Free a block of `2^order` frames, merge it with its buddy as far as possible
+    fn free_block(&mut self, mut base: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = base ^ (1 << order);
+            if buddy >= B::CAP || !self.free_area[order].test(buddy) {
+                break;
+            }
+            self.free_area[order].remove(buddy..buddy + 1);
+            base &= !(1 << order);
+            order += 1;
+        }
+        self.free_area[order].insert(base..base + 1);
+    }
+    /// Find the free block containing frame `key`, return its base and order
+    fn find_block(&self, key: usize) -> Option<(usize, usize)> {
+        (0..=MAX_ORDER)
+            .map(|order| (key & !((1 << order) - 1), order))
+            .find(|&(base, order)| self.free_area[order].test(base))
+    }
+}
+
+fn log2_floor(x: usize) -> usize {
+    assert_ne!(x, 0);
+    (usize::max_value().count_ones() - 1 - x.leading_zeros()) as usize
+}
+
+fn log2_ceil(x: usize) -> usize {
+    let log2 = log2_floor(x);
+    if x == 1 << log2 { log2 } else { log2 + 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_allocator::BitAlloc4K;
+
+    #[test]
+    fn log2() {
+        assert_eq!(log2_floor(1), 0);
+        assert_eq!(log2_floor(5), 2);
+        assert_eq!(log2_ceil(1), 0);
+        assert_eq!(log2_ceil(5), 3);
+        assert_eq!(log2_ceil(8), 3);
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let mut ba = BuddyAlloc::<BitAlloc4K>::default();
+        ba.insert(0..16);
+        assert!(ba.free_area[4].test(0));
+
+        let a = ba.alloc().unwrap();
+        assert_eq!(a, 0);
+        for k in 0..4 {
+            assert!(ba.free_area[k].test(1 << k));
+        }
+        assert!(!ba.free_area[4].any());
+
+        ba.dealloc(a);
+        for k in 0..4 {
+            assert!(!ba.free_area[k].any());
+        }
+        assert!(ba.free_area[4].test(0));
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut ba = BuddyAlloc::<BitAlloc4K>::default();
+        ba.insert(3..13);
+        // [3] [4, 8) [8, 12) [12]
+        assert!(ba.free_area[0].test(3));
+        assert!(ba.free_area[2].test(4));
+        assert!(ba.free_area[2].test(8));
+        assert!(ba.free_area[0].test(12));
+
+        ba.remove(5..10);
+        // [3] [4] [10, 12) [12]
+        assert!(ba.free_area[0].test(4));
+        assert!(!ba.free_area[0].test(5));
+        assert!(ba.free_area[1].test(10));
+        assert!(!ba.free_area[2].any());
+
+        ba.insert(5..10);
+        assert!(ba.free_area[2].test(4));
+        assert!(ba.free_area[2].test(8));
+    }
+
+    #[test]
+    fn alloc_contiguous() {
+        let mut ba = BuddyAlloc::<BitAlloc4K>::default();
+        ba.insert(0..4096);
+        let a = ba.alloc_contiguous(3, 0).unwrap();
+        assert_eq!(a % 4, 0);
+        let b = ba.alloc_contiguous(16, 6).unwrap();
+        assert_eq!(b % 64, 0);
+        let c = ba.alloc().unwrap();
+        // the tail of `a` is given back
+        assert_eq!(c, a + 3);
+        assert!(ba.alloc_contiguous(4096, 0).is_none());
+
+        ba.dealloc(c);
+        ba.dealloc_contiguous(a, 3);
+        ba.dealloc_contiguous(b, 16);
+        assert!(ba.free_area[12].test(0));
+        assert_eq!(ba.alloc_contiguous(4096, 0), Some(0));
+        assert!(ba.alloc().is_none());
+    }
+
+    #[test]
+    fn count() {
+        let mut ba = BuddyAlloc::<BitAlloc4K>::default();
+        ba.insert(3..100);
+        assert_eq!(ba.count(), 97);
+        let a = ba.alloc_contiguous(5, 0).unwrap();
+        assert_eq!(ba.count(), 92);
+        ba.remove(90..100);
+        assert_eq!(ba.count(), 82);
+        ba.dealloc_contiguous(a, 5);
+        assert_eq!(ba.count(), 87);
+    }
+
+    #[test]
+    fn exhaust() {
+        let mut ba = BuddyAlloc::<BitAlloc4K>::default();
+        ba.insert(1..100);
+        let frames: [usize; 99] = {
+            let mut frames = [0; 99];
+            for f in frames.iter_mut() {
+                *f = ba.alloc().unwrap();
+            }
+            frames
+        };
+        assert!(ba.alloc().is_none());
+        for &f in frames.iter() {
+            assert!(f >= 1 && f < 100);
+            ba.dealloc(f);
+        }
+        // [1] [2, 4) [4, 8) ... [32, 64) [64, 96) [96, 100)
+        assert!(ba.free_area[5].test(32));
+        assert!(ba.free_area[5].test(64));
+        assert!(ba.alloc_contiguous(64, 0).is_none());
+    }
+}
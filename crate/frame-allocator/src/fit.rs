@@ -0,0 +1,216 @@
+//! Sequential fit algorithms: first fit, best fit and worst fit
+//!
+//! Free frames form runs (blocks) of contiguous free frames, ordered by address.
+//! To allocate `size` frames, all three algorithms walk through the runs, and pick one
+//! which is large enough:
+//!
+//! * `FirstFit`: the first (lowest address) one.
+//! * `BestFit`: the smallest one, to leave large runs for large requests.
+//! * `WorstFit`: the largest one, to leave large remainders after splitting.
+//!
+//! The frames are allocated from the beginning of the chosen run.
+
+use super::*;
+
+/// Set of free frames, able to walk through the runs of free frames
+///
+/// It keeps the free frames in bitmap `free`, and the used frames in bitmap `used`,
+/// so both the begin and end of a run can be found quickly with `BitAlloc::next()`.
+struct FreeMap<B: BitAlloc> {
+    free: B,
+    used: B,
+}
+
+impl<B: BitAlloc> Default for FreeMap<B> {
+    fn default() -> Self {
+        let mut used = B::default();
+        BitAlloc::insert(&mut used, 0..B::CAP);
+        FreeMap { free: B::default(), used }
+    }
+}
+
+impl<B: BitAlloc> FreeMap<B> {
+    /// Find the first run of free frames which begins at or after `key`
+    fn next_run(&self, key: usize) -> Option<Range<usize>> {
+        let start = self.free.next(key)?;
+        let end = self.used.next(start).unwrap_or(B::CAP);
+        Some(start..end)
+    }
+    /// Walk through all runs, call `f` with the base and the run
+    /// for each one able to hold `size` frames aligned to `1 << align_log2`
+    fn for_each_fit(&self, size: usize, align_log2: usize, mut f: impl FnMut(usize, &Range<usize>) -> bool) {
+        assert_ne!(size, 0);
+        let mut key = 0;
+        while let Some(run) = self.next_run(key) {
+            let base = align_up(run.start, align_log2);
+            if base + size <= run.end && !f(base, &run) {
+                return;
+            }
+            key = run.end;
+        }
+    }
+    fn take(&mut self, range: Range<usize>) {
+        BitAlloc::remove(&mut self.free, range.clone());
+        BitAlloc::insert(&mut self.used, range);
+    }
+    fn give(&mut self, range: Range<usize>) {
+        BitAlloc::insert(&mut self.free, range.clone());
+        BitAlloc::remove(&mut self.used, range);
+    }
+}
+
+macro_rules! impl_fit {
+    ($name: ident, $find: expr) => {
+        #[derive(Default)]
+        pub struct $name<B: BitAlloc>(FreeMap<B>);
+
+        impl<B: BitAlloc> FrameAllocator for $name<B> {
+            const CAP: usize = B::CAP;
+            fn alloc(&mut self) -> Option<usize> {
+                self.alloc_contiguous(1, 0)
+            }
+            fn dealloc(&mut self, key: usize) {
+                self.dealloc_contiguous(key, 1);
+            }
+            fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+                let base: Option<usize> = $find(&self.0, size, align_log2);
+                if let Some(base) = base {
+                    self.0.take(base..base + size);
+                }
+                base
+            }
+            fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+                assert!(base + size <= B::CAP);
+                debug_assert!(self.0.next_run(base).map_or(true, |run| run.start >= base + size), "double free");
+                self.0.give(base..base + size);
+            }
+            fn insert(&mut self, range: Range<usize>) {
+                self.0.give(range);
+            }
+            fn remove(&mut self, range: Range<usize>) {
+                self.0.take(range);
+            }
+            fn count(&self) -> usize {
+                self.0.free.count()
+            }
+        }
+    };
+}
+
+impl_fit!(FirstFit, |map: &FreeMap<B>, size, align_log2| {
+    let mut ret = None;
+    map.for_each_fit(size, align_log2, |base, _| {
+        ret = Some(base);
+        false
+    });
+    ret
+});
+
+impl_fit!(BestFit, |map: &FreeMap<B>, size, align_log2| {
+    let mut ret: Option<(usize, usize)> = None;
+    map.for_each_fit(size, align_log2, |base, run| {
+        let len = run.end - run.start;
+        if ret.map_or(true, |(_, best)| len < best) {
+            ret = Some((base, len));
+        }
+        // can't be better than an exact fit
+        len != size
+    });
+    ret.map(|(base, _)| base)
+});
+
+impl_fit!(WorstFit, |map: &FreeMap<B>, size, align_log2| {
+    let mut ret: Option<(usize, usize)> = None;
+    map.for_each_fit(size, align_log2, |base, run| {
+        let len = run.end - run.start;
+        if ret.map_or(true, |(_, worst)| len > worst) {
+            ret = Some((base, len));
+        }
+        true
+    });
+    ret.map(|(base, _)| base)
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_allocator::BitAlloc4K;
+
+    /// Free runs: [0, 10) [20, 23) [30, 35) [40, 4096)
+    fn init<T: FrameAllocator>() -> T {
+        let mut ba = T::default();
+        ba.insert(0..4096);
+        ba.remove(10..20);
+        ba.remove(23..30);
+        ba.remove(35..40);
+        ba
+    }
+
+    #[test]
+    fn free_map() {
+        let map: FirstFit<BitAlloc4K> = init();
+        let map = map.0;
+        assert_eq!(map.next_run(0), Some(0..10));
+        assert_eq!(map.next_run(10), Some(20..23));
+        assert_eq!(map.next_run(33), Some(33..35));
+        assert_eq!(map.next_run(40), Some(40..4096));
+        assert_eq!(map.next_run(4096), None);
+    }
+
+    #[test]
+    fn first_fit() {
+        let mut ba: FirstFit<BitAlloc4K> = init();
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(0));
+        assert_eq!(ba.alloc_contiguous(2, 3), Some(8));
+        assert_eq!(ba.alloc_contiguous(3, 0), Some(4));
+        assert_eq!(ba.alloc(), Some(7));
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(30));
+        ba.dealloc_contiguous(0, 4);
+        assert_eq!(ba.alloc(), Some(0));
+        assert_eq!(ba.alloc_contiguous(4096, 0), None);
+    }
+
+    #[test]
+    fn best_fit() {
+        let mut ba: BestFit<BitAlloc4K> = init();
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(30));
+        assert_eq!(ba.alloc(), Some(34));
+        assert_eq!(ba.alloc(), Some(20));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(21));
+        assert_eq!(ba.alloc_contiguous(2, 0), Some(0));
+        ba.dealloc_contiguous(30, 4);
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(30));
+        assert_eq!(ba.alloc_contiguous(5, 0), Some(2));
+        assert_eq!(ba.alloc_contiguous(9, 0), Some(40));
+    }
+
+    #[test]
+    fn worst_fit() {
+        let mut ba: WorstFit<BitAlloc4K> = init();
+        assert_eq!(ba.alloc_contiguous(4, 0), Some(40));
+        assert_eq!(ba.alloc(), Some(44));
+        assert_eq!(ba.alloc_contiguous(16, 4), Some(48));
+        ba.dealloc(44);
+        assert_eq!(ba.alloc_contiguous(2, 1), Some(64));
+        assert_eq!(ba.alloc(), Some(66));
+    }
+
+    #[test]
+    fn exhaust() {
+        fn test<T: FrameAllocator>() {
+            let mut ba = T::default();
+            ba.insert(0..100);
+            for _ in 0..100 {
+                assert!(ba.alloc().is_some());
+            }
+            assert!(ba.alloc().is_none());
+            assert_eq!(ba.count(), 0);
+            ba.dealloc_contiguous(50, 10);
+            assert_eq!(ba.count(), 10);
+            assert_eq!(ba.alloc_contiguous(10, 0), Some(50));
+        }
+        test::<FirstFit<BitAlloc4K>>();
+        test::<BestFit<BitAlloc4K>>();
+        test::<WorstFit<BitAlloc4K>>();
+    }
+}
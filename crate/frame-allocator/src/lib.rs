@@ -0,0 +1,86 @@
+//! Physical frame allocators
+//!
+//! Every allocator here implements `FrameAllocator`, so the kernel can choose one of them
+//! by changing a single type alias.
+//!
+//! * `BitAlloc`: the naive bitmap allocator, see crate `bit-allocator`.
+//! * `BuddyAlloc`: the buddy system.
+//! * `FirstFit`, `BestFit`, `WorstFit`: the sequential fit algorithms.
+//!
+//! Their capacity is fixed at compile time. `ZonedAlloc` puts several of them side by side,
+//! so the capacity can be chosen from the memory map at boot.
+//!
+//! All of them are built on top of `BitAlloc` bitmaps, so that they don't need the heap,
+//! and can be used before the heap is initialized.
+
+#![no_std]
+
+extern crate bit_allocator;
+
+use bit_allocator::BitAlloc;
+use core::ops::Range;
+
+pub use buddy::BuddyAlloc;
+pub use fit::{FirstFit, BestFit, WorstFit};
+pub use zone::ZonedAlloc;
+
+mod buddy;
+mod fit;
+mod zone;
+
+/// Allocator of physical frames, numbered from 0.
+///
+/// CAP: it manages frames in `[0, CAP)`.
+///
+/// alloc: allocate a free frame.
+/// dealloc: free an allocated frame.
+///
+/// alloc_contiguous: allocate `size` contiguous frames, the first one aligned to `1 << align_log2`.
+/// dealloc_contiguous: free `size` contiguous frames allocated by `alloc_contiguous`.
+///
+/// insert: mark frames in the range as available
+/// remove: mark frames in the range as unavailable
+///
+/// count: the number of free frames
+pub trait FrameAllocator: Default {
+    const CAP: usize;
+    fn alloc(&mut self) -> Option<usize>;
+    fn dealloc(&mut self, key: usize);
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize>;
+    fn dealloc_contiguous(&mut self, base: usize, size: usize);
+    fn insert(&mut self, range: Range<usize>);
+    fn remove(&mut self, range: Range<usize>);
+    fn count(&self) -> usize;
+}
+
+impl<T: BitAlloc> FrameAllocator for T {
+    const CAP: usize = <T as BitAlloc>::CAP;
+    fn alloc(&mut self) -> Option<usize> {
+        BitAlloc::alloc(self)
+    }
+    fn dealloc(&mut self, key: usize) {
+        BitAlloc::dealloc(self, key)
+    }
+    fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        BitAlloc::alloc_contiguous(self, size, align_log2)
+    }
+    fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        BitAlloc::dealloc_contiguous(self, base, size)
+    }
+    fn insert(&mut self, range: Range<usize>) {
+        BitAlloc::insert(self, range)
+    }
+    fn remove(&mut self, range: Range<usize>) {
+        BitAlloc::remove(self, range)
+    }
+    fn count(&self) -> usize {
+        BitAlloc::count(self)
+    }
+}
+
+/// Round `x` up to a multiple of `1 << align_log2`
+#[inline(always)]
+fn align_up(x: usize, align_log2: usize) -> usize {
+    let mask = (1usize << align_log2) - 1;
+    (x + mask) & !mask
+}
@@ -0,0 +1,134 @@
+//! Frame allocator over zones
+//!
+//! Zone `i` is a `FrameAllocator` managing frames `[i * CAP, (i + 1) * CAP)`.
+//! The number of zones is chosen at runtime, and they are placed in memory given by the user,
+//! since the heap is usually not available when the frame allocator is initialized.
+//!
+//! Contiguous frames never cross the boundary of zones.
+
+use super::*;
+use core::ptr;
+
+pub struct ZonedAlloc<A: FrameAllocator + 'static> {
+    zones: &'static mut [A],
+}
+
+impl<A: FrameAllocator> Default for ZonedAlloc<A> {
+    fn default() -> Self {
+        ZonedAlloc { zones: &mut [] }
+    }
+}
+
+impl<A: FrameAllocator> ZonedAlloc<A> {
+    /// The number of zones to manage frames in `[0, end)`
+    pub fn zone_num(end: usize) -> usize {
+        (end + A::CAP - 1) / A::CAP
+    }
+    /// Place `num` empty zones at `base`, replacing the old ones
+    ///
+    /// `base` must point to memory for `num` zones, which lives as long as the allocator.
+    pub unsafe fn init(&mut self, base: *mut A, num: usize) {
+        for i in 0..num {
+            ptr::write(base.add(i), A::default());
+        }
+        self.zones = core::slice::from_raw_parts_mut(base, num);
+    }
+    /// It manages frames in `[0, capacity)`
+    pub fn capacity(&self) -> usize {
+        self.zones.len() * A::CAP
+    }
+    pub fn alloc(&mut self) -> Option<usize> {
+        let i = self.zones.iter().position(|zone| zone.count() != 0)?;
+        self.zones[i].alloc().map(|key| key + i * A::CAP)
+    }
+    pub fn dealloc(&mut self, key: usize) {
+        self.zones[key / A::CAP].dealloc(key % A::CAP);
+    }
+    pub fn alloc_contiguous(&mut self, size: usize, align_log2: usize) -> Option<usize> {
+        // the base of each zone is not aligned to more than CAP
+        if size > A::CAP || 1 << align_log2 > A::CAP {
+            return None;
+        }
+        self.zones.iter_mut().enumerate()
+            .filter(|(_, zone)| zone.count() >= size)
+            .filter_map(|(i, zone)| zone.alloc_contiguous(size, align_log2).map(|key| key + i * A::CAP))
+            .next()
+    }
+    pub fn dealloc_contiguous(&mut self, base: usize, size: usize) {
+        let i = base / A::CAP;
+        assert!(base % A::CAP + size <= A::CAP, "frames cross the boundary of zones");
+        self.zones[i].dealloc_contiguous(base % A::CAP, size);
+    }
+    /// Mark frames in the range as available, it must be in `[0, capacity)`
+    pub fn insert(&mut self, range: Range<usize>) {
+        assert!(range.end <= self.capacity(), "frames {:?} out of capacity {}", range, self.capacity());
+        self.for_range(range, |zone, range| zone.insert(range));
+    }
+    /// Mark frames in the range as unavailable, frames out of capacity are ignored
+    pub fn remove(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.capacity());
+        self.for_range(range.start..end, |zone, range| zone.remove(range));
+    }
+    /// The number of free frames
+    pub fn count(&self) -> usize {
+        self.zones.iter().map(|zone| zone.count()).sum()
+    }
+    /// Split the range by zones, call `f` with each zone and the part in it
+    fn for_range(&mut self, range: Range<usize>, f: impl Fn(&mut A, Range<usize>)) {
+        let Range { mut start, end } = range;
+        while start < end {
+            let i = start / A::CAP;
+            let zone_end = ((i + 1) * A::CAP).min(end);
+            f(&mut self.zones[i], start - i * A::CAP..zone_end - i * A::CAP);
+            start = zone_end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_allocator::BitAlloc4K;
+
+    #[test]
+    fn zones() {
+        assert_eq!(ZonedAlloc::<BitAlloc4K>::zone_num(1), 1);
+        assert_eq!(ZonedAlloc::<BitAlloc4K>::zone_num(4096), 1);
+        assert_eq!(ZonedAlloc::<BitAlloc4K>::zone_num(4097), 2);
+
+        let mut storage = [BitAlloc4K::default(), BitAlloc4K::default(), BitAlloc4K::default()];
+        let mut ba = ZonedAlloc::<BitAlloc4K>::default();
+        assert_eq!(ba.capacity(), 0);
+        assert!(ba.alloc().is_none());
+        unsafe { ba.init(storage.as_mut_ptr(), 3); }
+        assert_eq!(ba.capacity(), 3 * 4096);
+
+        ba.insert(4000..9000);
+        // zone 0: [4000, 4096), zone 1: [4096, 8192), zone 2: [8192, 9000)
+        assert_eq!(ba.count(), 5000);
+        ba.remove(4000..4100);
+        ba.remove(8000..20000);
+        assert_eq!(ba.count(), 3900);
+
+        let a = ba.alloc().unwrap();
+        assert!(a >= 4100 && a < 8000);
+        assert_eq!(ba.alloc_contiguous(100, 0), Some(4100));
+        assert_eq!(ba.alloc_contiguous(4096, 0), None);
+        assert_eq!(ba.count(), 3799);
+
+        ba.dealloc(a);
+        ba.dealloc_contiguous(4100, 100);
+        assert_eq!(ba.count(), 3900);
+    }
+
+    #[test]
+    fn buddy_zones() {
+        let mut storage = [BuddyAlloc::<BitAlloc4K>::default(), BuddyAlloc::<BitAlloc4K>::default()];
+        let mut ba = ZonedAlloc::<BuddyAlloc<BitAlloc4K>>::default();
+        unsafe { ba.init(storage.as_mut_ptr(), 2); }
+        ba.insert(100..8192);
+        // the free block [4096, 8192) is in the second zone
+        assert_eq!(ba.alloc_contiguous(4096, 12), Some(4096));
+        assert_eq!(ba.count(), 3996);
+    }
+}
@@ -28,6 +28,12 @@ use core::ops::{Deref, DerefMut};
 /// Wrapper for page table, supporting shared map & copy-on-write
 pub struct CowExt{
     rc_map: FrameRcMap,
+    /// reference count of shared frames swapped out, keyed by the swap token
+    swapped_rc_map: FrameRcMap,
+    /// the number of frames (or swap slots) with any reference
+    frame_count: usize,
+    /// the number of frames with more than one reference
+    shared_count: usize,
 }
 
 impl CowExt {
@@ -39,6 +45,9 @@ impl CowExt {
     pub fn new() -> Self {
         CowExt {
             rc_map: FrameRcMap::default(),
+            swapped_rc_map: FrameRcMap::default(),
+            frame_count: 0,
+            shared_count: 0,
         }
     }
     /*
@@ -51,10 +60,12 @@ impl CowExt {
     */
     pub fn map_to_shared(&mut self, target: PhysAddr, writable: bool) {
         let frame = target / PAGE_SIZE;
+        let old = self.ref_count(&frame);
         match writable {
             true => self.rc_map.write_increase(&frame),
             false => self.rc_map.read_increase(&frame),
         }
+        self.update_count(old, old + 1);
     }
     /*
     **  @brief  unmap a virual address from physics address
@@ -64,27 +75,104 @@ impl CowExt {
     */
     pub fn unmap_shared(&mut self, target: PhysAddr, writable: bool) -> bool {
         let frame = target / PAGE_SIZE;
+        let old = self.ref_count(&frame);
         if !writable {
             self.rc_map.read_decrease(&frame);
         } 
         else {
             self.rc_map.write_decrease(&frame);
         }
-        self.rc_map.read_count(&frame) + self.rc_map.write_count(&frame) == 0
+        self.update_count(old, old - 1);
+        old == 1
         //page_table.unmap(addr);
     }
 
     pub fn is_one_shared(&mut self, target: PhysAddr) -> bool {
         let frame = target / PAGE_SIZE;
-        self.rc_map.read_count(&frame) + self.rc_map.write_count(&frame) == 1
+        self.ref_count(&frame) == 1
+    }
+    /*
+    **  @brief  move the reference count of a shared frame to its swap slot
+    **          Called when the shared frame is swapped out
+    **  @param  target: PhysAddr     the physics address of the frame swapped out
+    **  @param  token: usize         the token of the swap slot
+    **  @retval none
+    */
+    pub(crate) fn swap_out_shared(&mut self, target: PhysAddr, token: usize) {
+        let count = self.rc_map.take(&(target / PAGE_SIZE));
+        self.swapped_rc_map.put(&token, count);
+    }
+    /*
+    **  @brief  move the reference count of a swap slot back to the frame
+    **          Called when the shared frame is swapped in
+    **  @param  token: usize         the token of the swap slot
+    **  @param  target: PhysAddr     the physics address of the frame swapped in
+    **  @retval none
+    */
+    pub(crate) fn swap_in_shared(&mut self, token: usize, target: PhysAddr) {
+        let count = self.swapped_rc_map.take(&token);
+        self.rc_map.put(&(target / PAGE_SIZE), count);
+    }
+    /*
+    **  @brief  unmap a virual address whose shared frame is swapped out
+    **  @param  token: usize         the token of the swap slot
+    **  @param  writable: bool       whether the page is writable and shared
+    **  @retval bool                 whether it is the last reference to the swap slot
+    */
+    pub(crate) fn unmap_swapped(&mut self, token: usize, writable: bool) -> bool {
+        let old = self.swapped_rc_map.read_count(&token) as usize + self.swapped_rc_map.write_count(&token) as usize;
+        match writable {
+            true => self.swapped_rc_map.write_decrease(&token),
+            false => self.swapped_rc_map.read_decrease(&token),
+        }
+        self.update_count(old, old - 1);
+        old == 1
+    }
+    /*
+    **  @brief  get the number of frames managed by the COW extension
+    **  @retval usize                the number of frames with any reference
+    */
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+    /*
+    **  @brief  get the number of frames shared by more than one page
+    **  @retval usize                the number of frames with more than one reference
+    */
+    pub fn shared_count(&self) -> usize {
+        self.shared_count
+    }
+    /*
+    **  @brief  get the total reference count of the frame
+    **  @param  frame: &Frame        the frame to get the reference count
+    **  @retval usize                the sum of read and write reference count
+    */
+    fn ref_count(&mut self, frame: &Frame) -> usize {
+        self.rc_map.read_count(frame) as usize + self.rc_map.write_count(frame) as usize
+    }
+    /*
+    **  @brief  update the frame counters when the reference count of a frame changes
+    **  @param  old: usize           the reference count before
+    **  @param  new: usize           the reference count after
+    **  @retval none
+    */
+    fn update_count(&mut self, old: usize, new: usize) {
+        match (old, new) {
+            (0, 1) => self.frame_count += 1,
+            (1, 0) => self.frame_count -= 1,
+            (1, 2) => self.shared_count += 1,
+            (2, 1) => self.shared_count -= 1,
+            _ => {}
+        }
     }
 }
 
 /// A map contains reference count for shared frame
 ///
 /// It will lazily construct the `BTreeMap`, to avoid heap alloc when heap is unavailable.
+/// The counts are u32, since a frame like the zero page may be shared by a huge number of pages.
 #[derive(Default)]
-struct FrameRcMap(Option<BTreeMap<Frame, (u16, u16)>>);
+struct FrameRcMap(Option<BTreeMap<Frame, (u32, u32)>>);
 
 type Frame = usize;
 
@@ -92,17 +180,17 @@ impl FrameRcMap {
     /*
     **  @brief  get the read reference count of the frame
     **  @param  frame: &Frame        the frame to get the read reference count
-    **  @retval u16                  the read reference count
+    **  @retval u32                  the read reference count
     */
-    fn read_count(&mut self, frame: &Frame) -> u16 {
+    fn read_count(&mut self, frame: &Frame) -> u32 {
         self.map().get(frame).unwrap_or(&(0, 0)).0
     }
     /*
     **  @brief  get the write reference count of the frame
     **  @param  frame: &Frame        the frame to get the write reference count
-    **  @retval u16                  the write reference count
+    **  @retval u32                  the write reference count
     */
-    fn write_count(&mut self, frame: &Frame) -> u16 {
+    fn write_count(&mut self, frame: &Frame) -> u32 {
         self.map().get(frame).unwrap_or(&(0, 0)).1
     }
     /*
@@ -140,11 +228,28 @@ impl FrameRcMap {
         self.map().get_mut(frame).unwrap().1 -= 1;
     }
     /*
+    **  @brief  remove the reference count of the frame
+    **  @param  frame: &Frame        the frame to remove the reference count
+    **  @retval (u32, u32)           the read and write reference count removed
+    */
+    fn take(&mut self, frame: &Frame) -> (u32, u32) {
+        self.map().remove(frame).unwrap_or((0, 0))
+    }
+    /*
+    **  @brief  set the reference count of the frame
+    **  @param  frame: &Frame        the frame to set the reference count
+    **  @param  count: (u32, u32)    the read and write reference count
+    **  @retval none
+    */
+    fn put(&mut self, frame: &Frame, count: (u32, u32)) {
+        self.map().insert(frame.clone(), count);
+    }
+    /*
     **  @brief  get the internal btree map, lazily initialize the btree map if it is not present
-    **  @retval &mut BTreeMap<Frame, (u16, u16)>
+    **  @retval &mut BTreeMap<Frame, (u32, u32)>
     **                               the internal btree map
     */
-    fn map(&mut self) -> &mut BTreeMap<Frame, (u16, u16)> {
+    fn map(&mut self) -> &mut BTreeMap<Frame, (u32, u32)> {
         if self.0.is_none() {
             self.0 = Some(BTreeMap::new());
         }
@@ -188,12 +293,19 @@ pub mod test {
         pt.map_to_shared(target, false);
         assert_eq!(pt.rc_map.read_count(&frame), 1);
         assert_eq!(pt.rc_map.write_count(&frame), 2);
+        assert_eq!(pt.frame_count(), 1);
+        assert_eq!(pt.shared_count(), 1);
 
         pt.unmap_shared(target, true);
         pt.unmap_shared(target, false);
         assert_eq!(pt.rc_map.read_count(&frame), 0);
         assert_eq!(pt.rc_map.write_count(&frame), 1);
         assert!(pt.is_one_shared(target));
+        assert_eq!(pt.frame_count(), 1);
+        assert_eq!(pt.shared_count(), 0);
+
+        assert!(pt.unmap_shared(target, true));
+        assert_eq!(pt.frame_count(), 0);
     }
     
 }
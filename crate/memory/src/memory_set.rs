@@ -70,12 +70,40 @@ pub trait MemoryHandler{
 
     fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr);
 
+    // map the pages in [start, end) page by page, a handler may map them with huge pages instead
+    fn map_range(&self, pt: &mut PageTable, inpt: usize, start: VirtAddr, end: VirtAddr) {
+        for page in Page::range_of(start, end) {
+            self.map(pt, inpt, page.start_address());
+        }
+    }
+
     // noted that map_clone is used without the new inactive page table being enabled 
     fn map_clone(&mut self, inpt: usize, addr: VirtAddr);
 
     fn unmap(&self, pt: &mut PageTable, inpt: usize, addr:VirtAddr);
 
-    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool;
+    // the access is checked against the attributes of the area before it is called
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool;
+
+    fn get_flags(&self) -> MemoryAttr;
+
+    // create a handler with the attributes for part of the area, used when the area is split or protected
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler>;
+
+    // apply the attributes of the handler to a page, used when the protection of the area is changed
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr);
+
+    // write a modified page back to where its content comes from, e.g. a file, used by msync
+    fn sync(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {}
+
+    // finish the work of unmap and sync deferred until the page table is released, e.g. writing to a file
+    fn flush(&self) {}
+
+    // whether the adjacent areas split from this one can be merged back, when their attributes become the same
+    fn mergeable(&self) -> bool { true }
+
+    // make `addr` a bound between the mapped pages, used before the area is split at it, e.g. to split a huge page across it
+    fn split(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {}
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -148,6 +176,27 @@ impl MemoryArea {
         addr >= self.start_addr && addr < self.end_addr
     }
     /*
+    **  @brief  test whether the memory area is inside a virtual address range
+    **  @param  start: VirtAddr      the virtual address of beginning of the range
+    **  @param  end: VirtAddr        the virtual address of end of the range
+    **  @retval bool                 whether the memory area is inside the range
+    */
+    fn is_inside(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.start_addr >= start && self.end_addr <= end
+    }
+    /*
+    **  @brief  split the memory area at a virtual address
+    **  @param  addr: VirtAddr       the page aligned virtual address to split at
+    **  @retval MemoryArea           the higher part [addr, end), the memory area itself becomes [start, addr)
+    */
+    fn split_at(&mut self, addr: VirtAddr) -> MemoryArea {
+        assert!(self.start_addr < addr && addr < self.end_addr, "split address out of the memory area");
+        let memory_handler = self.memory_handler.box_split(self.memory_handler.get_flags());
+        let higher = MemoryArea { start_addr: addr, end_addr: self.end_addr, memory_handler, name: self.name };
+        self.end_addr = addr;
+        higher
+    }
+    /*
     **  @brief  test whether the memory area is overlap with another memory area
     **  @param  other: &MemoryArea   another memory area to test
     **  @retval bool                 whether the memory area is overlap with another memory area
@@ -165,10 +214,7 @@ impl MemoryArea {
     **  @retval none
     */
     fn map(&self, pt: &mut PageTable, inpt: usize) {
-        for page in Page::range_of(self.start_addr, self.end_addr) {
-            let addr = page.start_address();
-            self.memory_handler.map(pt, inpt, addr);
-        }
+        self.memory_handler.map_range(pt, inpt, self.start_addr, self.end_addr);
     }
     /*
     **  @brief  unmap the memory area from the physice address in a page table
@@ -182,6 +228,20 @@ impl MemoryArea {
         }
     }
 
+    /*
+    **  @brief  change the attributes of the memory area, and apply them to its pages in a page table
+    **  @param  pt: &mut T::Active   the page table to use
+    **  @param  flags: MemoryAttr    the new attributes
+    **  @retval none
+    */
+    fn protect(&mut self, pt: &mut PageTable, inpt: usize, flags: MemoryAttr) {
+        self.memory_handler = self.memory_handler.box_split(flags);
+        for page in Page::range_of(self.start_addr, self.end_addr) {
+            let addr = page.start_address();
+            self.memory_handler.protect(pt, inpt, addr);
+        }
+    }
+
     pub fn get_start_addr(&self) -> VirtAddr {
         self.start_addr
     }
@@ -189,14 +249,45 @@ impl MemoryArea {
     pub fn get_end_addr(&self) -> VirtAddr{
         self.end_addr
     }
-    /*
-    pub fn get_flags(&self) -> &MemoryAttr{
-        &self.flags
+
+    pub fn get_flags(&self) -> MemoryAttr{
+        self.memory_handler.get_flags()
     }
+
+    /*
+    **  @brief  handle a page fault in the memory area, by its handler
+    **  @param  page_table: &mut T::Active
+    **                               the page table to use
+    **  @param  fault: PageFault     the page fault in the memory area
+    **  @retval bool                 whether the page fault is handled, false if the access is not allowed
     */
+    pub fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        if !self.get_flags().allows(fault.access) {
+            return false;
+        }
+        if fault.user && !self.get_flags().is_user() {
+            return false;
+        }
+        self.memory_handler.page_fault_handler(page_table, inpt, fault)
+    }
 
-    pub fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool {
-        self.memory_handler.page_fault_handler(page_table, inpt, addr)
+    /*
+    **  @brief  write back the pages of the memory area in a virtual address range
+    **  @param  pt: &mut T::Active   the page table to use
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the range
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the range
+    **  @retval none
+    */
+    fn sync(&self, pt: &mut PageTable, inpt: usize, start: VirtAddr, end: VirtAddr) {
+        let start = start.max(self.start_addr);
+        let end = end.min(self.end_addr);
+        if start >= end {
+            return;
+        }
+        for page in Page::range_of(start, end) {
+            let addr = page.start_address();
+            self.memory_handler.sync(pt, inpt, addr);
+        }
     }
 
     fn map_clone(&mut self, inpt: usize){
@@ -258,16 +349,72 @@ impl MemoryAttr {
         entry.update();
     }
 
+    /*
+    **  @brief  apply the memory attribute to a page table entry, but keep its present bit
+    **          used when changing the protection of pages, which may be not present now
+    **  @param  entry: &mut impl Entry
+    **                               the page table entry to apply the attribute
+    **  @retval none
+    */
+    pub fn apply_protection(&self, entry: &mut Entry) {
+        entry.set_user(self.user);
+        entry.set_writable(!self.readonly);
+        entry.set_execute(self.execute);
+        entry.update();
+    }
+
     pub fn is_readonly(&self) -> bool {
         self.readonly
     }
+
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+
+    /*
+    **  @brief  test whether an access is allowed by the memory attribute
+    **  @param  access: Access       the kind of the access
+    **  @retval bool                 whether the access is allowed
+    */
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => true,
+            Access::Write => !self.readonly,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// The kind of memory access
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// The description of a page fault, given by the trap handler of each architecture
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PageFault {
+    /// the virtual address accessed
+    pub addr: VirtAddr,
+    /// the kind of the access
+    pub access: Access,
+    /// whether the access is from user mode
+    pub user: bool,
+    /// whether the page is present, then it is a protection fault rather than a missing page
+    pub present: bool,
 }
 
 /// set of memory space with multiple memory area with associated page table and stack space
 /// like `mm_struct` in ucore
 pub struct MemorySet<T: InactivePageTable> {
     areas: Vec<MemoryArea>,
-    page_table: T,
+    // Boxed, so its address is stable. The handlers keep the raw pointer `pt_ptr` to it, e.g. the kernel
+    // swap manager records it for every swappable frame to unmap the frame from its owner when swapping out.
+    // `clone` builds the new set of a forked process locally and returns it by value, so an unboxed table
+    // would move, and swapping out a page of the child would then edit a dangling table.
+    page_table: Box<T>,
 }
 
 impl<T: InactivePageTable> MemorySet<T> {
@@ -278,13 +425,13 @@ impl<T: InactivePageTable> MemorySet<T> {
     pub fn new() -> Self {
         MemorySet {
             areas: Vec::<MemoryArea>::new(),
-            page_table: T::new(),
+            page_table: Box::new(T::new()),
         }
     }
     pub fn new_bare() -> Self {
         MemorySet {
             areas: Vec::<MemoryArea>::new(),
-            page_table: T::new_bare(),
+            page_table: Box::new(T::new_bare()),
         }
     }
     /*
@@ -304,11 +451,212 @@ impl<T: InactivePageTable> MemorySet<T> {
         assert!(self.areas.iter()
                     .find(|other| area.is_overlap_with(other))
                     .is_none(), "memory area overlap");
-        let pt_ptr = (&mut self.page_table) as *mut T as usize;
+        let pt_ptr = (&mut *self.page_table) as *mut T as usize;
         self.page_table.edit(|pt| area.map(pt, pt_ptr));
         self.areas.push(area);
     }
     /*
+    **  @brief  unmap the pages in a virtual address range, the memory areas across its bounds are split
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the range
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the range
+    **  @retval none
+    */
+    pub fn unmap(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.split(start);
+        self.split(end);
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
+        let (removed, kept): (Vec<MemoryArea>, Vec<MemoryArea>) = areas.drain(..)
+            .partition(|area| area.is_inside(start, end));
+        *areas = kept;
+        page_table.edit(|pt| {
+            for area in removed.iter() {
+                area.unmap(pt, pt_ptr);
+            }
+        });
+        for area in removed.iter() {
+            area.memory_handler.flush();
+        }
+    }
+    /*
+    **  @brief  change the attributes of the pages in a virtual address range,
+    **          the memory areas across its bounds are split, and the neighbours with the same attributes are merged
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the range
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the range
+    **  @param  flags: MemoryAttr    the new attributes
+    **  @retval bool                 false if some pages in the range are not in any memory area, then nothing is changed
+    */
+    pub fn protect(&mut self, start: VirtAddr, end: VirtAddr, flags: MemoryAttr) -> bool {
+        if !self.covers(start, end) {
+            return false;
+        }
+        self.split(start);
+        self.split(end);
+        {
+            let Self { ref mut page_table, ref mut areas, .. } = self;
+            let pt_ptr = (&mut **page_table) as *mut T as usize;
+            page_table.edit(|pt| {
+                for area in areas.iter_mut().filter(|area| area.is_inside(start, end)) {
+                    area.protect(pt, pt_ptr, flags);
+                }
+            });
+        }
+        self.merge();
+        true
+    }
+    /*
+    **  @brief  test whether all pages in a virtual address range are in the memory areas
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the range
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the range
+    **  @retval bool                 whether the range is covered by the memory areas
+    */
+    pub fn covers(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut addr = start;
+        while addr < end {
+            match self.find_area(addr) {
+                Some(area) => addr = Page::of_addr(area.end_addr - 1).start_address() + PAGE_SIZE,
+                None => return false,
+            }
+        }
+        true
+    }
+    /*
+    **  @brief  extend the memory area ending at a virtual address, the new pages are mapped by its handler
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the memory area
+    **  @param  new_end: VirtAddr    the page aligned virtual address of the new end
+    **  @retval bool                 false if no memory area ends at `end`, or the new pages overlap other areas,
+    **                               then nothing is changed
+    */
+    pub fn extend(&mut self, end: VirtAddr, new_end: VirtAddr) -> bool {
+        assert!(end <= new_end && new_end % PAGE_SIZE == 0, "invalid new end of the memory area");
+        if self.find_free_area(end, new_end, new_end - end) != Some(end) {
+            return false;
+        }
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
+        match areas.iter_mut().find(|area| area.end_addr == end) {
+            Some(area) => {
+                page_table.edit(|pt| area.memory_handler.map_range(pt, pt_ptr, end, new_end));
+                area.end_addr = new_end;
+                true
+            }
+            None => false,
+        }
+    }
+    /*
+    **  @brief  extend the memory area beginning at a virtual address downward, e.g. for a stack,
+    **          the new pages are mapped by its handler
+    **          noted that the handler should not depend on the beginning of the area
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the memory area
+    **  @param  new_start: VirtAddr  the page aligned virtual address of the new beginning
+    **  @retval bool                 false if no memory area begins at `start`, or the new pages overlap other areas,
+    **                               then nothing is changed
+    */
+    pub fn extend_down(&mut self, start: VirtAddr, new_start: VirtAddr) -> bool {
+        assert!(new_start <= start && new_start % PAGE_SIZE == 0, "invalid new beginning of the memory area");
+        if self.find_free_area(new_start, start, start - new_start) != Some(new_start) {
+            return false;
+        }
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
+        match areas.iter_mut().find(|area| area.start_addr == start) {
+            Some(area) => {
+                page_table.edit(|pt| area.memory_handler.map_range(pt, pt_ptr, new_start, start));
+                area.start_addr = new_start;
+                true
+            }
+            None => false,
+        }
+    }
+    /*
+    **  @brief  write back the modified pages in a virtual address range
+    **  @param  start: VirtAddr      the page aligned virtual address of beginning of the range
+    **  @param  end: VirtAddr        the page aligned virtual address of end of the range
+    **  @retval bool                 false if some pages in the range are not in any memory area, then nothing is written
+    */
+    pub fn sync(&mut self, start: VirtAddr, end: VirtAddr) -> bool {
+        if !self.covers(start, end) {
+            return false;
+        }
+        let Self { ref mut page_table, ref areas, .. } = self;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
+        page_table.edit(|pt| {
+            for area in areas.iter() {
+                area.sync(pt, pt_ptr, start, end);
+            }
+        });
+        for area in areas.iter() {
+            area.memory_handler.flush();
+        }
+        true
+    }
+    /*
+    **  @brief  find a free virtual address range not overlapped with any memory area
+    **  @param  start: VirtAddr      the page aligned virtual address to begin searching at
+    **  @param  end: VirtAddr        the virtual address the range should not go beyond
+    **  @param  len: usize           the length of the range
+    **  @retval Option<VirtAddr>     the beginning of the lowest free range at or above `start`, if presented
+    */
+    pub fn find_free_area(&self, start: VirtAddr, end: VirtAddr, len: usize) -> Option<VirtAddr> {
+        let mut addr = start;
+        loop {
+            let addr_end = addr.checked_add(len)?;
+            if addr_end > end {
+                return None;
+            }
+            let next = self.areas.iter()
+                .filter(|area| area.start_addr < addr_end && Page::of_addr(area.end_addr - 1).start_address() + PAGE_SIZE > addr)
+                .map(|area| Page::of_addr(area.end_addr - 1).start_address() + PAGE_SIZE)
+                .max();
+            match next {
+                Some(next) => addr = next,
+                None => return Some(addr),
+            }
+        }
+    }
+    /*
+    **  @brief  split the memory area containing a virtual address at it
+    **  @param  addr: VirtAddr       the page aligned virtual address to split at
+    **  @retval none
+    */
+    fn split(&mut self, addr: VirtAddr) {
+        assert_eq!(addr % PAGE_SIZE, 0, "split address should be page aligned");
+        let Self { ref mut page_table, ref mut areas, .. } = self;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
+        let higher = match areas.iter_mut().find(|area| area.start_addr < addr && addr < area.end_addr) {
+            Some(area) => {
+                page_table.edit(|pt| area.memory_handler.split(pt, pt_ptr, addr));
+                Some(area.split_at(addr))
+            }
+            None => None,
+        };
+        if let Some(area) = higher {
+            areas.push(area);
+        }
+    }
+    /*
+    **  @brief  merge the adjacent memory areas with the same name and attributes
+    **          noted that the areas of the same name are created by the same handler
+    **  @retval none
+    */
+    fn merge(&mut self) {
+        self.areas.sort_by_key(|area| area.start_addr);
+        let mut i = 1;
+        while i < self.areas.len() {
+            let mergeable = {
+                let (prev, area) = (&self.areas[i - 1], &self.areas[i]);
+                prev.end_addr == area.start_addr && prev.name == area.name && prev.get_flags() == area.get_flags()
+                    && prev.memory_handler.mergeable() && area.memory_handler.mergeable()
+            };
+            if mergeable {
+                let area = self.areas.remove(i);
+                self.areas[i - 1].end_addr = area.end_addr;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    /*
     **  @brief  get iterator of the memory area
     **  @retval impl Iterator<Item=&MemoryArea>
     **                               the memory area iterator
@@ -344,13 +692,16 @@ impl<T: InactivePageTable> MemorySet<T> {
     */
     pub fn clear(&mut self) {
         let Self { ref mut page_table, ref mut areas, .. } = self;
-        let pt_ptr = (page_table) as *mut T as usize;
+        let pt_ptr = (&mut **page_table) as *mut T as usize;
         info!("come in to clear");
         page_table.edit(|pt| {
             for area in areas.iter() {
                 area.unmap(pt, pt_ptr);
             }
         });
+        for area in areas.iter() {
+            area.memory_handler.flush();
+        }
         info!("finish unmmap");
         areas.clear();
         info!("finish clear");
@@ -368,8 +719,8 @@ impl<T: InactivePageTable> MemorySet<T> {
 
 impl<T: InactivePageTable> Clone for MemorySet<T> {
     fn clone(&self) -> Self {
-        let mut page_table = T::new();
-        let pt_ptr = (&mut page_table) as *mut T as usize;
+        let mut page_table = Box::new(T::new());
+        let pt_ptr = (&mut *page_table) as *mut T as usize;
         let mut newareas = self.areas.clone();
         
         for area in newareas.iter_mut(){
@@ -407,4 +758,524 @@ impl<T: InactivePageTable> Debug for MemorySet<T> {
             .finish()
     }
 }
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cow::CowExt;
+    use swap::SwapExt;
+    use swap::fifo::FifoSwapManager;
+    use swap::mock_swapper::MockSwapper;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    type Inactive = MockInactivePageTable;
+    type MockSwapExt = SwapExt<FifoSwapManager, MockSwapper, Inactive>;
+
+    /// Allocate a frame for each page, and copy it on fork, like `NormalMemoryHandler` in the kernel
+    #[derive(Clone)]
+    struct ByFrame(MemoryAttr);
+
+    impl MemoryHandler for ByFrame {
+        fn box_clone(&self) -> Box<MemoryHandler> {
+            Box::new(self.clone())
+        }
+        fn map(&self, pt: &mut PageTable, _: usize, addr: VirtAddr) {
+            let target = Inactive::alloc_frame().expect("failed to allocate frame");
+            self.0.apply(pt.map(addr, target));
+        }
+        fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+            let data = MockActivePageTable.get_page_slice_mut(addr).to_vec();
+            let page_table = unsafe { &mut *(inpt as *mut Inactive) };
+            page_table.edit(|pt| self.map(pt, inpt, addr));
+            unsafe { page_table.with(|| MockActivePageTable.get_page_slice_mut(addr).copy_from_slice(&data)); }
+        }
+        fn unmap(&self, pt: &mut PageTable, _: usize, addr: VirtAddr) {
+            Inactive::dealloc_frame(pt.get_entry(addr).expect("fail to get entry").target());
+            pt.unmap(addr);
+        }
+        fn page_fault_handler(&self, _: &mut PageTable, _: usize, _: PageFault) -> bool {
+            false
+        }
+        fn get_flags(&self) -> MemoryAttr {
+            self.0
+        }
+        fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+            Box::new(ByFrame(flags))
+        }
+        fn protect(&self, pt: &mut PageTable, _: usize, addr: VirtAddr) {
+            self.0.apply_protection(pt.get_entry(addr).expect("fail to get entry"));
+        }
+    }
+
+    /// Allocate a swappable frame for each page, like `SwapMemoryHandler` in the kernel
+    #[derive(Clone)]
+    struct Swap {
+        swap: Rc<RefCell<MockSwapExt>>,
+        flags: MemoryAttr,
+    }
+
+    impl MemoryHandler for Swap {
+        fn box_clone(&self) -> Box<MemoryHandler> {
+            Box::new(self.clone())
+        }
+        fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+            let target = Inactive::alloc_frame().expect("failed to allocate frame");
+            self.flags.apply(pt.map(addr, target));
+            unsafe { self.swap.borrow_mut().set_swappable(pt, inpt as *mut Inactive, addr); }
+        }
+        fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+            let data = MockActivePageTable.get_page_slice_mut(addr).to_vec();
+            let page_table = unsafe { &mut *(inpt as *mut Inactive) };
+            page_table.edit(|pt| self.map(pt, inpt, addr));
+            unsafe { page_table.with(|| MockActivePageTable.get_page_slice_mut(addr).copy_from_slice(&data)); }
+        }
+        fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+            unsafe {
+                self.swap.borrow_mut().remove_from_swappable(pt, inpt as *mut Inactive, addr,
+                    || Inactive::alloc_frame().expect("failed to allocate frame"));
+            }
+            Inactive::dealloc_frame(pt.get_entry(addr).expect("fail to get entry").target());
+            pt.unmap(addr);
+        }
+        fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+            if fault.present || !page_table.get_entry(fault.addr).map_or(false, |entry| entry.swapped()) {
+                return false;
+            }
+            let target = Inactive::alloc_frame().expect("failed to allocate frame");
+            self.swap.borrow_mut().swap_in(page_table, inpt as *mut Inactive, Page::of_addr(fault.addr).start_address(), target).is_ok()
+        }
+        fn get_flags(&self) -> MemoryAttr {
+            self.flags
+        }
+        fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+            Box::new(Swap { flags, ..self.clone() })
+        }
+        fn protect(&self, pt: &mut PageTable, _: usize, addr: VirtAddr) {
+            self.flags.apply_protection(pt.get_entry(addr).expect("fail to get entry"));
+        }
+    }
+
+    /// Share the frames with copy-on-write on fork, like `CowMemoryHandler` in the kernel
+    #[derive(Clone)]
+    struct Cow {
+        cow: Rc<RefCell<CowExt>>,
+        swap: Rc<RefCell<MockSwapExt>>,
+        flags: MemoryAttr,
+    }
+
+    impl Cow {
+        fn set_swappable_shared(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr, writable: bool) {
+            unsafe { self.swap.borrow_mut().set_swappable_shared(pt, inpt as *mut Inactive, addr, writable, &mut self.cow.borrow_mut()); }
+        }
+    }
+
+    impl MemoryHandler for Cow {
+        fn box_clone(&self) -> Box<MemoryHandler> {
+            Box::new(self.clone())
+        }
+        fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+            let target = Inactive::alloc_frame().expect("failed to allocate frame");
+            self.flags.apply(pt.map(addr, target));
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            entry.set_shared(!self.flags.is_readonly());
+            entry.update();
+            self.set_swappable_shared(pt, inpt, addr, !self.flags.is_readonly());
+        }
+        fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+            let active = &mut MockActivePageTable;
+            // the new page will share the frame, so swap it in first
+            if active.get_entry(addr).expect("fail to get entry").swapped() {
+                let target = Inactive::alloc_frame().expect("failed to allocate frame");
+                self.swap.borrow_mut().swap_in_shared(active, addr, target, &mut self.cow.borrow_mut()).ok().unwrap();
+            }
+            let target = {
+                let entry = active.get_entry(addr).expect("fail to get entry");
+                entry.set_writable(false);
+                entry.update();
+                entry.target()
+            };
+            let page_table = unsafe { &mut *(inpt as *mut Inactive) };
+            page_table.edit(|pt| {
+                self.flags.apply(pt.map(addr, target));
+                let entry = pt.get_entry(addr).expect("fail to get entry");
+                entry.set_writable(false);
+                entry.set_shared(!self.flags.is_readonly());
+                entry.update();
+                self.set_swappable_shared(pt, inpt, addr, !self.flags.is_readonly());
+            });
+        }
+        fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+            let writable = pt.get_entry(addr).expect("fail to get entry").writable_shared();
+            let last = unsafe {
+                self.swap.borrow_mut().remove_from_swappable_shared(pt, inpt as *mut Inactive, addr, writable, &mut self.cow.borrow_mut())
+            };
+            pt.unmap(addr);
+            if let Some(target) = last {
+                Inactive::dealloc_frame(target);
+            }
+        }
+        fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+            let addr = Page::of_addr(fault.addr).start_address();
+            if !fault.present {
+                // the shared frame is swapped out, swap it in for all pages sharing it
+                if !page_table.get_entry(addr).map_or(false, |entry| entry.swapped()) {
+                    return false;
+                }
+                let target = Inactive::alloc_frame().expect("failed to allocate frame");
+                return self.swap.borrow_mut().swap_in_shared(page_table, addr, target, &mut self.cow.borrow_mut()).is_ok();
+            }
+            if fault.access != Access::Write || self.flags.is_readonly() {
+                return false;
+            }
+            let (target, writable) = {
+                let entry = page_table.get_entry(addr).expect("fail to get entry");
+                (entry.target(), entry.writable_shared())
+            };
+            if self.cow.borrow_mut().is_one_shared(target) {
+                let entry = page_table.get_entry(addr).expect("fail to get entry");
+                entry.set_writable(true);
+                entry.update();
+                return true;
+            }
+            // copy the page to a new frame
+            let data = page_table.get_page_slice_mut(addr).to_vec();
+            unsafe {
+                self.swap.borrow_mut().remove_from_swappable_shared(page_table, inpt as *mut Inactive, addr, writable, &mut self.cow.borrow_mut());
+            }
+            {
+                let entry = page_table.get_entry(addr).expect("fail to get entry");
+                entry.set_writable(true);
+                entry.set_shared(true);
+                entry.set_target(Inactive::alloc_frame().expect("failed to allocate frame"));
+                entry.update();
+            }
+            self.set_swappable_shared(page_table, inpt, addr, true);
+            page_table.get_page_slice_mut(addr).copy_from_slice(&data);
+            true
+        }
+        fn get_flags(&self) -> MemoryAttr {
+            self.flags
+        }
+        fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+            Box::new(Cow { flags, ..self.clone() })
+        }
+        fn protect(&self, pt: &mut PageTable, _: usize, addr: VirtAddr) {
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            self.flags.apply_protection(entry);
+            if !entry.present() || !self.cow.borrow_mut().is_one_shared(entry.target()) {
+                entry.set_writable(false);
+                entry.update();
+            }
+        }
+    }
+
+    fn new_exts() -> (Rc<RefCell<CowExt>>, Rc<RefCell<MockSwapExt>>) {
+        let swap = SwapExt::new(FifoSwapManager::default(), MockSwapper::default());
+        (Rc::new(RefCell::new(CowExt::new())), Rc::new(RefCell::new(swap)))
+    }
+
+    /*
+    **  @brief  access a virtual address of the memory set like the CPU,
+    **          the page faults are handled by the memory area containing it
+    **  @param  ms: &mut MemorySet<Inactive>
+    **                               the memory set, activated before the access
+    **  @param  addr: VirtAddr       the virtual address to access
+    **  @param  access: Access       the kind of the access
+    **  @retval none
+    */
+    fn access(ms: &mut MemorySet<Inactive>, addr: VirtAddr, access: Access) {
+        unsafe { ms.activate(); }
+        loop {
+            let fault = match MockActivePageTable.get_entry(addr) {
+                Some(ref entry) if entry.present() && (access != Access::Write || entry.writable()) => return,
+                Some(entry) => PageFault { addr, access, user: true, present: entry.present() },
+                None => PageFault { addr, access, user: true, present: false },
+            };
+            let inpt = ms.get_page_table_mut() as *mut Inactive as usize;
+            let handled = ms.find_area(addr)
+                .map_or(false, |area| area.page_fault_handler(&mut MockActivePageTable, inpt, fault));
+            assert!(handled, "page fault at {:#x} is not handled", addr);
+        }
+    }
+
+    fn read(ms: &mut MemorySet<Inactive>, addr: VirtAddr) -> u8 {
+        access(ms, addr, Access::Read);
+        MockActivePageTable.read(addr)
+    }
+
+    fn write(ms: &mut MemorySet<Inactive>, addr: VirtAddr, data: u8) {
+        access(ms, addr, Access::Write);
+        MockActivePageTable.write(addr, data);
+    }
+
+    fn fork(ms: &mut MemorySet<Inactive>) -> MemorySet<Inactive> {
+        // the memory set is cloned with its page table active
+        unsafe { ms.activate(); }
+        ms.clone()
+    }
+
+    fn mapped_pages(ms: &mut MemorySet<Inactive>) -> usize {
+        let mut count = 0;
+        ms.get_page_table_mut().edit(|pt| count = MappedPages::new(pt, 0, !0).count());
+        count
+    }
+
+    #[test]
+    fn push_and_clear() {
+        let frames = Inactive::allocated_frames();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x4000, Box::new(ByFrame(MemoryAttr::default().user())), "data"));
+        assert_eq!(Inactive::allocated_frames(), frames + 3);
+        assert_eq!(mapped_pages(&mut ms), 3);
+
+        write(&mut ms, 0x1000, 1);
+        write(&mut ms, 0x3fff, 2);
+        assert_eq!(read(&mut ms, 0x1000), 1);
+        assert_eq!(read(&mut ms, 0x3fff), 2);
+
+        ms.clear();
+        assert_eq!(mapped_pages(&mut ms), 0);
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    fn areas(ms: &MemorySet<Inactive>) -> Vec<(VirtAddr, VirtAddr, MemoryAttr)> {
+        let mut areas: Vec<_> = ms.iter().map(|area| (area.start_addr, area.end_addr, area.get_flags())).collect();
+        areas.sort_by_key(|&(start, _, _)| start);
+        areas
+    }
+
+    #[test]
+    fn unmap_in_area() {
+        let frames = Inactive::allocated_frames();
+        let flags = MemoryAttr::default().user();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x5000, Box::new(ByFrame(flags)), "data"));
+        write(&mut ms, 0x1000, 1);
+        write(&mut ms, 0x4000, 4);
+
+        ms.unmap(0x2000, 0x4000);
+        assert_eq!(areas(&ms), vec![(0x1000, 0x2000, flags), (0x4000, 0x5000, flags)]);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+        assert_eq!(mapped_pages(&mut ms), 2);
+        assert!(MockActivePageTable.get_entry(0x2000).is_none());
+        assert_eq!(read(&mut ms, 0x1000), 1);
+        assert_eq!(read(&mut ms, 0x4000), 4);
+
+        ms.clear();
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn unmap_across_areas() {
+        let frames = Inactive::allocated_frames();
+        let flags = MemoryAttr::default().user();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x3000, Box::new(ByFrame(flags)), "a"));
+        ms.push(MemoryArea::new(0x3000, 0x5000, Box::new(ByFrame(flags.execute())), "b"));
+
+        ms.unmap(0x2000, 0x4000);
+        assert_eq!(areas(&ms), vec![(0x1000, 0x2000, flags), (0x4000, 0x5000, flags.execute())]);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+        assert_eq!(mapped_pages(&mut ms), 2);
+
+        ms.clear();
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn protect_and_merge() {
+        let frames = Inactive::allocated_frames();
+        let flags = MemoryAttr::default().user();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x4000, Box::new(ByFrame(flags)), "data"));
+        write(&mut ms, 0x2000, 2);
+        assert!(!ms.protect(0x3000, 0x5000, flags.readonly()));
+
+        assert!(ms.protect(0x2000, 0x3000, flags.readonly()));
+        assert_eq!(areas(&ms), vec![(0x1000, 0x2000, flags), (0x2000, 0x3000, flags.readonly()), (0x3000, 0x4000, flags)]);
+        unsafe { ms.activate(); }
+        assert!(MockActivePageTable.get_entry(0x1000).unwrap().writable());
+        assert!(!MockActivePageTable.get_entry(0x2000).unwrap().writable());
+        assert!(MockActivePageTable.get_entry(0x3000).unwrap().writable());
+        assert_eq!(read(&mut ms, 0x2000), 2);
+
+        // merged back after the protection is restored
+        assert!(ms.protect(0x2000, 0x3000, flags));
+        assert_eq!(areas(&ms), vec![(0x1000, 0x4000, flags)]);
+        assert!(MockActivePageTable.get_entry(0x2000).unwrap().writable());
+        assert_eq!(Inactive::allocated_frames(), frames + 3);
+
+        ms.clear();
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn fork_copies_memory() {
+        let frames = Inactive::allocated_frames();
+        let mut parent = MemorySet::<Inactive>::new();
+        parent.push(MemoryArea::new(0x1000, 0x3000, Box::new(ByFrame(MemoryAttr::default().user())), "data"));
+        write(&mut parent, 0x1000, 1);
+
+        let mut child = fork(&mut parent);
+        assert_ne!(child.token(), parent.token());
+        assert_eq!(Inactive::allocated_frames(), frames + 4);
+        assert_eq!(read(&mut child, 0x1000), 1);
+        write(&mut child, 0x1000, 2);
+        assert_eq!(read(&mut parent, 0x1000), 1);
+        assert_eq!(read(&mut child, 0x1000), 2);
+
+        drop(child);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+        drop(parent);
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn cow_fault_after_fork() {
+        let frames = Inactive::allocated_frames();
+        let (cow, swap) = new_exts();
+        let mut parent = MemorySet::<Inactive>::new();
+        let handler = Cow { cow: cow.clone(), swap: swap.clone(), flags: MemoryAttr::default().user() };
+        parent.push(MemoryArea::new(0x1000, 0x3000, Box::new(handler), "cow"));
+        write(&mut parent, 0x1000, 1);
+        write(&mut parent, 0x2000, 2);
+
+        // the frames are shared instead of copied
+        let mut child = fork(&mut parent);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+        assert_eq!(cow.borrow().shared_count(), 2);
+        assert_eq!(read(&mut child, 0x1000), 1);
+
+        // the first write makes a copy
+        write(&mut child, 0x1000, 3);
+        assert_eq!(Inactive::allocated_frames(), frames + 3);
+        assert_eq!(cow.borrow().shared_count(), 1);
+        assert_eq!(read(&mut parent, 0x1000), 1);
+        assert_eq!(read(&mut child, 0x1000), 3);
+
+        // the last page sharing the frame takes it without a copy
+        write(&mut parent, 0x1000, 4);
+        assert_eq!(Inactive::allocated_frames(), frames + 3);
+        assert_eq!(read(&mut child, 0x1000), 3);
+
+        drop(child);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+        assert_eq!(cow.borrow().shared_count(), 0);
+        assert_eq!(read(&mut parent, 0x2000), 2);
+        drop(parent);
+        assert_eq!(Inactive::allocated_frames(), frames);
+        assert_eq!(cow.borrow().frame_count(), 0);
+    }
+
+    #[test]
+    fn swap_in_and_out() {
+        let frames = Inactive::allocated_frames();
+        let (cow, swap) = new_exts();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x3000, Box::new(Swap { swap: swap.clone(), flags: MemoryAttr::default().user() }), "swap"));
+        write(&mut ms, 0x1000, 1);
+        write(&mut ms, 0x2000, 2);
+
+        // swapped out in the order they are mapped
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+        assert_eq!(swap.borrow().swapped_count(), 1);
+        assert!(MockActivePageTable.get_entry(0x1000).unwrap().swapped());
+
+        // swapped in by the page fault
+        assert_eq!(read(&mut ms, 0x1000), 1);
+        assert_eq!(swap.borrow().swapped_count(), 0);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+
+        // a page swapped out is freed on unmapping
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+        assert!(MockActivePageTable.get_entry(0x2000).unwrap().swapped());
+        ms.clear();
+        assert_eq!(swap.borrow().swapped_count(), 0);
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn fork_swappable() {
+        let frames = Inactive::allocated_frames();
+        let (cow, swap) = new_exts();
+        let mut parent = MemorySet::<Inactive>::new();
+        parent.push(MemoryArea::new(0x1000, 0x3000, Box::new(Swap { swap: swap.clone(), flags: MemoryAttr::default().user() }), "swap"));
+        write(&mut parent, 0x1000, 1);
+
+        // the pages of the child are copied, and swappable as well
+        let mut child = fork(&mut parent);
+        assert_eq!(Inactive::allocated_frames(), frames + 4);
+        write(&mut child, 0x1000, 2);
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+        assert_eq!(swap.borrow().swapped_count(), 1);
+        assert_eq!(read(&mut parent, 0x1000), 1);
+        assert_eq!(read(&mut child, 0x1000), 2);
+        assert_eq!(swap.borrow().swapped_count(), 0);
+
+        drop(child);
+        drop(parent);
+        assert_eq!(Inactive::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn user_fault_in_kernel_area() {
+        let (cow, swap) = new_exts();
+        let mut ms = MemorySet::<Inactive>::new();
+        ms.push(MemoryArea::new(0x1000, 0x2000, Box::new(Swap { swap: swap.clone(), flags: MemoryAttr::default() }), "kernel"));
+        unsafe { ms.activate(); }
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+
+        let inpt = ms.get_page_table_mut() as *mut Inactive as usize;
+        let fault = |user| PageFault { addr: 0x1000, access: Access::Read, user, present: false };
+        let area = ms.find_area(0x1000).unwrap();
+        assert!(!area.page_fault_handler(&mut MockActivePageTable, inpt, fault(true)));
+        assert!(MockActivePageTable.get_entry(0x1000).unwrap().swapped());
+        assert!(area.page_fault_handler(&mut MockActivePageTable, inpt, fault(false)));
+        assert!(MockActivePageTable.get_entry(0x1000).unwrap().present());
+    }
+
+    #[test]
+    fn swap_shared_after_fork() {
+        let frames = Inactive::allocated_frames();
+        let (cow, swap) = new_exts();
+        let mut parent = MemorySet::<Inactive>::new();
+        let handler = Cow { cow: cow.clone(), swap: swap.clone(), flags: MemoryAttr::default().user() };
+        parent.push(MemoryArea::new(0x1000, 0x2000, Box::new(handler), "cow"));
+        write(&mut parent, 0x1000, 42);
+        let mut child = fork(&mut parent);
+
+        // the shared frame is swapped out once, for both pages
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+        assert_eq!(swap.borrow().swapped_count(), 1);
+        assert_eq!(Inactive::allocated_frames(), frames);
+        for ms in [&mut parent, &mut child].iter_mut() {
+            unsafe { ms.activate(); }
+            assert!(MockActivePageTable.get_entry(0x1000).unwrap().swapped());
+        }
+
+        // swapped in by the child, for both pages
+        assert_eq!(read(&mut child, 0x1000), 42);
+        assert_eq!(swap.borrow().swapped_count(), 0);
+        unsafe { parent.activate(); }
+        assert!(MockActivePageTable.get_entry(0x1000).unwrap().present());
+
+        // swapped out again, and written by the parent
+        let target = swap.borrow_mut().swap_out_any(&mut MockActivePageTable, &mut cow.borrow_mut()).ok().unwrap();
+        Inactive::dealloc_frame(target);
+        write(&mut parent, 0x1000, 43);
+        assert_eq!(read(&mut child, 0x1000), 42);
+        assert_eq!(Inactive::allocated_frames(), frames + 2);
+
+        drop(child);
+        drop(parent);
+        assert_eq!(Inactive::allocated_frames(), frames);
+        assert_eq!(swap.borrow().swapped_count(), 0);
+        assert_eq!(cow.borrow().frame_count(), 0);
+    }
+}
@@ -0,0 +1,222 @@
+//! Mock Inactive Page Table
+//!
+//! An mock implementation for the InactivePageTable, with mock physical memory and frame allocation.
+//! Used to test MemorySet with its handlers, CowExt and SwapExt together without the kernel.
+//!
+//! Each inactive page table has its own entries, and all of them share the physical memory.
+//! MockActivePageTable works on the entries of the page table activated or edited at the moment,
+//! like the recursive mapping of a real page table.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cell::{RefCell, UnsafeCell};
+use core::{ptr, slice};
+use super::*;
+use super::mock_page_table::MockEntry;
+
+const FRAME_COUNT: usize = 64;
+
+type MockEntries = BTreeMap<VirtAddr, MockEntry>;
+
+// the physical memory and the active page table
+struct MockMemory {
+    data: Vec<u8>,
+    allocated: Vec<bool>,
+    active: *mut MockEntries,
+}
+
+thread_local! {
+    // each test runs in its own thread, so the tests do not share the memory
+    static MEMORY: RefCell<MockMemory> = RefCell::new(MockMemory {
+        data: vec![0; FRAME_COUNT * PAGE_SIZE],
+        allocated: vec![false; FRAME_COUNT],
+        active: ptr::null_mut(),
+    });
+}
+
+fn with_memory<T>(f: impl FnOnce(&mut MockMemory) -> T) -> T {
+    MEMORY.with(|memory| f(&mut memory.borrow_mut()))
+}
+
+/*
+**  @brief  set the active page table
+**  @param  entries: *mut MockEntries
+**                               the entries of the page table to activate
+**  @retval *mut MockEntries     the entries of the page table active before
+*/
+fn set_active(entries: *mut MockEntries) -> *mut MockEntries {
+    with_memory(|memory| ::core::mem::replace(&mut memory.active, entries))
+}
+
+fn active_entries<'a>() -> &'a mut MockEntries {
+    let entries = with_memory(|memory| memory.active);
+    assert!(!entries.is_null(), "no page table is active");
+    unsafe { &mut *entries }
+}
+
+/*
+**  @brief  get the content of a frame in the mock physical memory
+**  @param  target: PhysAddr     the physics address in the frame
+**  @retval &'a mut [u8]         the content of the frame
+*/
+fn frame_data<'a>(target: PhysAddr) -> &'a mut [u8] {
+    let base = target & !(PAGE_SIZE - 1);
+    assert!(base < FRAME_COUNT * PAGE_SIZE, "physical memory access out of range");
+    let data = with_memory(|memory| memory.data.as_mut_ptr());
+    unsafe { slice::from_raw_parts_mut(data.add(base), PAGE_SIZE) }
+}
+
+// a mock inactive page table for test purpose
+pub struct MockInactivePageTable {
+    // boxed, so its address is kept as the token and the active page table
+    entries: Box<UnsafeCell<MockEntries>>,
+}
+
+impl InactivePageTable for MockInactivePageTable {
+    type Active = MockActivePageTable;
+
+    fn new() -> Self {
+        // there is no kernel memory in the mock
+        Self::new_bare()
+    }
+    fn new_bare() -> Self {
+        MockInactivePageTable { entries: Box::new(UnsafeCell::new(BTreeMap::new())) }
+    }
+    fn edit(&mut self, f: impl FnOnce(&mut Self::Active)) {
+        unsafe { self.with(|| f(&mut MockActivePageTable)); }
+    }
+    unsafe fn activate(&self) {
+        set_active(self.entries.get());
+    }
+    unsafe fn with<T>(&self, f: impl FnOnce() -> T) -> T {
+        let old = set_active(self.entries.get());
+        let ret = f();
+        set_active(old);
+        ret
+    }
+    fn token(&self) -> usize {
+        self.entries.get() as usize
+    }
+    fn alloc_frame() -> Option<PhysAddr> {
+        with_memory(|memory| {
+            let i = memory.allocated.iter().position(|&allocated| !allocated)?;
+            memory.allocated[i] = true;
+            Some(i * PAGE_SIZE)
+        })
+    }
+    fn dealloc_frame(target: PhysAddr) {
+        with_memory(|memory| {
+            let i = target / PAGE_SIZE;
+            assert!(memory.allocated[i], "frame {:#x} is not allocated", target);
+            memory.allocated[i] = false;
+        })
+    }
+}
+
+impl MockInactivePageTable {
+    /*
+    **  @brief  get the number of frames allocated in the current thread
+    **  @retval usize                the number of frames allocated
+    */
+    pub fn allocated_frames() -> usize {
+        with_memory(|memory| memory.allocated.iter().filter(|&&allocated| allocated).count())
+    }
+}
+
+impl Drop for MockInactivePageTable {
+    fn drop(&mut self) {
+        let entries = self.entries.get();
+        with_memory(|memory| if memory.active == entries {
+            memory.active = ptr::null_mut();
+        });
+    }
+}
+
+// the page table active at the moment, see MockInactivePageTable
+pub struct MockActivePageTable;
+
+impl PageTable for MockActivePageTable {
+    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> &mut Entry {
+        let page = addr & !(PAGE_SIZE - 1);
+        let entries = active_entries();
+        assert!(!entries.contains_key(&page), "page {:#x} is already mapped", addr);
+        let entry = entries.entry(page).or_insert_with(MockEntry::default);
+        entry.set_target(target & !(PAGE_SIZE - 1));
+        entry.set_present(true);
+        entry.set_writable(true);
+        entry
+    }
+    fn unmap(&mut self, addr: VirtAddr) {
+        let page = addr & !(PAGE_SIZE - 1);
+        assert!(active_entries().remove(&page).is_some(), "page {:#x} is not mapped", addr);
+    }
+    fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry> {
+        let page = addr & !(PAGE_SIZE - 1);
+        active_entries().get_mut(&page).map(|entry| entry as &mut Entry)
+    }
+    fn next_mapped(&mut self, addr: VirtAddr) -> Option<VirtAddr> {
+        let page = addr & !(PAGE_SIZE - 1);
+        active_entries().range(page..).next().map(|(&page, _)| page)
+    }
+    fn get_page_slice_mut<'a, 'b>(&'a mut self, addr: VirtAddr) -> &'b mut [u8] {
+        frame_data(self.translate(addr, false))
+    }
+    fn read(&mut self, addr: VirtAddr) -> u8 {
+        frame_data(self.translate(addr, false))[addr & (PAGE_SIZE - 1)]
+    }
+    fn write(&mut self, addr: VirtAddr, data: u8) {
+        frame_data(self.translate(addr, true))[addr & (PAGE_SIZE - 1)] = data;
+    }
+}
+
+impl MockActivePageTable {
+    /*
+    **  @brief  translate virtual address to physics address like the CPU
+    **          the page faults should be handled before, by the caller
+    **  @param  addr: VirtAddr       the virtual address to translation
+    **  @param  write: bool          whether the access is a write
+    **  @retval PhysAddr             the translation result
+    */
+    fn translate(&mut self, addr: VirtAddr, write: bool) -> PhysAddr {
+        let entry = active_entries().get_mut(&(addr & !(PAGE_SIZE - 1)))
+            .unwrap_or_else(|| panic!("page fault: {:#x} is not mapped", addr));
+        assert!(entry.present(), "page fault: {:#x} is not present", addr);
+        assert!(!write || entry.writable(), "page fault: {:#x} is not writable", addr);
+        entry.access(write);
+        entry.target() | (addr & (PAGE_SIZE - 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_and_with() {
+        let frames = MockInactivePageTable::allocated_frames();
+        let mut pt1 = MockInactivePageTable::new();
+        let mut pt2 = MockInactivePageTable::new();
+        let target = MockInactivePageTable::alloc_frame().unwrap();
+        pt1.edit(|pt| { pt.map(0x1000, target); });
+        pt2.edit(|pt| { pt.map(0x2000, target); });
+        assert_ne!(pt1.token(), pt2.token());
+        assert_eq!(MockInactivePageTable::allocated_frames(), frames + 1);
+
+        unsafe {
+            pt1.activate();
+            MockActivePageTable.write(0x1004, 42);
+            assert!(MockActivePageTable.get_entry(0x2000).is_none());
+            // the frame is shared by both page tables
+            assert_eq!(pt2.with(|| MockActivePageTable.read(0x2004)), 42);
+            // switched back to pt1 after with
+            let mut active = MockActivePageTable;
+            let entry = active.get_entry(0x1000).unwrap();
+            assert!(entry.accessed() && entry.dirty());
+        }
+        pt1.edit(|pt| pt.unmap(0x1000));
+        pt2.edit(|pt| pt.unmap(0x2000));
+        MockInactivePageTable::dealloc_frame(target);
+        assert_eq!(MockInactivePageTable::allocated_frames(), frames);
+    }
+}
@@ -27,6 +27,10 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    user: bool,
+    execute: bool,
+    // in use between map and unmap, even if not present
+    mapped: bool,
 }
 
 impl Entry for MockEntry {
@@ -53,10 +57,22 @@ impl Entry for MockEntry {
     }
     fn swapped(&self) -> bool { self.swapped }
     fn set_swapped(&mut self, value: bool) { self.swapped = value; }
-    fn user(&self) -> bool { unimplemented!() }
-    fn set_user(&mut self, value: bool) { unimplemented!() }
-    fn execute(&self) -> bool { unimplemented!() }
-    fn set_execute(&mut self, value: bool) { unimplemented!() }
+    fn user(&self) -> bool { self.user }
+    fn set_user(&mut self, value: bool) { self.user = value; }
+    fn execute(&self) -> bool { self.execute }
+    fn set_execute(&mut self, value: bool) { self.execute = value; }
+}
+
+impl MockEntry {
+    /*
+    **  @brief  mark the entry as accessed, and dirty if it is written
+    **  @param  write: bool          whether the access is a write
+    **  @retval none
+    */
+    pub(super) fn access(&mut self, write: bool) {
+        self.accessed = true;
+        self.dirty |= write;
+    }
 }
 
 type PageFaultHandler = Box<FnMut(&mut MockPageTable, VirtAddr)>;
@@ -67,6 +83,7 @@ impl PageTable for MockPageTable {
         let entry = &mut self.entries[addr / PAGE_SIZE];
         assert!(!entry.present);
         entry.present = true;
+        entry.mapped = true;
         entry.writable = true;
         entry.target = target & !(PAGE_SIZE - 1);
         entry
@@ -75,10 +92,14 @@ impl PageTable for MockPageTable {
         let entry = &mut self.entries[addr / PAGE_SIZE];
         assert!(entry.present);
         entry.present = false;
+        entry.mapped = false;
     }
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry> {
         Some(&mut self.entries[addr / PAGE_SIZE])
     }
+    fn next_mapped(&mut self, addr: VirtAddr) -> Option<VirtAddr> {
+        (addr / PAGE_SIZE..PAGE_COUNT).find(|&i| self.entries[i].mapped).map(|i| i * PAGE_SIZE)
+    }
     fn get_page_slice_mut<'a,'b>(&'a mut self, addr: VirtAddr) -> &'b mut [u8] {
         self._read(addr);
         let pa = self.translate(addr) & !(PAGE_SIZE - 1);
@@ -175,6 +196,7 @@ impl MockPageTable {
 mod test {
     use super::*;
     use alloc::sync::Arc;
+    use alloc::vec::Vec;
     use core::cell::RefCell;
 
     #[test]
@@ -252,4 +274,54 @@ mod test {
         pt.read(0);
         assert_eq!(*page_fault_count.borrow(), 2);
     }
+
+    #[test]
+    fn mapped_pages() {
+        let mut pt = MockPageTable::new();
+        pt.map(0x1000, 0x1000);
+        pt.map(0x2000, 0x2000);
+        pt.map(0x3000, 0x5000);
+        pt.map(0x5000, 0x6000);
+        pt.map(0x6000, 0x7000);
+        pt.get_entry(0x2000).unwrap().set_shared(false);
+        pt.get_entry(0x2000).unwrap().set_writable(false);
+        pt.get_entry(0x5000).unwrap().set_present(false);
+        pt.get_entry(0x5000).unwrap().set_swapped(true);
+        pt.get_entry(0x6000).unwrap().set_present(false);
+        pt.get_entry(0x6000).unwrap().set_swapped(true);
+        pt.unmap(0x3000);
+
+        let pages: Vec<_> = MappedPages::new(&mut pt, 0, 0x10000).map(|page| page.addr).collect();
+        assert_eq!(pages, [0x1000, 0x2000, 0x5000, 0x6000]);
+        let pages: Vec<_> = MappedPages::new(&mut pt, 0x2000, 0x6000).map(|page| page.addr).collect();
+        assert_eq!(pages, [0x2000, 0x5000]);
+
+        let mut pages = MappedPages::new(&mut pt, 0, 0x10000);
+        let first = pages.next().unwrap();
+        assert!(first.present && first.writable && !first.shared);
+        let mut second = pages.next().unwrap();
+        assert!(second.present && !second.writable && second.shared);
+        // the swapped out pages are merged regardless of their targets
+        let mut swapped = pages.next().unwrap();
+        assert!(!swapped.present && swapped.swapped);
+        assert!(swapped.merge(&pages.next().unwrap()));
+        assert_eq!((swapped.addr, swapped.size), (0x5000, 0x2000));
+        assert!(pages.next().is_none());
+        assert!(!second.merge(&swapped));
+    }
+
+    #[test]
+    fn merge_mapped_pages() {
+        let mut pt = MockPageTable::new();
+        pt.map(0x0, 0x4000);
+        pt.map(0x1000, 0x5000);
+        pt.map(0x2000, 0x7000);
+        let pages: Vec<_> = MappedPages::new(&mut pt, 0, 0x10000).collect();
+        let mut range = pages[0];
+        assert!(range.merge(&pages[1]));
+        assert_eq!((range.addr, range.target, range.size), (0x0, 0x4000, 0x2000));
+        // the target does not follow
+        assert!(!range.merge(&pages[2]));
+        assert_eq!(format!("{}", range), "0x00000000-0x00002000 -> 0x00004000 pw---");
+    }
 }
\ No newline at end of file
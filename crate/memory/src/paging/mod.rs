@@ -4,11 +4,16 @@
 
 use super::*;
 use super::memory_set::InactivePageTable;
+use core::fmt;
 #[cfg(test)]
 pub use self::mock_page_table::MockPageTable;
+#[cfg(test)]
+pub use self::mock_inactive_page_table::{MockInactivePageTable, MockActivePageTable};
 
 #[cfg(test)]
 pub mod mock_page_table;
+#[cfg(test)]
+pub mod mock_inactive_page_table;
 
 // trait for PageTable
 pub trait PageTable {
@@ -28,10 +33,44 @@ pub trait PageTable {
     fn unmap(&mut self, addr: VirtAddr);
     /*
     **  @brief  get the page table entry of a virual address
+    **          if the address is in a huge page, it is the entry of the huge page
     **  @param  addr: VirtAddr       the virual address
     **  @retval Entry                the page table entry of the virual address
     */
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry>;
+    /*
+    **  @brief  get the size of the huge pages supported by the page table
+    **  @retval usize                the size of a huge page, 0 if huge pages are not supported
+    */
+    fn huge_page_size(&self) -> usize { 0 }
+    /*
+    **  @brief  map a huge page to the target physics address
+    **          the default is for page tables without huge pages, which never map one
+    **  @param  addr: VirtAddr       the virual address to map, aligned to the huge page size
+    **  @param  target: PhysAddr     the target physics address, aligned to the huge page size
+    **  @retval Option<&mut Entry>   the page table entry of the huge page,
+    **                               None if it can not be mapped, then map it with 4K pages instead
+    */
+    fn map_huge(&mut self, addr: VirtAddr, target: PhysAddr) -> Option<&mut Entry> {
+        None
+    }
+    /*
+    **  @brief  unmap a huge page from physics address
+    **          the default is for page tables without huge pages, where no huge page is mapped
+    **  @param  addr: VirtAddr       the virual address of the huge page
+    **  @retval none
+    */
+    fn unmap_huge(&mut self, addr: VirtAddr) {
+        self.unmap(addr);
+    }
+    /*
+    **  @brief  find the first mapped page containing or after a virtual address
+    **          a page is mapped if its entry is in use, even if it is not present, e.g. swapped out
+    **          the page tables themselves mapped by the recursive mapping are skipped
+    **  @param  addr: VirtAddr       the virual address to search from
+    **  @retval Option<VirtAddr>     the start address of the page or the huge page, None if not found
+    */
+    fn next_mapped(&mut self, addr: VirtAddr) -> Option<VirtAddr>;
     // For testing with mock
     /*
     **  @brief  used for testing with mock
@@ -195,4 +234,113 @@ pub trait Entry {
     **  @retval none
     */
     fn set_execute(&mut self, value: bool);
+    /*
+    **  @brief  get whether the entry maps a huge page
+    **  @retval bool                 whether it is the entry of a huge page
+    */
+    fn huge(&self) -> bool { false }
+}
+
+
+/// A copy of the entry of a mapped page, see `MappedPages`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MappedEntry {
+    pub addr: VirtAddr,
+    pub target: PhysAddr,
+    /// PAGE_SIZE, or the size of a huge page
+    pub size: usize,
+    pub present: bool,
+    pub writable: bool,
+    pub user: bool,
+    /// shared for copy-on-write, writable or readonly
+    pub shared: bool,
+    pub swapped: bool,
+}
+
+impl MappedEntry {
+    /*
+    **  @brief  get a copy of an entry
+    **  @param  addr: VirtAddr       the virtual address of the page
+    **  @param  size: usize          the size of the page
+    **  @param  entry: &Entry        the page table entry of the page
+    **  @retval MappedEntry          the copy of the entry
+    */
+    pub fn new(addr: VirtAddr, size: usize, entry: &Entry) -> Self {
+        MappedEntry {
+            addr,
+            target: entry.target(),
+            size,
+            present: entry.present(),
+            writable: entry.writable(),
+            user: entry.user(),
+            shared: entry.writable_shared() || entry.readonly_shared(),
+            swapped: entry.swapped(),
+        }
+    }
+    /*
+    **  @brief  extend the pages with the next pages,
+    **          if they have the same bits, and their targets follow if present
+    **  @param  next: &MappedEntry   the pages right after self
+    **  @retval bool                 whether they are merged into self
+    */
+    pub fn merge(&mut self, next: &MappedEntry) -> bool {
+        let follows = next.addr == self.addr + self.size
+            && (!self.present || next.target == self.target + self.size);
+        let same = (self.present, self.writable, self.user, self.shared, self.swapped)
+            == (next.present, next.writable, next.user, next.shared, next.swapped);
+        if follows && same {
+            self.size += next.size;
+        }
+        follows && same
+    }
+}
+
+impl fmt::Display for MappedEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bit = |value: bool, c: char| if value { c } else { '-' };
+        write!(f, "{:#010x}-{:#010x} -> {:#010x} {}{}{}{}{}", self.addr, self.addr + self.size, self.target,
+               bit(self.present, 'p'), bit(self.writable, 'w'), bit(self.user, 'u'),
+               bit(self.shared, 's'), bit(self.swapped, 'S'))
+    }
+}
+
+/// An iterator over the mapped pages of a page table in a range, by `PageTable::next_mapped`
+pub struct MappedPages<'a> {
+    table: &'a mut PageTable,
+    next: Option<VirtAddr>,
+    end: VirtAddr,
+}
+
+impl<'a> MappedPages<'a> {
+    /*
+    **  @brief  iterate over the mapped pages of a page table
+    **  @param  table: &mut PageTable  the page table
+    **  @param  start: VirtAddr      the start virtual address
+    **  @param  end: VirtAddr        the end virtual address, not included
+    **  @retval MappedPages          the iterator
+    */
+    pub fn new(table: &'a mut PageTable, start: VirtAddr, end: VirtAddr) -> Self {
+        MappedPages { table, next: Some(start), end }
+    }
+}
+
+impl<'a> Iterator for MappedPages<'a> {
+    type Item = MappedEntry;
+
+    fn next(&mut self) -> Option<MappedEntry> {
+        loop {
+            let addr = self.table.next_mapped(self.next?).filter(|&addr| addr < self.end)?;
+            let huge_page_size = self.table.huge_page_size();
+            let mapped = self.table.get_entry(addr).map(|entry| {
+                let size = if entry.huge() { huge_page_size } else { PAGE_SIZE };
+                MappedEntry::new(addr, size, entry)
+            });
+            let size = mapped.map_or(PAGE_SIZE, |mapped| mapped.size);
+            // go on after the whole huge page
+            self.next = addr.checked_add(size);
+            if mapped.is_some() {
+                return mapped;
+            }
+        }
+    }
 }
@@ -0,0 +1,142 @@
+//! Implememnt the swap manager with the aging page replacement algorithm
+//!
+//! Each page has an 8-bit age counter as a shift register of its reference history.
+//! On every tick, the counter is shifted right, with the accessed bit shifted into
+//! the highest bit, then the accessed bit is cleared.
+//! At most `TICK_SAMPLES` pages are sampled on a tick in turn, so with more pages,
+//! each counter is shifted every few ticks.
+//! The page with the smallest counter is the least recently used one approximately,
+//! and it's chosen as the victim.
+
+use alloc::vec::Vec;
+use super::*;
+
+pub struct AgingSwapManager<T: InactivePageTable> {
+    /// swappable frames and their age counters, in the order of push
+    frames: Vec<(Frame, u8)>,
+    /// the index of the next frame to sample
+    hand: usize,
+    mark: PhantomData<T>,
+}
+
+impl<T: InactivePageTable> Default for AgingSwapManager<T> {
+    fn default() -> Self {
+        AgingSwapManager {
+            frames: Vec::new(),
+            hand: 0,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T: InactivePageTable> SwapManager for AgingSwapManager<T> {
+    fn tick(&mut self, page_table: &mut PageTable) {
+        for id in tick_samples(self.frames.len(), &mut self.hand) {
+            let &mut (ref frame, ref mut age) = &mut self.frames[id];
+            let accessed = unsafe {
+                with_entry::<T, _>(page_table, frame, |entry| {
+                    let accessed = entry.accessed();
+                    if accessed {
+                        entry.clear_accessed();
+                        entry.update();
+                    }
+                    accessed
+                })
+            };
+            *age = (*age >> 1) | ((accessed as u8) << 7);
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
+        // it's just accessed, to be swapped in or mapped
+        self.frames.push((frame, 1 << 7));
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        info!("SwapManager remove token: {:x?} vaddr: {:x?}", token, addr);
+        let id = self.frames.iter()
+            .position(|&(ref x, _)| x.get_virtaddr() == addr && x.get_token() == token)
+            .expect("address not found");
+        self.frames.remove(id);
+    }
+
+    fn pop<S>(&mut self, _: &mut PageTable, _: &mut S) -> Option<Frame>
+        where S: Swapper
+    {
+        // the first one is chosen if there are several with the smallest age
+        let id = self.frames.iter()
+            .enumerate()
+            .min_by_key(|&(_, &(_, age))| age)
+            .map(|(id, _)| id)?;
+        Some(self.frames.remove(id).0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::mock_swapper::MockSwapper;
+    use paging::{MockInactivePageTable, MockActivePageTable};
+
+    #[test]
+    fn test() {
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = AgingSwapManager::<MockInactivePageTable>::default();
+        let mut swapper = MockSwapper::default();
+        let mut pt = MockActivePageTable;
+        for i in 0..4 {
+            pt.map(i * 0x1000, i * 0x1000);
+            manager.push(frame(i * 0x1000));
+        }
+
+        // age: 0x0: 1111_0000, 0x1000: 0101_0000, 0x2000: 0001_0000, 0x3000: 1011_0000
+        pt.read(0x0);
+        pt.read(0x3000);
+        manager.tick(&mut pt);
+        pt.read(0x0);
+        pt.read(0x1000);
+        manager.tick(&mut pt);
+        pt.read(0x0);
+        pt.read(0x3000);
+        manager.tick(&mut pt);
+        assert!(!pt.get_entry(0x0).unwrap().accessed());
+
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x2000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x1000)));
+        // age: 0x0: 0011_1100, 0x3000: 0010_1100
+        manager.tick(&mut pt);
+        manager.tick(&mut pt);
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x3000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x0)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), None);
+    }
+
+    #[test]
+    fn bounded_tick() {
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = AgingSwapManager::<MockInactivePageTable>::default();
+        let mut pt = MockActivePageTable;
+        for i in 0..TICK_SAMPLES + 1 {
+            pt.map(i * 0x1000, i * 0x1000);
+            manager.push(frame(i * 0x1000));
+            pt.read(i * 0x1000);
+        }
+
+        // the last page is sampled on the next tick
+        let last = TICK_SAMPLES * 0x1000;
+        manager.tick(&mut pt);
+        assert!(!pt.get_entry(0x0).unwrap().accessed());
+        assert!(pt.get_entry(last).unwrap().accessed());
+        manager.tick(&mut pt);
+        assert!(!pt.get_entry(last).unwrap().accessed());
+        // then the first ones in turn
+        pt.read(0x0);
+        manager.tick(&mut pt);
+        assert!(!pt.get_entry(0x0).unwrap().accessed());
+    }
+}
@@ -1,77 +1,102 @@
 //! Implememnt the swap manager with the enhanced clock page replacement algorithm
-//! TO BE FIXED
+//!
+//! Frames are kept in a circular list with a clock hand. To select a victim,
+//! the hand sweeps the list, and checks the (accessed, dirty) bits of each page:
+//!
+//! * (1, _): it's accessed recently, clear the accessed bit and give it a second chance.
+//! * (0, 1): it's dirty, clear the dirty bit and give it another chance,
+//!           since a clean page is cheaper to evict. `SwapExt` always writes the whole page
+//!           to the swapper when swapping out, so no data is lost.
+//! * (0, 0): the victim.
+//!
+//! The entries are accessed through the page table of each frame, which is temporarily activated.
 
 use alloc::collections::VecDeque;
 use super::*;
-use paging::Entry;
 
-#[derive(Default)]
-pub struct EnhancedClockSwapManager {
+pub struct EnhancedClockSwapManager<T: InactivePageTable> {
     clock_ptr: usize,
-    deque: VecDeque<VirtAddr>,
+    deque: VecDeque<Frame>,
+    mark: PhantomData<T>,
 }
 
-// FIXME: It's unusable. But can pass a simple test.
-impl SwapManager for EnhancedClockSwapManager {
-    fn tick(&mut self) {
+impl<T: InactivePageTable> Default for EnhancedClockSwapManager<T> {
+    fn default() -> Self {
+        EnhancedClockSwapManager {
+            clock_ptr: 0,
+            deque: VecDeque::new(),
+            mark: PhantomData,
+        }
     }
+}
 
-    fn push(&mut self, addr: usize) {
-        let pos = if self.clock_ptr == 0 {self.deque.len()} else {self.clock_ptr};
-        self.deque.insert(pos, addr);
+impl<T: InactivePageTable> SwapManager for EnhancedClockSwapManager<T> {
+    fn tick(&mut self, _: &mut PageTable) {}
+
+    fn push(&mut self, frame: Frame) {
+        info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
+        // insert it just behind the clock hand, so it is the last one to be checked
+        self.deque.insert(self.clock_ptr, frame);
+        self.move_next();
     }
 
-    fn remove(&mut self, addr: usize) {
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        info!("SwapManager remove token: {:x?} vaddr: {:x?}", token, addr);
         let id = self.deque.iter()
-            .position(|&x| x == addr)
+            .position(|ref x| x.get_virtaddr() == addr && x.get_token() == token)
             .expect("address not found");
+        self.deque.remove(id);
         if id < self.clock_ptr {
             self.clock_ptr -= 1;
         }
-        self.deque.remove(id);
+        if self.clock_ptr == self.deque.len() {
+            self.clock_ptr = 0;
+        }
     }
 
-    fn pop<T, S>(&mut self, page_table: &mut T, _swapper: &mut S) -> Option<VirtAddr>
-        where T: PageTable, S: Swapper
+    fn pop<S>(&mut self, page_table: &mut PageTable, _: &mut S) -> Option<Frame>
+        where S: Swapper
     {
+        if self.deque.is_empty() {
+            return None;
+        }
         loop {
-            let addr = self.deque[self.clock_ptr];
-            // FIXME: Once define `slice`, all modifies of `entry` below will fail.
-            //        Then the loop will never stop.
-            //        The reason may be `get_page_slice_mut()` contains unsafe operation,
-            //        which lead the compiler to do a wrong optimization.
-//            let slice = page_table.get_page_slice_mut(addr);
-            let entry = page_table.get_entry(addr).unwrap();
-//            println!("{:#x} , {}, {}", addr, entry.accessed(), entry.dirty());
-
-            match (entry.accessed(), entry.dirty()) {
-                (true, _) => {
-                    entry.clear_accessed();
-                },
-                (false, true) => {
-                    // FIXME:
-//                    if let Ok(token) = swapper.swap_out(slice) {
-//                        entry.clear_dirty();
-//                    }
-                },
-                _ => {
-                    return self.remove_current();
-                }
+            let frame = self.deque[self.clock_ptr];
+            let victim = unsafe {
+                with_entry::<T, _>(page_table, &frame, |entry| {
+                    match (entry.accessed(), entry.dirty()) {
+                        (true, _) => entry.clear_accessed(),
+                        (false, true) => entry.clear_dirty(),
+                        (false, false) => return true,
+                    }
+                    entry.update();
+                    false
+                })
+            };
+            if victim {
+                return Some(self.remove_current());
             }
             self.move_next();
         }
     }
 }
 
-
-impl EnhancedClockSwapManager {
-    fn remove_current(&mut self) -> Option<VirtAddr> {
-        let addr = self.deque.remove(self.clock_ptr);
+impl<T: InactivePageTable> EnhancedClockSwapManager<T> {
+    /*
+    **  @brief  remove the frame pointed by the clock hand
+    **  @retval Frame                the removed frame
+    */
+    fn remove_current(&mut self) -> Frame {
+        let frame = self.deque.remove(self.clock_ptr).unwrap();
         if self.clock_ptr == self.deque.len() {
             self.clock_ptr = 0;
         }
-        return addr;
+        frame
     }
+    /*
+    **  @brief  move the clock hand to the next frame
+    **  @retval none
+    */
     fn move_next(&mut self) {
         self.clock_ptr += 1;
         if self.clock_ptr == self.deque.len() {
@@ -83,19 +108,43 @@ impl EnhancedClockSwapManager {
 #[cfg(test)]
 mod test {
     use super::*;
-    use swap::test::*;
+    use super::mock_swapper::MockSwapper;
+    use paging::{MockInactivePageTable, MockActivePageTable};
 
     #[test]
     fn test() {
-        use self::MemOp::{R, W};
-        let ops = [
-            R(0x1000), R(0x2000), R(0x3000), R(0x4000),
-            R(0x3000), W(0x1000), R(0x4000), W(0x2000), R(0x5000),
-            R(0x2000), W(0x1000), R(0x2000), R(0x3000), R(0x4000)];
-        let pgfault_count = [
-            1, 2, 3, 4,
-            4, 4, 4, 4, 5,
-            5, 5, 5, 6, 7];
-        test_manager(EnhancedClockSwapManager::default(), &ops, &pgfault_count);
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = EnhancedClockSwapManager::<MockInactivePageTable>::default();
+        let mut swapper = MockSwapper::default();
+        let mut pt = MockActivePageTable;
+        for i in 0..5 {
+            pt.map(i * 0x1000, i * 0x1000);
+            manager.push(frame(i * 0x1000));
+        }
+        manager.remove(inactive.token(), 0x4000);
+
+        // (A, D): 0x0: (1, 0), 0x1000: (1, 1), 0x2000: (0, 0), 0x3000: (0, 1)
+        pt.read(0x0);
+        pt.write(0x1000, 1);
+        pt.write(0x3000, 1);
+        pt.get_entry(0x3000).unwrap().clear_accessed();
+
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x2000)));
+        assert!(!pt.get_entry(0x0).unwrap().accessed());
+        assert!(pt.get_entry(0x1000).unwrap().dirty());
+
+        // 0x3000 is checked at first, clean it. 0x0 is accessed again. 0x1000 is cleaned.
+        pt.read(0x0);
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x3000)));
+        assert!(!pt.get_entry(0x1000).unwrap().dirty());
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x0)));
+
+        // new frame is the last one to be checked
+        manager.push(frame(0x4000));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x1000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x4000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), None);
     }
 }
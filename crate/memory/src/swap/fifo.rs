@@ -10,7 +10,7 @@ pub struct FifoSwapManager  {
 }
 
 impl SwapManager for FifoSwapManager {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _: &mut PageTable) {}
 
     fn push(&mut self, frame: Frame) {
         info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
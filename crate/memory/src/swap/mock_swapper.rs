@@ -39,6 +39,9 @@ impl Swapper for MockSwapper {
         }
         Ok(())
     }
+    fn swap_discard(&mut self, token: usize) -> Result<(), ()> {
+        self.map.remove(&token).map(|_| ()).ok_or(())
+    }
 }
 
 impl MockSwapper {
@@ -88,5 +91,15 @@ mod test {
         let mut swapper = MockSwapper::default();
         let mut data: [u8; 4096] = unsafe{ uninitialized() };
         assert_eq!(swapper.swap_in(0, &mut data), Err(()));
+        assert_eq!(swapper.swap_discard(0), Err(()));
+    }
+
+    #[test]
+    fn swap_discard() {
+        let mut swapper = MockSwapper::default();
+        let mut data: [u8; 4096] = unsafe{ uninitialized() };
+        let token = swapper.swap_out(&data).unwrap();
+        swapper.swap_discard(token).unwrap();
+        assert_eq!(swapper.swap_in(token, &mut data), Err(()));
     }
 }
\ No newline at end of file
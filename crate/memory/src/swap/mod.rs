@@ -10,8 +10,13 @@ use super::*;
 use super::paging::*;
 use super::memory_set::InactivePageTable;
 use super::addr::Frame;
+use super::cow::CowExt;
 use alloc::rc::Rc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
 use core::ops::{Deref, DerefMut};
+use core::slice;
 use spin;
 use core::marker::PhantomData;
 
@@ -19,20 +24,42 @@ use core::marker::PhantomData;
 //pub use self::enhanced_clock::EnhancedClockSwapManager;
 
 pub mod fifo;
-//mod enhanced_clock;
+pub mod enhanced_clock;
+pub mod second_chance;
+pub mod aging;
+pub mod working_set;
 pub mod mock_swapper;
 //#[cfg(test)]
 //mod mock_swapper;
 
+/// The most frames sampled by a swap manager on a tick, to bound the time spent in the timer interrupt
+pub const TICK_SAMPLES: usize = 32;
+
+/*
+**  @brief  choose the frames to sample on a tick, at most TICK_SAMPLES of them in turn
+**  @param  len: usize           the number of swappable frames
+**  @param  hand: &mut usize     the index of the next frame to sample, moved past the chosen ones
+**  @retval impl Iterator<Item = usize>
+**                               the indexes of the frames to sample
+*/
+fn tick_samples(len: usize, hand: &mut usize) -> impl Iterator<Item = usize> {
+    let start = if len == 0 { 0 } else { *hand % len };
+    let count = len.min(TICK_SAMPLES);
+    *hand = if len == 0 { 0 } else { (start + count) % len };
+    (0..count).map(move |i| (start + i) % len)
+}
+
 /// Manage all swappable pages, decide which to swap out
 pub trait SwapManager {
     //type Inactive: InactivePageTable;
     /*
     **  @brief  update intarnal state pre tick
     **          Called when tick interrupt occured
+    **  @param  page_table: &mut PageTable
+    **                               the current page table, to sample the entries of frames
     **  @retval none
     */
-    fn tick(&mut self);
+    fn tick(&mut self, page_table: &mut PageTable);
     /*
     **  @brief  update intarnal state when page is pushed into memory
     **          Called when map a swappable page into the memory
@@ -81,12 +108,41 @@ pub trait Swapper {
     **  @retval Result<(), ()>       the execute result
     */
     fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()>;
+    /*
+    **  @brief  Deallocate the space on device without reading it, e.g. when the page is unmapped
+    **  @param  token: usize         the token indicating the location on the device
+    **  @retval Result<(), ()>       the execute result
+    */
+    fn swap_discard(&mut self, token: usize) -> Result<(), ()>;
+}
+
+/// Allow choosing the swapper at runtime, e.g. `Box<Swapper + Send>`
+impl<S: Swapper + ?Sized> Swapper for Box<S> {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        (**self).swap_out(data)
+    }
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        (**self).swap_update(token, data)
+    }
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        (**self).swap_in(token, data)
+    }
+    fn swap_discard(&mut self, token: usize) -> Result<(), ()> {
+        (**self).swap_discard(token)
+    }
 }
 
 /// Wrapper for page table, supporting swap functions
 pub struct SwapExt<M: SwapManager, S: Swapper, T: InactivePageTable> {
     swap_manager: M,
     swapper: S,
+    /// the number of pages swapped out
+    swapped_count: usize,
+    /// pages sharing a frame with copy-on-write, keyed by the physics address of the frame.
+    /// Only the first page is pushed to the swap manager.
+    shared: BTreeMap<PhysAddr, Vec<Frame>>,
+    /// pages sharing a frame which is swapped out, keyed by the swap token
+    shared_swapped: BTreeMap<usize, Vec<Frame>>,
     mark: PhantomData<T>,
 }
 
@@ -102,17 +158,38 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
         SwapExt {
             swap_manager,
             swapper,
+            swapped_count: 0,
+            shared: BTreeMap::new(),
+            shared_swapped: BTreeMap::new(),
             mark: PhantomData,
         }
     }
 
+    /*
+    **  @brief  get the number of pages swapped out
+    **  @retval usize                the number of pages on the swap device
+    */
+    pub fn swapped_count(&self) -> usize {
+        self.swapped_count
+    }
+
+    /*
+    **  @brief  update the state of swap manager pre tick
+    **  @param  page_table: &mut PageTable
+    **                               the current page table
+    **  @retval none
+    */
+    pub fn tick(&mut self, page_table: &mut PageTable) {
+        self.swap_manager.tick(page_table);
+    }
+
     /*
     **  @brief set a page swappable
     **  @param pt: *mut T2           the raw pointer for the target page's inactive page table
     **  @param addr: VirtAddr        the target page's virtual address
     */
     pub unsafe fn set_swappable(&mut self, page_table: &mut PageTable, pt: *mut T, addr: VirtAddr){
-        let Self {ref mut swap_manager, ref mut swapper, ref mut mark} = self;
+        let Self {ref mut swap_manager, ref mut swapper, ref mut mark, ..} = self;
         let targetpt = &mut *(pt);
         let pttoken = {
             info!("SET_SWAPPABLE: the target page table token is {:x?}, addr is {:x?}", targetpt.token(), addr);
@@ -135,7 +212,7 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
     */
     pub unsafe fn remove_from_swappable(&mut self, page_table: &mut PageTable, pt: *mut T, addr: VirtAddr, alloc_frame: impl FnOnce() -> PhysAddr){
         //info!("come into remove_from swappable");
-        let Self {ref mut swap_manager, ref mut swapper, ref mut mark} = self;
+        let Self {ref mut swap_manager, ref mut swapper, ref mut swapped_count, ..} = self;
         let targetpt = &mut *(pt);
         let pttoken = {
             info!("SET_UNSWAPPABLE: the target page table token is {:x?}, addr is {:x?}", targetpt.token(), addr);
@@ -163,6 +240,7 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
             info!("swap in vaddr {:x?} at remove from swappable.", addr);
             let data = page_table.get_page_slice_mut(addr);
             swapper.swap_in(token, data).unwrap();
+            *swapped_count -= 1;
         });
         trace!("come out of femove_from swappable");
     }
@@ -181,55 +259,77 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
 
     /*
     **  @brief  Swap out any one of the swapped pages
+    **  @param  cow: &mut CowExt     the COW extension, to move the reference count of a shared frame
     **  @retval Result<PhysAddr, SwapError>
     **                               the physics address of released frame if success,
     **                               the error if failed
     */
-    pub fn swap_out_any(&mut self, page_table: &mut PageTable) -> Result<PhysAddr, SwapError> {
+    pub fn swap_out_any(&mut self, page_table: &mut PageTable, cow: &mut CowExt) -> Result<PhysAddr, SwapError> {
         info!("COME in to swap_out_any");
         let victim: Option<Frame> = {
-            let Self {ref mut swap_manager, ref mut swapper, ref mut mark} = self;
+            let Self {ref mut swap_manager, ref mut swapper, ..} = self;
             swap_manager.pop(page_table, swapper)
         };
-        info!("swap out page {}", victim.unwrap().get_virtaddr());
         match victim {
             None => Err(SwapError::NoSwapped),
-            Some(frame) => self.swap_out(page_table, &frame),
+            Some(frame) => {
+                info!("swap out page {:x?}", frame.get_virtaddr());
+                self.swap_out(page_table, &frame, cow)
+            }
         }
     }
 
     /*
-    **  @brief  Swap out page
+    **  @brief  Swap out page, if the frame is shared, all pages sharing it are swapped out together
     **  @param  frame: Frame       the Frame of page recording the page info
+    **  @param  cow: &mut CowExt     the COW extension, to move the reference count of a shared frame
     **  @retval Result<PhysAddr, SwapError>
     **                               the physics address of the original map target frame if success,
     **                               the error if failed
     */
-    fn swap_out(&mut self, page_table: &mut PageTable, frame: &Frame) -> Result<PhysAddr, SwapError> {
-        let Self {ref mut swap_manager, ref mut swapper, ref mut mark} = self;
-        let ret = unsafe{
-            let pt = &mut *(frame.get_page_table() as *mut T);
-            pt.with(|| {
-                //use core::slice;
-                //let data = unsafe { slice::from_raw_parts_mut((frame.virtaddr & !(PAGE_SIZE - 1)) as *mut u8, PAGE_SIZE) };
-                let data = page_table.get_page_slice_mut(frame.get_virtaddr());
-                let entry = page_table.get_entry(frame.get_virtaddr())
-                    .ok_or(SwapError::NotMapped)?;
-                if entry.swapped() {
-                    return Err(SwapError::AlreadySwapped);
-                }
-                //assert!(!entry.swapped(), "Page already swapped!");
-                let token = swapper.swap_out(data).map_err(|_| SwapError::IOError)?;
-                //let token = swapper.swap_out(data).unwrap();
-                let target = entry.target();
-                entry.set_target(token * PAGE_SIZE);
-                entry.set_swapped(true);
-                entry.set_present(false);
-                entry.update();
-                Ok(target)
-            })
+    fn swap_out(&mut self, page_table: &mut PageTable, frame: &Frame, cow: &mut CowExt) -> Result<PhysAddr, SwapError> {
+        let (target, token) = {
+            let swapper = &mut self.swapper;
+            unsafe {
+                let pt = &*(frame.get_page_table() as *const T);
+                pt.with(|| {
+                    let data = page_table.get_page_slice_mut(frame.get_virtaddr());
+                    let target = {
+                        let entry = page_table.get_entry(frame.get_virtaddr())
+                            .ok_or(SwapError::NotMapped)?;
+                        if entry.swapped() {
+                            return Err(SwapError::AlreadySwapped);
+                        }
+                        entry.target()
+                    };
+                    let token = swapper.swap_out(data).map_err(|_| SwapError::IOError)?;
+                    Ok((target, token))
+                })?
+            }
         };
-        ret
+        let shared = self.shared.remove(&target);
+        {
+            let frames = match shared {
+                Some(ref frames) => frames.as_slice(),
+                None => slice::from_ref(frame),
+            };
+            for frame in frames.iter() {
+                unsafe {
+                    with_entry::<T, _>(page_table, frame, |entry| {
+                        entry.set_target(token * PAGE_SIZE);
+                        entry.set_swapped(true);
+                        entry.set_present(false);
+                        entry.update();
+                    });
+                }
+            }
+        }
+        if let Some(frames) = shared {
+            cow.swap_out_shared(target, token);
+            self.shared_swapped.insert(token, frames);
+        }
+        self.swapped_count += 1;
+        Ok(target)
     }
     /*
     **  @brief  map the virtual address to a target physics address and then swap in page data, noted that the page should be in the current page table
@@ -253,6 +353,7 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
         entry.update();
         let data = page_table.get_page_slice_mut(addr);
         self.swapper.swap_in(token, data).map_err(|_| SwapError::IOError)?;
+        self.swapped_count -= 1;
         let pttoken = unsafe{
             (*pt).token()
         };
@@ -261,6 +362,283 @@ impl<M: SwapManager, S: Swapper, T:InactivePageTable> SwapExt<M, S, T> {
         self.swap_manager.push(frame);
         Ok(())
     }
+
+    /*
+    **  @brief  map a page to a frame shared with copy-on-write, and set it swappable
+    **          The frame is swapped out only once for all pages sharing it
+    **  @param  pt: *mut T           the raw pointer for the target page's inactive page table
+    **  @param  addr: VirtAddr       the target page's virtual address, which is mapped to the shared frame
+    **  @param  writable: bool       whether the page is writable and shared
+    **  @param  cow: &mut CowExt     the COW extension counting the references of the frame
+    **  @retval none
+    */
+    pub unsafe fn set_swappable_shared(&mut self, page_table: &mut PageTable, pt: *mut T, addr: VirtAddr, writable: bool, cow: &mut CowExt) {
+        let targetpt = &mut *(pt);
+        let frame = Frame::new(pt as usize, addr, targetpt.token());
+        let target = targetpt.with(|| {
+            let entry = page_table.get_entry(addr).expect("failed to get page entry when set swappable");
+            assert!(entry.present(), "shared page should be present when set swappable");
+            entry.target()
+        });
+        cow.map_to_shared(target, writable);
+        let frames = self.shared.entry(target).or_insert_with(Vec::new);
+        if frames.is_empty() {
+            self.swap_manager.push(frame);
+        }
+        frames.push(frame);
+    }
+
+    /*
+    **  @brief  remove a page mapped to a shared frame from swappable pages, before unmapping it
+    **          If the frame is swapped out, the swap slot is freed with the last page,
+    **          and the entry is left not present with its target cleared, as it maps no frame
+    **  @param  pt: *mut T           the raw pointer for the target page's inactive page table
+    **  @param  addr: VirtAddr       the target page's virtual address
+    **  @param  writable: bool       whether the page is writable and shared
+    **  @param  cow: &mut CowExt     the COW extension counting the references of the frame
+    **  @retval Option<PhysAddr>     the frame to deallocate if it is the last page sharing it
+    */
+    pub unsafe fn remove_from_swappable_shared(&mut self, page_table: &mut PageTable, pt: *mut T, addr: VirtAddr, writable: bool, cow: &mut CowExt) -> Option<PhysAddr> {
+        let targetpt = &mut *(pt);
+        let pttoken = targetpt.token();
+        let frame = Frame::new(pt as usize, addr, pttoken);
+        let (target, swapped) = targetpt.with(|| {
+            let entry = page_table.get_entry(addr).expect("failed to get page entry when remove from swappable");
+            let ret = (entry.target(), entry.swapped());
+            if entry.swapped() {
+                // the target is the swap slot, not a frame
+                entry.set_swapped(false);
+                entry.set_target(0);
+                entry.update();
+            }
+            ret
+        });
+        if swapped {
+            let token = target / PAGE_SIZE;
+            let last = cow.unmap_swapped(token, writable);
+            if let Some(frames) = self.shared_swapped.get_mut(&token) {
+                frames.retain(|f| *f != frame);
+            }
+            if last {
+                self.shared_swapped.remove(&token);
+                // nobody will fault it back, just free the slot
+                self.swapper.swap_discard(token).ok().expect("failed to free swap slot");
+                self.swapped_count -= 1;
+            }
+            return None;
+        }
+        let last = cow.unmap_shared(target, writable);
+        let mut frames = self.shared.remove(&target).unwrap_or_default();
+        match frames.iter().position(|f| *f == frame) {
+            Some(0) => {
+                frames.remove(0);
+                self.swap_manager.remove(pttoken, addr);
+                if let Some(&next) = frames.first() {
+                    self.swap_manager.push(next);
+                }
+            }
+            Some(i) => { frames.remove(i); }
+            None => {}
+        }
+        if !frames.is_empty() {
+            self.shared.insert(target, frames);
+        }
+        if last { Some(target) } else { None }
+    }
+
+    /*
+    **  @brief  swap in a shared frame, and map all pages sharing it to the target physics address,
+    **          noted that the page should be in the current page table
+    **          The frame is read through the given page only, the others are mapped after it is read,
+    **          and nothing is changed if it fails
+    **  @param  addr: VirtAddr       the virual address of one of the pages sharing the frame
+    **  @param  target: PhysAddr     the target physics address
+    **  @param  cow: &mut CowExt     the COW extension counting the references of the frame
+    **  @retval Result<(), SwapError>
+    **                               the execute result, and the error if failed
+    */
+    pub fn swap_in_shared(&mut self, page_table: &mut PageTable, addr: VirtAddr, target: PhysAddr, cow: &mut CowExt) -> Result<(), SwapError> {
+        let token = {
+            let entry = page_table.get_entry(addr)
+                .ok_or(SwapError::NotMapped)?;
+            if !entry.swapped() {
+                return Err(SwapError::NotSwapped);
+            }
+            entry.target() / PAGE_SIZE
+        };
+        if !self.shared_swapped.contains_key(&token) {
+            return Err(SwapError::NotSwapped);
+        }
+        set_entry_swapped(page_table, addr, target, false);
+        let result = {
+            let data = page_table.get_page_slice_mut(addr);
+            self.swapper.swap_in(token, data)
+        };
+        if result.is_err() {
+            set_entry_swapped(page_table, addr, token * PAGE_SIZE, true);
+            return Err(SwapError::IOError);
+        }
+        let frames = self.shared_swapped.remove(&token).unwrap();
+        for frame in frames.iter() {
+            unsafe {
+                with_entry::<T, _>(page_table, frame, |entry| {
+                    entry.set_target(target);
+                    entry.set_swapped(false);
+                    entry.set_present(true);
+                    entry.update();
+                });
+            }
+        }
+        self.swapped_count -= 1;
+        cow.swap_in_shared(token, target);
+        self.swap_manager.push(frames[0]);
+        self.shared.insert(target, frames);
+        Ok(())
+    }
+}
+
+/*
+**  @brief  map a page in the current page table to a frame, or mark it swapped to a swap slot
+**  @param  addr: VirtAddr       the virtual address of the page
+**  @param  target: PhysAddr     the physics address of the frame, or the swap slot if swapped
+**  @param  swapped: bool        whether the page is swapped out
+**  @retval none
+*/
+fn set_entry_swapped(page_table: &mut PageTable, addr: VirtAddr, target: PhysAddr, swapped: bool) {
+    let entry = page_table.get_entry(addr).expect("failed to get page entry when swap in");
+    entry.set_target(target);
+    entry.set_swapped(swapped);
+    entry.set_present(!swapped);
+    entry.update();
+}
+
+/*
+**  @brief  access the page table entry of a frame, with its page table temporarily activated
+**  @param  page_table: &mut PageTable
+**                               the current page table
+**  @param  frame: &Frame        the frame recording the page table and virtual address
+**  @param  f: impl FnOnce(&mut Entry) -> R
+**                               the function to run on the entry
+**  @retval R                    the result of f
+*/
+unsafe fn with_entry<T, R>(page_table: &mut PageTable, frame: &Frame, f: impl FnOnce(&mut Entry) -> R) -> R
+    where T: InactivePageTable
+{
+    let pt = &*(frame.get_page_table() as *const T);
+    pt.with(|| {
+        let entry = page_table.get_entry(frame.get_virtaddr())
+            .expect("failed to get page entry of swappable frame");
+        f(entry)
+    })
+}
+
+#[cfg(test)]
+mod test_shared {
+    use super::*;
+    use super::fifo::FifoSwapManager;
+    use super::mock_swapper::MockSwapper;
+    use paging::{MockInactivePageTable, MockActivePageTable};
+
+    #[test]
+    fn swap_shared() {
+        let frames = MockInactivePageTable::allocated_frames();
+        let mut pt = MockActivePageTable;
+        let mut cow = CowExt::new();
+        let mut inpt = MockInactivePageTable::new();
+        unsafe { inpt.activate(); }
+        let inpt = &mut inpt as *mut MockInactivePageTable;
+        let mut ext = SwapExt::<FifoSwapManager, MockSwapper, MockInactivePageTable>::new(FifoSwapManager::default(), MockSwapper::default());
+        let frame = MockInactivePageTable::alloc_frame().unwrap();
+
+        // 0x1000 and 0x2000 share the frame
+        pt.map(0x1000, frame);
+        pt.map(0x2000, frame);
+        pt.write(0x1000, 42);
+        unsafe {
+            ext.set_swappable_shared(&mut pt, inpt, 0x1000, true, &mut cow);
+            ext.set_swappable_shared(&mut pt, inpt, 0x2000, true, &mut cow);
+        }
+        assert_eq!(cow.frame_count(), 1);
+        assert_eq!(cow.shared_count(), 1);
+
+        // swapped out once, for both pages
+        assert_eq!(ext.swap_out_any(&mut pt, &mut cow).ok(), Some(frame));
+        MockInactivePageTable::dealloc_frame(frame);
+        assert_eq!(ext.swapped_count(), 1);
+        for &addr in [0x1000, 0x2000].iter() {
+            let entry = pt.get_entry(addr).unwrap();
+            assert!(entry.swapped() && !entry.present());
+        }
+        assert!(ext.swap_out_any(&mut pt, &mut cow).is_err());
+
+        // fault back through either page
+        let target = MockInactivePageTable::alloc_frame().unwrap();
+        assert!(ext.swap_in_shared(&mut pt, 0x2000, target, &mut cow).is_ok());
+        assert_eq!(ext.swapped_count(), 0);
+        for &addr in [0x1000, 0x2000].iter() {
+            let entry = pt.get_entry(addr).unwrap();
+            assert!(!entry.swapped() && entry.present());
+            assert_eq!(entry.target(), target);
+        }
+        assert_eq!(pt.read(0x1000), 42);
+        assert!(!cow.is_one_shared(target));
+
+        // unmapping the first page leaves the second swappable
+        assert_eq!(unsafe { ext.remove_from_swappable_shared(&mut pt, inpt, 0x1000, true, &mut cow) }, None);
+        pt.unmap(0x1000);
+        assert!(cow.is_one_shared(target));
+        assert_eq!(ext.swap_out_any(&mut pt, &mut cow).ok(), Some(target));
+        MockInactivePageTable::dealloc_frame(target);
+
+        // unmapping the last page frees the swap slot
+        assert_eq!(unsafe { ext.remove_from_swappable_shared(&mut pt, inpt, 0x2000, true, &mut cow) }, None);
+        {
+            let entry = pt.get_entry(0x2000).unwrap();
+            assert!(!entry.present() && !entry.swapped());
+            assert_eq!(entry.target(), 0);
+        }
+        pt.unmap(0x2000);
+        assert_eq!(ext.swapped_count(), 0);
+        assert_eq!(cow.frame_count(), 0);
+        assert!(ext.swapper.swap_discard(0).is_err());
+        assert_eq!(MockInactivePageTable::allocated_frames(), frames);
+    }
+
+    #[test]
+    fn swap_in_shared_failed() {
+        let frames = MockInactivePageTable::allocated_frames();
+        let mut pt = MockActivePageTable;
+        let mut cow = CowExt::new();
+        let mut inpt = MockInactivePageTable::new();
+        unsafe { inpt.activate(); }
+        let inpt = &mut inpt as *mut MockInactivePageTable;
+        let mut ext = SwapExt::<FifoSwapManager, MockSwapper, MockInactivePageTable>::new(FifoSwapManager::default(), MockSwapper::default());
+        let frame = MockInactivePageTable::alloc_frame().unwrap();
+        pt.map(0x1000, frame);
+        pt.map(0x2000, frame);
+        unsafe {
+            ext.set_swappable_shared(&mut pt, inpt, 0x1000, true, &mut cow);
+            ext.set_swappable_shared(&mut pt, inpt, 0x2000, true, &mut cow);
+        }
+        assert_eq!(ext.swap_out_any(&mut pt, &mut cow).ok(), Some(frame));
+
+        // the swap slot is lost, both pages are left swapped out
+        let token = pt.get_entry(0x1000).unwrap().target() / PAGE_SIZE;
+        assert!(ext.swapper.swap_discard(token).is_ok());
+        assert!(ext.swap_in_shared(&mut pt, 0x2000, frame, &mut cow).is_err());
+        assert_eq!(ext.swapped_count(), 1);
+        for &addr in [0x1000, 0x2000].iter() {
+            let entry = pt.get_entry(addr).unwrap();
+            assert!(entry.swapped() && !entry.present());
+            assert_eq!(entry.target(), token * PAGE_SIZE);
+        }
+        assert!(ext.shared_swapped.contains_key(&token));
+
+        pt.unmap(0x1000);
+        pt.unmap(0x2000);
+        MockInactivePageTable::dealloc_frame(frame);
+        assert_eq!(MockInactivePageTable::allocated_frames(), frames);
+    }
 }
 
 pub enum SwapError {
@@ -0,0 +1,96 @@
+//! Implememnt the swap manager with the second chance page replacement algorithm
+//!
+//! It's FIFO with a chance to stay: when the oldest page is accessed since
+//! it was checked last time, clear its accessed bit and move it to the back of the queue.
+
+use alloc::collections::VecDeque;
+use super::*;
+
+pub struct SecondChanceSwapManager<T: InactivePageTable> {
+    deque: VecDeque<Frame>,
+    mark: PhantomData<T>,
+}
+
+impl<T: InactivePageTable> Default for SecondChanceSwapManager<T> {
+    fn default() -> Self {
+        SecondChanceSwapManager {
+            deque: VecDeque::new(),
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T: InactivePageTable> SwapManager for SecondChanceSwapManager<T> {
+    fn tick(&mut self, _: &mut PageTable) {}
+
+    fn push(&mut self, frame: Frame) {
+        info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
+        self.deque.push_back(frame);
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        info!("SwapManager remove token: {:x?} vaddr: {:x?}", token, addr);
+        let id = self.deque.iter()
+            .position(|ref x| x.get_virtaddr() == addr && x.get_token() == token)
+            .expect("address not found");
+        self.deque.remove(id);
+    }
+
+    fn pop<S>(&mut self, page_table: &mut PageTable, _: &mut S) -> Option<Frame>
+        where S: Swapper
+    {
+        // every page has its accessed bit cleared in the first round
+        while let Some(frame) = self.deque.pop_front() {
+            let accessed = unsafe {
+                with_entry::<T, _>(page_table, &frame, |entry| {
+                    let accessed = entry.accessed();
+                    if accessed {
+                        entry.clear_accessed();
+                        entry.update();
+                    }
+                    accessed
+                })
+            };
+            if !accessed {
+                return Some(frame);
+            }
+            self.deque.push_back(frame);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::mock_swapper::MockSwapper;
+    use paging::{MockInactivePageTable, MockActivePageTable};
+
+    #[test]
+    fn test() {
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = SecondChanceSwapManager::<MockInactivePageTable>::default();
+        let mut swapper = MockSwapper::default();
+        let mut pt = MockActivePageTable;
+        for i in 0..4 {
+            pt.map(i * 0x1000, i * 0x1000);
+            manager.push(frame(i * 0x1000));
+        }
+
+        pt.read(0x0);
+        pt.write(0x1000, 1);
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x2000)));
+        // queue: 0x3000 0x0 0x1000
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x3000)));
+
+        pt.read(0x0);
+        pt.read(0x1000);
+        // all are accessed, the oldest one is chosen after a round
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x0)));
+
+        manager.remove(inactive.token(), 0x1000);
+        assert_eq!(manager.pop(&mut pt, &mut swapper), None);
+    }
+}
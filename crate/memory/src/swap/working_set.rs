@@ -0,0 +1,177 @@
+//! Implememnt the swap manager with the working set page replacement algorithm,
+//! whose window is adjusted by the page fault frequency (PFF)
+//!
+//! The working set is the pages accessed in the last `window` ticks.
+//! On every tick, the accessed bits are sampled to record the last time each page is accessed.
+//! At most `TICK_SAMPLES` pages are sampled on a tick in turn, so with more pages,
+//! the time recorded is as coarse as the ticks taken to sample all of them.
+//! A page out of the working set is chosen as the victim, in the order of push.
+//! If all pages are in the working set, the least recently accessed one is chosen.
+//!
+//! Every `PFF_INTERVAL` ticks, the window is adjusted by the number of page faults
+//! (pages pushed) in the interval: if there are too many faults, the working set is too small,
+//! so the window is doubled; if there are too few, the window is halved.
+
+use alloc::vec::Vec;
+use super::*;
+
+/// Ticks in an interval to count page faults
+pub const PFF_INTERVAL: usize = 16;
+/// Double the window if there are more page faults in an interval
+pub const PFF_UPPER: usize = 8;
+/// Halve the window if there are fewer page faults in an interval
+pub const PFF_LOWER: usize = 2;
+pub const MIN_WINDOW: usize = 2;
+pub const MAX_WINDOW: usize = 256;
+
+pub struct WorkingSetSwapManager<T: InactivePageTable> {
+    /// swappable frames and the last time they are accessed, in the order of push
+    frames: Vec<(Frame, usize)>,
+    /// the index of the next frame to sample
+    hand: usize,
+    /// current time in ticks
+    time: usize,
+    /// the window of the working set in ticks
+    window: usize,
+    /// page faults in the current interval
+    faults: usize,
+    mark: PhantomData<T>,
+}
+
+impl<T: InactivePageTable> Default for WorkingSetSwapManager<T> {
+    fn default() -> Self {
+        WorkingSetSwapManager {
+            frames: Vec::new(),
+            hand: 0,
+            time: 0,
+            window: MIN_WINDOW,
+            faults: 0,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T: InactivePageTable> SwapManager for WorkingSetSwapManager<T> {
+    fn tick(&mut self, page_table: &mut PageTable) {
+        self.time += 1;
+        let time = self.time;
+        for id in tick_samples(self.frames.len(), &mut self.hand) {
+            let &mut (ref frame, ref mut last) = &mut self.frames[id];
+            unsafe {
+                with_entry::<T, _>(page_table, frame, |entry| {
+                    if entry.accessed() {
+                        *last = time;
+                        entry.clear_accessed();
+                        entry.update();
+                    }
+                });
+            }
+        }
+        if self.time % PFF_INTERVAL == 0 {
+            if self.faults > PFF_UPPER {
+                self.window = (self.window * 2).min(MAX_WINDOW);
+            } else if self.faults < PFF_LOWER {
+                self.window = (self.window / 2).max(MIN_WINDOW);
+            }
+            self.faults = 0;
+        }
+    }
+
+    fn push(&mut self, frame: Frame) {
+        info!("SwapManager push token: {:x?} vaddr: {:x?}", frame.get_token(), frame.get_virtaddr());
+        self.faults += 1;
+        self.frames.push((frame, self.time));
+    }
+
+    fn remove(&mut self, token: usize, addr: VirtAddr) {
+        info!("SwapManager remove token: {:x?} vaddr: {:x?}", token, addr);
+        let id = self.frames.iter()
+            .position(|&(ref x, _)| x.get_virtaddr() == addr && x.get_token() == token)
+            .expect("address not found");
+        self.frames.remove(id);
+    }
+
+    fn pop<S>(&mut self, _: &mut PageTable, _: &mut S) -> Option<Frame>
+        where S: Swapper
+    {
+        let Self { ref frames, time, window, .. } = *self;
+        let id = frames.iter()
+            .position(|&(_, last)| time - last >= window)
+            .or_else(|| frames.iter()
+                .enumerate()
+                .min_by_key(|&(_, &(_, last))| last)
+                .map(|(id, _)| id))?;
+        Some(self.frames.remove(id).0)
+    }
+}
+
+impl<T: InactivePageTable> WorkingSetSwapManager<T> {
+    /*
+    **  @brief  get the window of the working set
+    **  @retval usize                the window in ticks
+    */
+    pub fn window(&self) -> usize {
+        self.window
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::mock_swapper::MockSwapper;
+    use paging::{MockInactivePageTable, MockActivePageTable};
+
+    #[test]
+    fn working_set() {
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = WorkingSetSwapManager::<MockInactivePageTable>::default();
+        let mut swapper = MockSwapper::default();
+        let mut pt = MockActivePageTable;
+        for i in 0..4 {
+            pt.map(i * 0x1000, i * 0x1000);
+            manager.push(frame(i * 0x1000));
+        }
+        assert_eq!(manager.window(), MIN_WINDOW);
+
+        // last access: 0x0: 3, 0x1000: 0, 0x2000: 1, 0x3000: 2
+        pt.read(0x2000);
+        manager.tick(&mut pt);
+        pt.read(0x3000);
+        manager.tick(&mut pt);
+        pt.read(0x0);
+        manager.tick(&mut pt);
+
+        // 0x1000 and 0x2000 are out of the working set
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x1000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x2000)));
+        // all are in the working set, choose the least recently accessed
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x3000)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), Some(frame(0x0)));
+        assert_eq!(manager.pop(&mut pt, &mut swapper), None);
+    }
+
+    #[test]
+    fn page_fault_frequency() {
+        let inactive = MockInactivePageTable::new();
+        unsafe { inactive.activate(); }
+        let frame = |addr| Frame::new(&inactive as *const _ as usize, addr, inactive.token());
+        let mut manager = WorkingSetSwapManager::<MockInactivePageTable>::default();
+        let mut pt = MockActivePageTable;
+
+        // too many faults, grow the window
+        for i in 0..PFF_INTERVAL {
+            manager.push(frame(i * 0x1000));
+            manager.remove(inactive.token(), i * 0x1000);
+            manager.tick(&mut pt);
+        }
+        assert_eq!(manager.window(), MIN_WINDOW * 2);
+
+        // too few faults, shrink the window
+        for _ in 0..PFF_INTERVAL {
+            manager.tick(&mut pt);
+        }
+        assert_eq!(manager.window(), MIN_WINDOW);
+    }
+}
@@ -0,0 +1,479 @@
+//! Slab allocator for small kernel objects
+//!
+//! An object up to `MAX_SIZE` bytes is rounded up to a size class: a power of 2 no less than
+//! its size, its align and 8 bytes. Each size class cuts whole pages into objects of its size,
+//! and keeps the free ones in a list linked through their first word.
+//! As pages are page-aligned, every object is aligned to its size class.
+//! Larger objects are left to `SlabSupport::alloc_large`.
+//!
+//! Each CPU has a magazine for every size class: a small stack of free objects,
+//! so most allocations and frees don't touch the shared lists and their lock.
+//! An empty magazine is refilled to half from the shared list,
+//! and a full magazine is flushed to half into it.
+//!
+//! Pages are kept after their objects are freed. Under memory pressure, `shrink` sorts
+//! the shared lists by address, and gives back the pages whose objects are all in them.
+
+#![no_std]
+#![feature(const_fn)]
+
+extern crate spin;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ptr;
+use spin::Mutex;
+
+pub const PAGE_SIZE: usize = 4096;
+/// The smallest size class is `1 << MIN_CLASS_LOG2` bytes
+const MIN_CLASS_LOG2: usize = 3;
+/// Size classes: 8, 16, ..., 2048 bytes
+const CLASS_NUM: usize = 9;
+/// The largest size class
+pub const MAX_SIZE: usize = 1 << (MIN_CLASS_LOG2 + CLASS_NUM - 1);
+pub const MAX_CPU_NUM: usize = 8;
+/// Capacity of a magazine
+const MAGAZINE_SIZE: usize = 32;
+
+/// What the slab allocator needs from the kernel
+pub trait SlabSupport {
+    /// Id of the current CPU, less than `MAX_CPU_NUM`
+    fn cpu_id() -> usize;
+    /// Allocate a page, return its virtual address
+    fn alloc_page() -> Option<usize>;
+    fn dealloc_page(addr: usize);
+    /// Allocate an object which doesn't fit in any size class
+    unsafe fn alloc_large(layout: Layout) -> *mut u8;
+    unsafe fn dealloc_large(ptr: *mut u8, layout: Layout);
+    /// Disable interrupt and return the old state.
+    /// Magazines of a CPU must not be reentered by an interrupt handler on it.
+    unsafe fn disable_and_store() -> usize;
+    unsafe fn restore(flags: usize);
+}
+
+pub struct SlabAllocator<S> {
+    lists: Mutex<[FreeList; CLASS_NUM]>,
+    /// Only accessed by the owner CPU with interrupt disabled
+    magazines: UnsafeCell<[[Magazine; CLASS_NUM]; MAX_CPU_NUM]>,
+    support: PhantomData<S>,
+}
+
+unsafe impl<S> Sync for SlabAllocator<S> {}
+
+impl<S> SlabAllocator<S> {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            lists: Mutex::new([FreeList::EMPTY; CLASS_NUM]),
+            magazines: UnsafeCell::new([[Magazine::EMPTY; CLASS_NUM]; MAX_CPU_NUM]),
+            support: PhantomData,
+        }
+    }
+}
+
+impl<S: SlabSupport> SlabAllocator<S> {
+    unsafe fn magazine(&self, class: usize) -> &mut Magazine {
+        let cpu_id = S::cpu_id();
+        assert!(cpu_id < MAX_CPU_NUM);
+        &mut (*self.magazines.get())[cpu_id][class]
+    }
+
+    unsafe fn alloc_small(&self, class: usize) -> *mut u8 {
+        let magazine = self.magazine(class);
+        if magazine.len == 0 {
+            let mut lists = self.lists.lock();
+            let list = &mut lists[class];
+            while magazine.len < MAGAZINE_SIZE / 2 {
+                if list.head == 0 {
+                    match S::alloc_page() {
+                        Some(page) => list.push_page(page, class_size(class)),
+                        None => break,
+                    }
+                }
+                magazine.push(list.pop());
+            }
+        }
+        match magazine.pop() {
+            Some(addr) => addr as *mut u8,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc_small(&self, addr: usize, class: usize) {
+        let magazine = self.magazine(class);
+        if magazine.len == MAGAZINE_SIZE {
+            let mut lists = self.lists.lock();
+            while magazine.len > MAGAZINE_SIZE / 2 {
+                lists[class].push(magazine.pop().unwrap());
+            }
+        }
+        magazine.push(addr);
+    }
+
+    /// Give back pages with no object in use, return the number of them.
+    ///
+    /// Objects in magazines of other CPUs are still counted as in use.
+    pub fn shrink(&self) -> usize {
+        unsafe {
+            let flags = S::disable_and_store();
+            let mut lists = self.lists.lock();
+            let mut count = 0;
+            // free pages are linked through their first word, and given back after unlocking,
+            // since `dealloc_page` may wait for other CPUs spinning on the lock
+            let mut pages = 0;
+            for class in 0..CLASS_NUM {
+                let magazine = self.magazine(class);
+                while let Some(addr) = magazine.pop() {
+                    lists[class].push(addr);
+                }
+                lists[class].sort();
+                count += lists[class].remove_free_pages(class_size(class), |page| {
+                    set_next(page, pages);
+                    pages = page;
+                });
+            }
+            drop(lists);
+            while pages != 0 {
+                let next = next_of(pages);
+                S::dealloc_page(pages);
+                pages = next;
+            }
+            S::restore(flags);
+            count
+        }
+    }
+}
+
+unsafe impl<S: SlabSupport> GlobalAlloc for SlabAllocator<S> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match class_of(&layout) {
+            Some(class) => {
+                let flags = S::disable_and_store();
+                let ptr = self.alloc_small(class);
+                S::restore(flags);
+                ptr
+            }
+            None => S::alloc_large(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match class_of(&layout) {
+            Some(class) => {
+                let flags = S::disable_and_store();
+                self.dealloc_small(ptr as usize, class);
+                S::restore(flags);
+            }
+            None => S::dealloc_large(ptr, layout),
+        }
+    }
+}
+
+/// Size class of objects with `layout`, None if too large
+fn class_of(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    if size > MAX_SIZE {
+        return None;
+    }
+    let log2 = size.trailing_zeros() as usize;
+    Some(log2.max(MIN_CLASS_LOG2) - MIN_CLASS_LOG2)
+}
+
+fn class_size(class: usize) -> usize {
+    1 << (class + MIN_CLASS_LOG2)
+}
+
+/// Singly linked list of free objects, the next pointer is stored in the first word
+#[derive(Debug, Copy, Clone)]
+struct FreeList {
+    head: usize,
+}
+
+impl FreeList {
+    const EMPTY: FreeList = FreeList { head: 0 };
+
+    unsafe fn push(&mut self, addr: usize) {
+        *(addr as *mut usize) = self.head;
+        self.head = addr;
+    }
+    unsafe fn pop(&mut self) -> usize {
+        assert_ne!(self.head, 0, "pop from empty list");
+        let addr = self.head;
+        self.head = *(addr as *const usize);
+        addr
+    }
+    /// Cut a page into objects of `size`, push them in address order
+    unsafe fn push_page(&mut self, page: usize, size: usize) {
+        for addr in (page..page + PAGE_SIZE).step_by(size).rev() {
+            self.push(addr);
+        }
+    }
+    /// Sort the objects by address
+    unsafe fn sort(&mut self) {
+        self.head = merge_sort(self.head);
+    }
+    /// Remove the objects of pages with all objects free, then call `f` with these pages.
+    /// The list must be sorted.
+    unsafe fn remove_free_pages(&mut self, size: usize, mut f: impl FnMut(usize)) -> usize {
+        let mut count = 0;
+        // where the address of the current object is stored
+        let mut link = &mut self.head as *mut usize;
+        while *link != 0 {
+            let page = *link & !(PAGE_SIZE - 1);
+            let mut end = *link;
+            let mut num = 0;
+            while end != 0 && end & !(PAGE_SIZE - 1) == page {
+                end = next_of(end);
+                num += 1;
+            }
+            if num == PAGE_SIZE / size {
+                *link = end;
+                f(page);
+                count += 1;
+            } else {
+                for _ in 0..num {
+                    link = *link as *mut usize;
+                }
+            }
+        }
+        count
+    }
+}
+
+unsafe fn next_of(addr: usize) -> usize {
+    *(addr as *const usize)
+}
+
+unsafe fn set_next(addr: usize, next: usize) {
+    *(addr as *mut usize) = next;
+}
+
+/// Sort a list of objects by address, return the new head
+unsafe fn merge_sort(head: usize) -> usize {
+    if head == 0 || next_of(head) == 0 {
+        return head;
+    }
+    // split it in halves
+    let (mut slow, mut fast) = (head, next_of(head));
+    while fast != 0 && next_of(fast) != 0 {
+        slow = next_of(slow);
+        fast = next_of(next_of(fast));
+    }
+    let second = next_of(slow);
+    set_next(slow, 0);
+    let (mut a, mut b) = (merge_sort(head), merge_sort(second));
+    // merge them after a dummy head
+    let mut dummy: usize = 0;
+    let mut tail = &mut dummy as *mut usize as usize;
+    while a != 0 && b != 0 {
+        let node = if a < b { a } else { b };
+        if a < b { a = next_of(a); } else { b = next_of(b); }
+        set_next(tail, node);
+        tail = node;
+    }
+    set_next(tail, if a != 0 { a } else { b });
+    dummy
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Magazine {
+    len: usize,
+    objs: [usize; MAGAZINE_SIZE],
+}
+
+impl Magazine {
+    const EMPTY: Magazine = Magazine { len: 0, objs: [0; MAGAZINE_SIZE] };
+
+    fn push(&mut self, addr: usize) {
+        self.objs[self.len] = addr;
+        self.len += 1;
+    }
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.objs[self.len])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::{alloc, dealloc};
+    use std::cell::Cell;
+    use std::vec::Vec;
+
+    thread_local! {
+        static CPU_ID: Cell<usize> = Cell::new(0);
+        static PAGES: Cell<usize> = Cell::new(0);
+        static LARGE: Cell<usize> = Cell::new(0);
+    }
+
+    /// Each test runs in its own thread, so it has its own counters
+    struct TestSupport;
+
+    impl SlabSupport for TestSupport {
+        fn cpu_id() -> usize {
+            CPU_ID.with(|c| c.get())
+        }
+        fn alloc_page() -> Option<usize> {
+            PAGES.with(|c| c.set(c.get() + 1));
+            Some(unsafe { alloc(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) } as usize)
+        }
+        fn dealloc_page(addr: usize) {
+            PAGES.with(|c| c.set(c.get() - 1));
+            unsafe { dealloc(addr as *mut u8, Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()) }
+        }
+        unsafe fn alloc_large(layout: Layout) -> *mut u8 {
+            LARGE.with(|c| c.set(c.get() + 1));
+            alloc(layout)
+        }
+        unsafe fn dealloc_large(ptr: *mut u8, layout: Layout) {
+            LARGE.with(|c| c.set(c.get() - 1));
+            dealloc(ptr, layout)
+        }
+        unsafe fn disable_and_store() -> usize { 0 }
+        unsafe fn restore(_flags: usize) {}
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn size_class() {
+        assert_eq!(class_of(&layout(1, 1)), Some(0));
+        assert_eq!(class_of(&layout(8, 8)), Some(0));
+        assert_eq!(class_of(&layout(9, 1)), Some(1));
+        assert_eq!(class_of(&layout(24, 8)), Some(2));
+        assert_eq!(class_of(&layout(8, 64)), Some(3));
+        assert_eq!(class_of(&layout(2048, 8)), Some(8));
+        assert_eq!(class_of(&layout(2049, 8)), None);
+        assert_eq!(class_of(&layout(8, 4096)), None);
+    }
+
+    #[test]
+    fn alloc_dealloc() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        let sizes = [1, 8, 24, 100, 512, 2000];
+        let mut objs = Vec::new();
+        for i in 0..200 {
+            let size = sizes[i % sizes.len()];
+            let ptr = unsafe { slab.alloc(layout(size, 8)) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % size.max(8).next_power_of_two(), 0);
+            unsafe { ptr::write_bytes(ptr, i as u8, size); }
+            objs.push((ptr, size, i as u8));
+        }
+        // no object is overwritten by others
+        for &(ptr, size, value) in objs.iter() {
+            let data = unsafe { std::slice::from_raw_parts(ptr, size) };
+            assert!(data.iter().all(|&b| b == value));
+        }
+        let pages = PAGES.with(|c| c.get());
+        for &(ptr, size, _) in objs.iter() {
+            unsafe { slab.dealloc(ptr, layout(size, 8)); }
+        }
+        // freed objects are reused
+        for &(_, size, _) in objs.iter() {
+            unsafe { slab.alloc(layout(size, 8)); }
+        }
+        assert_eq!(PAGES.with(|c| c.get()), pages);
+    }
+
+    #[test]
+    fn aligned() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        for &align in [16, 64, 256, 2048].iter() {
+            let ptr = unsafe { slab.alloc(layout(8, align)) };
+            assert_eq!(ptr as usize % align, 0);
+        }
+    }
+
+    #[test]
+    fn magazine() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        let l = layout(64, 8);
+        let a = unsafe { slab.alloc(l) } as usize;
+        // refilled to half from the list
+        let magazine = unsafe { slab.magazine(3) };
+        assert_eq!(magazine.len, MAGAZINE_SIZE / 2 - 1);
+        assert_eq!(magazine.objs[magazine.len - 1], a - 64);
+        assert_eq!(slab.lists.lock()[3].head, a + 64);
+
+        let objs: Vec<_> = (0..MAGAZINE_SIZE).map(|_| unsafe { slab.alloc(l) }).collect();
+        assert_eq!(magazine.len, MAGAZINE_SIZE / 2 - 1);
+        // full magazine is flushed to half
+        for &ptr in objs.iter() {
+            unsafe { slab.dealloc(ptr, l); }
+        }
+        assert_eq!(magazine.len, MAGAZINE_SIZE - 1);
+        let head = slab.lists.lock()[3].head as *mut u8;
+        assert!(objs.contains(&head));
+        assert_eq!(PAGES.with(|c| c.get()), 1);
+    }
+
+    #[test]
+    fn per_cpu() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        let l = layout(16, 16);
+        let a = unsafe { slab.alloc(l) };
+        // freed on another CPU, then reused by it
+        CPU_ID.with(|c| c.set(1));
+        unsafe { slab.dealloc(a, l); }
+        assert_eq!(unsafe { slab.alloc(l) }, a);
+        CPU_ID.with(|c| c.set(0));
+        assert_ne!(unsafe { slab.alloc(l) }, a);
+    }
+
+    #[test]
+    fn large() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        let l = layout(0x8000, 0x8000);
+        let ptr = unsafe { slab.alloc(l) };
+        assert_eq!(ptr as usize % 0x8000, 0);
+        assert_eq!(LARGE.with(|c| c.get()), 1);
+        unsafe { slab.dealloc(ptr, l); }
+        assert_eq!(LARGE.with(|c| c.get()), 0);
+        assert_eq!(PAGES.with(|c| c.get()), 0);
+    }
+
+    #[test]
+    fn sort() {
+        let mut objs = [0usize; 8];
+        let mut list = FreeList::EMPTY;
+        for &i in [3, 1, 4, 0, 7, 5, 2, 6].iter() {
+            unsafe { list.push(&mut objs[i] as *mut _ as usize); }
+        }
+        unsafe { list.sort(); }
+        for i in 0..8 {
+            assert_eq!(unsafe { list.pop() }, &objs[i] as *const _ as usize);
+        }
+        assert_eq!(list.head, 0);
+    }
+
+    #[test]
+    fn shrink() {
+        let slab = SlabAllocator::<TestSupport>::new();
+        let l = layout(1024, 8);
+        let objs: Vec<_> = (0..20).map(|_| unsafe { slab.alloc(l) }).collect();
+        // objects are in 5 pages, others are free
+        let pages = PAGES.with(|c| c.get());
+        assert_eq!(slab.shrink(), pages - 5);
+        assert_eq!(PAGES.with(|c| c.get()), 5);
+        // free all objects but one, its page is kept
+        for &ptr in objs[1..].iter() {
+            unsafe { slab.dealloc(ptr, l); }
+        }
+        assert_eq!(slab.shrink(), 4);
+        assert_eq!(PAGES.with(|c| c.get()), 1);
+        unsafe { slab.dealloc(objs[0], l); }
+        assert_eq!(slab.shrink(), 1);
+        assert_eq!(PAGES.with(|c| c.get()), 0);
+    }
+}
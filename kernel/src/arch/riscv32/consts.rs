@@ -5,10 +5,18 @@ const P2_MASK: usize = 0x3ff << 22;
 pub const RECURSIVE_INDEX: usize = 0x3fe;
 pub const KERNEL_OFFSET: usize = 0;
 pub const KERNEL_P2_INDEX: usize = 0x8000_0000 >> 22;
-pub const KERNEL_HEAP_SIZE: usize = 0x00a0_0000;
+/// Offset and size of the kernel heap area, where pages for the heap are mapped
+pub const KERNEL_HEAP_OFFSET: usize = 0xC000_0000;
+pub const KERNEL_HEAP_AREA_SIZE: usize = 0x0100_0000;
 pub const MEMORY_OFFSET: usize = 0x8000_0000;
+/// Size of a huge page (megapage), mapped by a p2 entry
+pub const HUGE_PAGE_SIZE: usize = P2_SIZE;
 //pub const MEMORY_END: usize = 0x8080_0000; //for thinpad, not enough now
 pub const MEMORY_END: usize = 0x8100_0000;
 pub const USER_STACK_OFFSET: usize = 0x70000000;
 pub const USER_STACK_SIZE: usize = 0x10000;
-pub const USER32_STACK_OFFSET: usize = USER_STACK_OFFSET;
\ No newline at end of file
+pub const USER32_STACK_OFFSET: usize = USER_STACK_OFFSET;
+/// Offset and end of the area searched for mmap without a fixed address,
+/// below the guard page of the user stack at its limit
+pub const USER_MMAP_OFFSET: usize = 0x4000_0000;
+pub const USER_MMAP_END: usize = USER_STACK_OFFSET - 0x1000;
\ No newline at end of file
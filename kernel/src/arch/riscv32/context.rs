@@ -167,6 +167,12 @@ impl Context {
         lw sp, 0(a1)
         lw s11, 1*4(sp)
         csrrw x0, 0x180, s11 // satp
+        // flush the TLB unless the page table is tagged with an ASID, see `InactivePageTable0::switch_token`
+        srli t0, s11, 22
+        andi t0, t0, 0x1ff
+        bnez t0, 1f
+        sfence.vma
+1:
         lw ra, 0*4(sp)
         lw s0, 2*4(sp)
         lw s1, 3*4(sp)
@@ -260,4 +266,14 @@ impl Context {
     pub unsafe fn get_init_tf(&self) -> TrapFrame {
         (*(self.0 as *const InitStack)).tf.clone()
     }
+
+    /*
+    * @param:
+    *   satp: the satp to load when switching to this context, on the current hart
+    * @brief:
+    *   set the page table of the context, right before switching to it, see `InactivePageTable0::switch_token`
+    */
+    pub unsafe fn set_page_table(&mut self, satp: usize) {
+        (*(self.0 as *mut ContextData)).satp = satp;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+//! Minimal parser of the flattened device tree (FDT) passed by bbl,
+//! only to find the physical memory regions and the reserved ones.
+//!
+//! See the Devicetree Specification, chapter 5 for the format.
+
+use core::{slice, str};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/*
+* @param:
+*   dtb: the physical address of the device tree blob
+*   f: called with the base address and size of each memory region
+* @brief:
+*   walk through the 'reg' property of all memory nodes in the device tree
+* @retval:
+*   false if there is no valid device tree at 'dtb'
+*/
+pub fn memory_regions(dtb: usize, mut f: impl FnMut(usize, usize)) -> bool {
+    let data = match blob(dtb) {
+        Some(data) => data,
+        None => return false,
+    };
+    let strings = read_u32(data, 12) as usize;
+
+    let mut pos = read_u32(data, 8) as usize;
+    let mut depth = 0;
+    let mut in_memory = false;
+    // default values for the root node, from the specification
+    let mut address_cells = 2;
+    let mut size_cells = 1;
+    loop {
+        let token = read_u32(data, pos);
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = read_str(data, pos);
+                pos += align4(name.len() + 1);
+                depth += 1;
+                // children of the root are at depth 2
+                if depth == 2 {
+                    in_memory = name == "memory" || name.starts_with("memory@");
+                }
+            }
+            FDT_END_NODE => {
+                depth -= 1;
+                in_memory = false;
+            }
+            FDT_PROP => {
+                let len = read_u32(data, pos) as usize;
+                let name = read_str(data, strings + read_u32(data, pos + 4) as usize);
+                let value = &data[pos + 8..pos + 8 + len];
+                pos += 8 + align4(len);
+                match (depth, name) {
+                    (1, "#address-cells") => address_cells = read_u32(value, 0) as usize,
+                    (1, "#size-cells") => size_cells = read_u32(value, 0) as usize,
+                    (2, "reg") if in_memory => {
+                        for entry in value.chunks((address_cells + size_cells) * 4) {
+                            let base = read_cells(entry, 0, address_cells);
+                            let size = read_cells(entry, address_cells * 4, size_cells);
+                            f(base, size);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            // FDT_END, or anything unknown
+            _ => break,
+        }
+    }
+    true
+}
+
+/*
+* @param:
+*   dtb: the physical address of the device tree blob
+*   f: called with the base address and size of each reserved region
+* @brief:
+*   walk through the memory reservation block of the device tree,
+*   the regions in it must not be allocated though they are in the memory nodes
+* @retval:
+*   false if there is no valid device tree at 'dtb'
+*/
+pub fn reserved_regions(dtb: usize, mut f: impl FnMut(usize, usize)) -> bool {
+    let data = match blob(dtb) {
+        Some(data) => data,
+        None => return false,
+    };
+    // pairs of 64-bit address and size, ending with a pair of zero
+    let mut pos = read_u32(data, 16) as usize;
+    loop {
+        let base = read_cells(data, pos, 2);
+        let size = read_cells(data, pos + 8, 2);
+        if base == 0 && size == 0 {
+            break;
+        }
+        f(base, size);
+        pos += 16;
+    }
+    true
+}
+
+/// The device tree blob at 'dtb', or None if there is no valid one
+fn blob(dtb: usize) -> Option<&'static [u8]> {
+    if dtb == 0 || dtb % 4 != 0 {
+        return None;
+    }
+    let header = unsafe { slice::from_raw_parts(dtb as *const u8, 40) };
+    if read_u32(header, 0) != FDT_MAGIC {
+        return None;
+    }
+    let total_size = read_u32(header, 4) as usize;
+    Some(unsafe { slice::from_raw_parts(dtb as *const u8, total_size) })
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (data[pos] as u32) << 24 | (data[pos + 1] as u32) << 16 | (data[pos + 2] as u32) << 8 | data[pos + 3] as u32
+}
+
+/// Read a number of `cells` big-endian u32, saturated to `usize`
+fn read_cells(data: &[u8], pos: usize, cells: usize) -> usize {
+    let value = (0..cells).fold(0u64, |value, i| value << 32 | read_u32(data, pos + i * 4) as u64);
+    value.min(usize::max_value() as u64) as usize
+}
+
+fn read_str(data: &[u8], pos: usize) -> &str {
+    let len = data[pos..].iter().position(|&c| c == 0).unwrap();
+    str::from_utf8(&data[pos..pos + len]).unwrap_or("")
+}
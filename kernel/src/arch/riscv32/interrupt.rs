@@ -2,6 +2,8 @@ use super::riscv::register::*;
 pub use self::context::*;
 use ::memory::{InactivePageTable0};
 use memory::MemorySet;
+use memory::{PageFault, Access};
+use ucore_memory::paging::{PageTable, Entry};
 
 #[path = "context.rs"]
 mod context;
@@ -79,9 +81,9 @@ pub extern fn rust_trap(tf: &mut TrapFrame) {
         Trap::Interrupt(I::SupervisorTimer) => timer(),
         Trap::Exception(E::IllegalInstruction) => illegal_inst(tf),
         Trap::Exception(E::UserEnvCall) => syscall(tf),
-        Trap::Exception(E::LoadPageFault) => page_fault(tf),
-        Trap::Exception(E::StorePageFault) => page_fault(tf),
-        Trap::Exception(E::InstructionPageFault) => page_fault(tf),
+        Trap::Exception(E::LoadPageFault) => page_fault(tf, Access::Read),
+        Trap::Exception(E::StorePageFault) => page_fault(tf, Access::Write),
+        Trap::Exception(E::InstructionPageFault) => page_fault(tf, Access::Execute),
         _ => ::trap::error(tf),
     }
     trace!("Interrupt end");
@@ -94,6 +96,7 @@ fn serial() {
 fn ipi() {
     debug!("IPI");
     super::bbl::sbi::clear_ipi();
+    ::trap::ipi();
 }
 
 /*
@@ -132,14 +135,19 @@ fn illegal_inst(tf: &mut TrapFrame) {
 /*
 * @param:
 *   TrapFrame: the Trapframe for the page fault exception
+*   access: the kind of the access, by the cause of the exception
 * @brief:
 *   process page fault exception
 */
-fn page_fault(tf: &mut TrapFrame) {
+fn page_fault(tf: &mut TrapFrame, access: Access) {
+    use super::riscv::register::sstatus::SPP;
     let addr = stval::read();
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
-    if !::memory::page_fault_handler(addr) {
+    // the cause does not tell a missing page from a protection fault, so look up the entry
+    let present = ::memory::active_table().get_entry(addr).map_or(false, |entry| entry.present());
+    let fault = PageFault { addr, access, user: tf.sstatus.spp() == SPP::User, present };
+    if !::memory::page_fault_handler(fault) {
         ::trap::error(tf);
     }
 }
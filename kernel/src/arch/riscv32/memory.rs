@@ -1,14 +1,16 @@
 use core::{slice, mem};
-use memory::{active_table, FRAME_ALLOCATOR, init_heap, MemoryArea, MemoryAttr, MemorySet, SimpleMemoryHandler};
+use memory::{active_table, MemoryArea, MemoryAttr, MemorySet, SimpleMemoryHandler};
 use super::riscv::{addr::*, register::sstatus};
 use ucore_memory::PAGE_SIZE;
 use alloc::boxed::Box;
 
 /*
+* @param:
+*   dtb: the physical address of the device tree blob
 * @brief:
-*   Init the mermory management module, allow memory access and set up page table and init heap and frame allocator
+*   Init the mermory management module, allow memory access and set up page table and init frame allocator
 */
-pub fn init() {
+pub fn init(dtb: usize) {
     #[repr(align(4096))]  // align the PageData struct to 4096 bytes
     struct PageData([u8; PAGE_SIZE]);
     static PAGE_TABLE_ROOT: PageData = PageData([0; PAGE_SIZE]);
@@ -16,9 +18,8 @@ pub fn init() {
     unsafe { sstatus::set_sum(); }  // Allow user memory access
     let frame = Frame::of_addr(PhysAddr::new(&PAGE_TABLE_ROOT as *const _ as u32));
     super::paging::setup_page_table(frame); // set up page table
-    // initialize heap and Frame allocator
-    init_frame_allocator();
-    init_heap();
+    // initialize Frame allocator, then heap is available
+    init_frame_allocator(dtb);
     // remap the kernel use 4K page
     remap_the_kernel();
 }
@@ -31,16 +32,41 @@ pub fn init_other() {
 }
 
 /*
+* @param:
+*   dtb: the physical address of the device tree blob
 * @brief:
-*   Init frame allocator, here use a BitAlloc implemented by segment tree.
+*   Init frame allocator with the memory regions in the device tree,
+*   or [end of kernel, MEMORY_END) if there is no device tree.
+*   Then reserve the regions in the memory reservation block of the device tree.
+*   The algorithm is chosen by `memory::FrameAlloc`.
 */
-fn init_frame_allocator() {
-    use bit_allocator::BitAlloc;
+fn init_frame_allocator(dtb: usize) {
     use core::ops::Range;
     use consts::{MEMORY_OFFSET, MEMORY_END};
+    use super::fdt;
 
-    let mut ba = FRAME_ALLOCATOR.lock();
-    ba.insert(to_range(end as usize + PAGE_SIZE, MEMORY_END));
+    const MAX_REGION_NUM: usize = 8;
+    let mut regions: [Range<usize>; MAX_REGION_NUM] = Default::default();
+    let mut num = 0;
+    // memory before the end of kernel is used by bbl, the device tree and the kernel itself
+    let free_start = end as usize + PAGE_SIZE;
+    fdt::memory_regions(dtb, |base, size| {
+        let start = base.max(free_start);
+        let stop = base.saturating_add(size);
+        if start < stop && num < MAX_REGION_NUM {
+            info!("memory region: [{:#x}, {:#x})", start, stop);
+            regions[num] = to_range(start, stop);
+            num += 1;
+        }
+    });
+    if num == 0 {
+        regions[0] = to_range(free_start, MEMORY_END);
+        num = 1;
+    }
+    ::memory::init_frame_allocator(regions[..num].iter().cloned());
+    fdt::reserved_regions(dtb, |base, size| {
+        ::memory::reserve_frames(base, base.saturating_add(size));
+    });
     info!("FrameAllocator init end");
 
     /*
@@ -9,6 +9,7 @@ pub mod memory;
 pub mod compiler_rt;
 pub mod consts;
 pub mod cpu;
+pub mod fdt;
 
 #[no_mangle]
 pub extern fn rust_main(hartid: usize, dtb: usize, hart_mask: usize) -> ! {
@@ -23,7 +24,7 @@ pub extern fn rust_main(hartid: usize, dtb: usize, hart_mask: usize) -> ! {
 
     ::logging::init();
     interrupt::init();
-    memory::init();
+    memory::init(dtb);
     timer::init();
     ::process::init();
 
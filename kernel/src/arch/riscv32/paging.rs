@@ -1,14 +1,15 @@
-use consts::{KERNEL_P2_INDEX, RECURSIVE_INDEX};
+use consts::{KERNEL_P2_INDEX, RECURSIVE_INDEX, KERNEL_HEAP_OFFSET, KERNEL_HEAP_AREA_SIZE, HUGE_PAGE_SIZE};
 // Depends on kernel
 use memory::{active_table, alloc_frame, dealloc_frame};
 use super::riscv::addr::*;
-use super::riscv::asm::{sfence_vma, sfence_vma_all};
+use super::riscv::asm::sfence_vma_all;
 use super::riscv::paging::{Mapper, PageTable as RvPageTable, PageTableEntry, PageTableFlags as EF, RecursivePageTable};
 use super::riscv::paging::{FrameAllocator, FrameDeallocator};
 use super::riscv::register::satp;
 use ucore_memory::memory_set::*;
 use ucore_memory::PAGE_SIZE;
 use ucore_memory::paging::*;
+use core::sync::atomic::AtomicUsize;
 
 /*
 * @param:
@@ -30,6 +31,7 @@ pub fn setup_page_table(frame: Frame) {
     p2.map_identity(KERNEL_P2_INDEX + 1, EF::VALID | EF::READABLE | EF::WRITABLE | EF::EXECUTABLE);
     p2.map_identity(KERNEL_P2_INDEX + 2, EF::VALID | EF::READABLE | EF::WRITABLE | EF::EXECUTABLE);
     //p2.map_identity(KERNEL_P2_INDEX + 3, EF::VALID | EF::READABLE | EF::WRITABLE | EF::EXECUTABLE);
+    map_kernel_heap_area(p2);
 
     use super::riscv::register::satp;
     unsafe { satp::set(satp::Mode::Sv32, 0, frame); }
@@ -37,9 +39,94 @@ pub fn setup_page_table(frame: Frame) {
     info!("setup init page table end");
 }
 
+#[repr(align(4096))]  // align the PageData struct to 4096 bytes
+#[derive(Copy, Clone)]
+struct PageData([u8; PAGE_SIZE]);
+
+// each p1 table covers 4M of the kernel heap area
+const KERNEL_HEAP_P1_NUM: usize = KERNEL_HEAP_AREA_SIZE >> 22;
+
+// p1 tables of the kernel heap area, shared by all page tables,
+// so a page mapped here is visible in every address space at once
+static mut KERNEL_HEAP_P1: [PageData; KERNEL_HEAP_P1_NUM] = [PageData([0; PAGE_SIZE]); KERNEL_HEAP_P1_NUM];
+
+/*
+* @param:
+*   p2: the p2 page table to set up
+* @brief:
+*   point the p2 entries of the kernel heap area to the shared p1 tables
+*/
+fn map_kernel_heap_area(p2: &mut RvPageTable) {
+    for i in 0..KERNEL_HEAP_P1_NUM {
+        let p1 = unsafe { &KERNEL_HEAP_P1[i] as *const _ as u32 };
+        p2[(KERNEL_HEAP_OFFSET >> 22) + i].set(Frame::of_addr(PhysAddr::new(p1)), EF::VALID);
+    }
+}
+
+/*
+* @param:
+*   addr: the virtual address in the kernel heap area
+* @brief:
+*   get the p1 entry of 'addr' in the shared p1 tables.
+*   it doesn't need the lock of the active table, so it can be used by the heap allocator.
+* @retval:
+*   the p1 entry of 'addr'
+*/
+fn kernel_heap_entry(addr: usize) -> &'static mut PageTableEntry {
+    assert!(addr >= KERNEL_HEAP_OFFSET && addr < KERNEL_HEAP_OFFSET + KERNEL_HEAP_AREA_SIZE,
+            "{:#x} is not in the kernel heap area", addr);
+    let page = (addr - KERNEL_HEAP_OFFSET) / PAGE_SIZE;
+    let p1 = unsafe { &mut *(&mut KERNEL_HEAP_P1[page / 1024] as *mut _ as *mut RvPageTable) };
+    &mut p1[page % 1024]
+}
+
+/*
+* @param:
+*   addr: the virtual address in the kernel heap area
+*   target: the physical address of the frame
+* @brief:
+*   map a page of the kernel heap area to the frame
+*/
+pub unsafe fn map_kernel_heap_page(addr: usize, target: usize) {
+    let entry = kernel_heap_entry(addr);
+    assert!(entry.is_unused(), "kernel heap page {:#x} is already mapped", addr);
+    entry.set(Frame::of_addr(PhysAddr::new(target as u32)), EF::VALID | EF::READABLE | EF::WRITABLE);
+    local_flush_tlb(addr);
+}
+
+/*
+* @param:
+*   addr: the virtual address in the kernel heap area
+* @brief:
+*   unmap a page of the kernel heap area
+* @retval:
+*   the physical address of the unmapped frame
+*/
+pub unsafe fn unmap_kernel_heap_page(addr: usize) -> usize {
+    let entry = kernel_heap_entry(addr);
+    assert!(!entry.is_unused(), "kernel heap page {:#x} is not mapped", addr);
+    let target = entry.addr().as_u32() as usize;
+    entry.set_unused();
+    local_flush_tlb(addr);
+    target
+}
+
+/*
+* @brief:
+*   flush TLB of other harts, after unmapping pages of the kernel heap area shared with them
+*/
+pub fn flush_kernel_heap_tlb_others() {
+    use consts::MAX_CPU_NUM;
+    use super::{bbl::sbi, cpu};
+    let hart_mask = ((1 << MAX_CPU_NUM) - 1) & !(1 << cpu::id());
+    sbi::remote_sfence_vma(hart_mask, KERNEL_HEAP_OFFSET, KERNEL_HEAP_AREA_SIZE);
+}
+
 pub struct ActivePageTable(RecursivePageTable<'static>, PageEntry);
 
-pub struct PageEntry(PageTableEntry, Page);
+/// A copy of the entry of a page, written back by `update`.
+/// The last field tells whether it is the p2 entry of a huge page (megapage).
+pub struct PageEntry(PageTableEntry, Page, bool);
 
 impl PageTable for ActivePageTable {
     //type Entry = PageEntry;
@@ -76,6 +163,8 @@ impl PageTable for ActivePageTable {
         let page = Page::of_addr(VirtAddr::new(addr));
         let (frame, flush) = self.0.unmap(page).unwrap();
         flush.flush();
+        invalidate_others(&page);
+        free_empty_p1(&page);
     }
 
     /*
@@ -92,13 +181,58 @@ impl PageTable for ActivePageTable {
         if !p2[page.p2_index()].flags().contains(EF::VALID) {
             return None;
         }
+        // a huge page is a leaf in the p2 table
+        if is_leaf(&p2[page.p2_index()]) {
+            self.1 = PageEntry(p2[page.p2_index()], page, true);
+            return Some(&mut self.1);
+        }
         // get a copy of the entry
         let entry = edit_entry_of(&page, |entry| *entry);
-        self.1 = PageEntry(entry, page);
+        self.1 = PageEntry(entry, page, false);
         Some(&mut self.1)
     }
 
 
+    fn huge_page_size(&self) -> usize {
+        HUGE_PAGE_SIZE
+    }
+
+    /*
+    * @param:
+    *   addr: the virtual addr aligned to 4M
+    *   target: the physical addr aligned to 4M
+    * @brief:
+    *   map the huge page (megapage) at 'addr' to 'target' by a leaf p2 entry
+    * @retval:
+    *   the PageEntry of the huge page, None if the p2 entry is in use, e.g. by a p1 table
+    */
+    fn map_huge(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
+        let p2 = unsafe { &mut *ROOT_PAGE_TABLE };
+        let page = Page::of_addr(VirtAddr::new(addr));
+        if !p2[page.p2_index()].is_unused() {
+            return None;
+        }
+        let flags = EF::VALID | EF::READABLE | EF::WRITABLE;
+        p2[page.p2_index()].set(Frame::of_addr(PhysAddr::new(target as u32)), flags);
+        local_flush_tlb(addr);
+        self.get_entry(addr)
+    }
+
+    /*
+    * @param:
+    *   addr: the virtual addr of the huge page
+    * @brief:
+    *   unmap the huge page (megapage) at 'addr'
+    */
+    fn unmap_huge(&mut self, addr: usize) {
+        let p2 = unsafe { &mut *ROOT_PAGE_TABLE };
+        let page = Page::of_addr(VirtAddr::new(addr));
+        assert!(is_leaf(&p2[page.p2_index()]), "{:#x} is not a huge page", addr);
+        p2[page.p2_index()].set_unused();
+        local_flush_tlb(addr);
+        invalidate_others(&page);
+    }
+
     /*
     * @param:
     *   addr:the input (virutal) address
@@ -112,6 +246,31 @@ impl PageTable for ActivePageTable {
         unsafe { slice::from_raw_parts_mut((addr & !(PAGE_SIZE - 1)) as *mut u8, PAGE_SIZE) }
     }
 
+    /*
+    * @param:
+    *   addr: the virtual address to search from
+    * @brief:
+    *   find the first mapped page containing or after 'addr', skipping the recursive mapping
+    * @retval:
+    *   the start address of the page or the huge page, None if not found
+    */
+    fn next_mapped(&mut self, addr: usize) -> Option<usize> {
+        let p2 = unsafe { &*ROOT_PAGE_TABLE };
+        let page = Page::of_addr(VirtAddr::new(addr));
+        let recursive = |i: usize| i == RECURSIVE_INDEX || i == RECURSIVE_INDEX + 1;
+        for i in (page.p2_index()..1024).filter(|&i| !recursive(i) && !p2[i].is_unused()) {
+            if is_leaf(&p2[i]) {
+                return Some(i << 22);
+            }
+            let from = if i == page.p2_index() { page.p1_index() } else { 0 };
+            let p1_page = Page::of_addr(VirtAddr::new(i << 22));
+            if let Some(j) = with_p1_of(&p1_page, |p1| (from..1024).find(|&j| !p1[j].is_unused())) {
+                return Some((i << 22) | (j << 12));
+            }
+        }
+        None
+    }
+
     /*
     * @param:
     *   addr: virtual address
@@ -135,6 +294,108 @@ impl PageTable for ActivePageTable {
     }
 }
 
+/*
+* @param:
+*   entry: the p2 entry
+* @brief:
+*   check whether the p2 entry maps a huge page (megapage), rather than point to a p1 table.
+*   the p2 entry of a p1 table is readable only in edit_entry_of and with_p1_of.
+* @retval:
+*   whether the p2 entry is a leaf
+*/
+fn is_leaf(entry: &PageTableEntry) -> bool {
+    entry.flags().intersects(EF::READABLE | EF::EXECUTABLE)
+}
+
+/*
+* @param:
+*   page: the page whose entry is changed
+* @brief:
+*   flush the entry of 'page' from the TLB of other harts later, see `tlb`
+*/
+fn invalidate_others(page: &Page) {
+    ::tlb::invalidate(page.start_address().as_usize(), is_kernel_p2_index(page.p2_index()));
+}
+
+/*
+* @param:
+*   addr: the virtual address
+* @brief:
+*   flush the entry of 'addr' from the TLB of the current hart
+*/
+pub fn local_flush_tlb(addr: usize) {
+    // for all the ASIDs, `sfence_vma` of the riscv crate flushes only ASID 0
+    unsafe { asm!("sfence.vma $0, x0" :: "r"(addr) :: "volatile"); }
+}
+
+/*
+* @brief:
+*   flush the whole TLB of the current hart
+*/
+pub fn local_flush_tlb_all() {
+    sfence_vma_all();
+}
+
+/*
+* @brief:
+*   flush the whole TLB of the current hart, for all the ASIDs
+*/
+pub fn local_flush_tlb_all_asids() {
+    sfence_vma_all();
+}
+
+/*
+* @param:
+*   asid: the ASID
+* @brief:
+*   flush the entries of 'asid' from the TLB of the current hart
+*/
+fn local_flush_asid(asid: usize) {
+    unsafe { asm!("sfence.vma x0, $0" :: "r"(asid) :: "volatile"); }
+}
+
+const SATP_ASID_SHIFT: usize = 22;
+const SATP_ASID_MASK: usize = 0x1ff << SATP_ASID_SHIFT;
+
+/*
+* @brief:
+*   find the largest ASID supported by the hart, the ASID bits not implemented are read as 0
+* @retval:
+*   the largest ASID, 0 if ASIDs are not supported
+*/
+pub fn max_asid() -> usize {
+    unsafe {
+        let old = read_satp();
+        write_satp(old | SATP_ASID_MASK);
+        let max = (read_satp() & SATP_ASID_MASK) >> SATP_ASID_SHIFT;
+        write_satp(old);
+        max
+    }
+}
+
+fn read_satp() -> usize {
+    let value: usize;
+    unsafe { asm!("csrr $0, 0x180" : "=r"(value)); }
+    value
+}
+
+unsafe fn write_satp(value: usize) {
+    asm!("csrw 0x180, $0" :: "r"(value) : "memory" : "volatile");
+}
+
+/*
+* @param:
+*   value: the satp of the page table to switch to
+* @brief:
+*   switch page tables on the current hart, the whole TLB is flushed if the page table has no ASID
+*/
+unsafe fn switch_satp(value: usize) {
+    write_satp(value);
+    if value & SATP_ASID_MASK == 0 {
+        sfence_vma_all();
+    }
+}
+
 fn edit_entry_of<T>(page: &Page, f: impl FnOnce(&mut PageTableEntry) -> T) -> T {
     let p2_flags = unsafe { (*ROOT_PAGE_TABLE)[page.p2_index()].flags_mut() };
     p2_flags.insert(EF::READABLE | EF::WRITABLE);
@@ -145,6 +406,78 @@ fn edit_entry_of<T>(page: &Page, f: impl FnOnce(&mut PageTableEntry) -> T) -> T
     ret
 }
 
+/*
+* @param:
+*   page: the page in the p1 table
+*   f: the function to apply on the p1 table
+* @brief:
+*   access the p1 table of 'page' through the recursive mapping, like edit_entry_of
+* @retval:
+*   the return value of f
+*/
+fn with_p1_of<T>(page: &Page, f: impl FnOnce(&mut RvPageTable) -> T) -> T {
+    let p2_flags = unsafe { (*ROOT_PAGE_TABLE)[page.p2_index()].flags_mut() };
+    p2_flags.insert(EF::READABLE | EF::WRITABLE);
+    let p1_addr = (RECURSIVE_INDEX << 22) | (page.p2_index() << 12);
+    let ret = f(unsafe { &mut *(p1_addr as *mut RvPageTable) });
+    p2_flags.remove(EF::READABLE | EF::WRITABLE);
+    ret
+}
+
+/*
+* @param:
+*   index: the index of the p2 entry
+* @brief:
+*   check whether the p2 entry is shared by all page tables, see InactivePageTable0::map_kernel.
+*   the p1 tables under it are never freed.
+* @retval:
+*   whether the p2 entry is shared with the kernel
+*/
+fn is_kernel_p2_index(index: usize) -> bool {
+    let heap_index = KERNEL_HEAP_OFFSET >> 22;
+    index == 0x40
+        || (index >= KERNEL_P2_INDEX && index < KERNEL_P2_INDEX + 3)
+        || (index >= heap_index && index < heap_index + KERNEL_HEAP_P1_NUM)
+        || index == RECURSIVE_INDEX || index == RECURSIVE_INDEX + 1
+}
+
+/*
+* @param:
+*   page: the page just unmapped
+* @brief:
+*   free the p1 table of 'page' if it is empty
+*/
+fn free_empty_p1(page: &Page) {
+    if is_kernel_p2_index(page.p2_index()) {
+        return;
+    }
+    if !with_p1_of(page, |p1| (0..1024).all(|i| p1[i].is_unused())) {
+        return;
+    }
+    let p2 = unsafe { &mut *ROOT_PAGE_TABLE };
+    let frame = p2[page.p2_index()].addr().as_u32() as usize;
+    p2[page.p2_index()].set_unused();
+    // flush the cached walk of 'page', and the recursive mapping of the p1 table
+    local_flush_tlb(page.start_address().as_usize());
+    local_flush_tlb((RECURSIVE_INDEX << 22) | (page.p2_index() << 12));
+    dealloc_frame(frame);
+}
+
+/*
+* @brief:
+*   free all p1 tables under the p2 entries not shared with the kernel, of the page table being edited
+*/
+fn free_user_p1_tables() {
+    let p2 = unsafe { &mut *ROOT_PAGE_TABLE };
+    // the frames of huge pages are freed by their handlers
+    for i in (0..1024).filter(|&i| !is_kernel_p2_index(i)) {
+        if !p2[i].is_unused() && !is_leaf(&p2[i]) {
+            dealloc_frame(p2[i].addr().as_u32() as usize);
+            p2[i].set_unused();
+        }
+    }
+}
+
 // define the ROOT_PAGE_TABLE, and the virtual address of it?
 const ROOT_PAGE_TABLE: *mut RvPageTable =
     (((RECURSIVE_INDEX << 10) | (RECURSIVE_INDEX + 1)) << 12) as *mut RvPageTable;
@@ -173,8 +506,9 @@ impl ActivePageTable {
         // Call f
         let table = unsafe { &mut *(page.start_address().as_usize() as *mut _) };
         f(self, table);
-        // Unmap the page
-        self.unmap(0xcafebabe);
+        // Unmap the page, but keep its p1 table, since it is mapped again soon
+        let (_, flush) = self.0.unmap(page).unwrap();
+        flush.flush();
     }
 
     pub fn token() -> usize {
@@ -184,8 +518,12 @@ impl ActivePageTable {
 /// implementation for the Entry trait in /crate/memory/src/paging/mod.rs
 impl Entry for PageEntry {
     fn update(&mut self) {
-        edit_entry_of(&self.1, |entry| *entry = self.0);
-        sfence_vma(0, self.1.start_address());
+        match self.2 {
+            true => unsafe { (*ROOT_PAGE_TABLE)[self.1.p2_index()] = self.0; },
+            false => edit_entry_of(&self.1, |entry| *entry = self.0),
+        }
+        local_flush_tlb(self.1.start_address().as_usize());
+        invalidate_others(&self.1);
     }
     fn accessed(&self) -> bool { self.0.flags().contains(EF::ACCESSED) }
     fn dirty(&self) -> bool { self.0.flags().contains(EF::DIRTY) }
@@ -203,8 +541,8 @@ impl Entry for PageEntry {
     }
 
     fn writable_shared(&self) -> bool { self.0.flags().contains(EF::RESERVED2) }
-    fn readonly_shared(&self) -> bool {unimplemented!()}
-    //fn readonly_shared(&self) -> bool { self.0.flags().contains(EF::RESERVED2) }
+    // there is no bit left for readonly shared pages, so they are not told from private ones
+    fn readonly_shared(&self) -> bool { false }
     fn set_shared(&mut self, writable: bool) {
         let flags = self.0.flags_mut();
         // to set the real write bit in RESERVED2
@@ -219,6 +557,7 @@ impl Entry for PageEntry {
     fn set_user(&mut self, value: bool) { self.0.flags_mut().set(EF::USER, value); }
     fn execute(&self) -> bool { self.0.flags().contains(EF::EXECUTABLE) }
     fn set_execute(&mut self, value: bool) { self.0.flags_mut().set(EF::EXECUTABLE, value); }
+    fn huge(&self) -> bool { self.2 }
     //fn mmio(&self) -> bool { false }
     //fn set_mmio(&mut self, value: bool) { }
 }
@@ -226,6 +565,8 @@ impl Entry for PageEntry {
 #[derive(Debug)]
 pub struct InactivePageTable0 {
     p2_frame: Frame,
+    /// the ASID with its generation, see `tlb::switch_asid`
+    asid: AtomicUsize,
 }
 
 impl InactivePageTable for InactivePageTable0 {
@@ -255,8 +596,10 @@ impl InactivePageTable for InactivePageTable0 {
         active_table().with_temporary_map(&frame, |_, table: &mut RvPageTable| {
             table.zero();
             table.set_recursive(RECURSIVE_INDEX, frame.clone());
+            // the kernel heap must be accessible even in a bare page table
+            map_kernel_heap_area(table);
         });
-        InactivePageTable0 { p2_frame: frame }
+        InactivePageTable0 { p2_frame: frame, asid: AtomicUsize::new(0) }
     }
 
     /*
@@ -266,19 +609,22 @@ impl InactivePageTable for InactivePageTable0 {
     *   temporarily map the inactive pagetable as an active p2page and apply f on the temporary modified active page table
     */
     fn edit(&mut self, f: impl FnOnce(&mut Self::Active)) {
-        active_table().with_temporary_map(&satp::read().frame(), |active_table, p2_table: &mut RvPageTable| {
-            let backup = p2_table[RECURSIVE_INDEX].clone();
-
-            // overwrite recursive mapping
-            p2_table[RECURSIVE_INDEX].set(self.p2_frame.clone(), EF::VALID);
-            sfence_vma_all();
-
-            // execute f in the new context
-            f(active_table);
-
-            // restore recursive mapping to original p2 table
-            p2_table[RECURSIVE_INDEX] = backup;
-            sfence_vma_all();
+        // the entries changed in f belong to this table, flush them on the harts running it
+        ::tlb::editing(self.token(), &self.asid, || {
+            active_table().with_temporary_map(&satp::read().frame(), |active_table, p2_table: &mut RvPageTable| {
+                let backup = p2_table[RECURSIVE_INDEX].clone();
+
+                // overwrite recursive mapping
+                p2_table[RECURSIVE_INDEX].set(self.p2_frame.clone(), EF::VALID);
+                sfence_vma_all();
+
+                // execute f in the new context
+                f(active_table);
+
+                // restore recursive mapping to original p2 table
+                p2_table[RECURSIVE_INDEX] = backup;
+                sfence_vma_all();
+            });
         });
     }
 
@@ -290,10 +636,7 @@ impl InactivePageTable for InactivePageTable0 {
         let old_frame = satp::read().frame();
         let new_frame = self.p2_frame.clone();
         debug!("switch table {:x?} -> {:x?}", old_frame, new_frame);
-        if old_frame != new_frame {
-            satp::set(satp::Mode::Sv32, 0, new_frame);
-            sfence_vma_all();
-        }
+        switch_satp(self.switch_token());
     }
 
     /*
@@ -305,19 +648,18 @@ impl InactivePageTable for InactivePageTable0 {
     *   the return value of f
     */
     unsafe fn with<T>(&self, f: impl FnOnce() -> T) -> T {
-        let old_frame = satp::read().frame();
+        let old_satp = read_satp();
         let new_frame = self.p2_frame.clone();
-        debug!("switch table {:x?} -> {:x?}", old_frame, new_frame);
-        if old_frame != new_frame {
-            satp::set(satp::Mode::Sv32, 0, new_frame);
-            sfence_vma_all();
-        }
+        debug!("switch table {:#x?} -> {:x?}", old_satp, new_frame);
+        switch_satp(self.switch_token());
         let target = f();
-        debug!("switch table {:x?} -> {:x?}", new_frame, old_frame);
-        if old_frame != new_frame {
-            satp::set(satp::Mode::Sv32, 0, old_frame);
-            sfence_vma_all();
+        debug!("switch table {:x?} -> {:#x?}", new_frame, old_satp);
+        // switch back to the ASID of the old table, with its entries kept if they are not stale
+        let asid = (old_satp & SATP_ASID_MASK) >> SATP_ASID_SHIFT;
+        if ::tlb::resume_asid(old_satp & !SATP_ASID_MASK, asid) && asid != 0 {
+            local_flush_asid(asid);
         }
+        switch_satp(old_satp);
         target
     }
 
@@ -341,6 +683,22 @@ impl InactivePageTable for InactivePageTable0 {
 }
 
 impl InactivePageTable0 {
+    /*
+    * @brief:
+    *   get the satp to switch to self on the current hart, with its ASID if supported.
+    *   the entries of the ASID in the TLB are kept, unless they may be stale.
+    *   self is recorded as running on the current hart, see `tlb::switch_asid`
+    * @retval:
+    *   the satp of self
+    */
+    pub fn switch_token(&self) -> usize {
+        let (asid, flush) = ::tlb::switch_asid(&self.asid, self.token());
+        if flush && asid != 0 {
+            local_flush_asid(asid);
+        }
+        self.token() | (asid << SATP_ASID_SHIFT)
+    }
+
     /*
     * @brief:
     *   map the kernel (code and heap) memory address (p2 page table) in the new inactive page table according the current active page table
@@ -371,6 +729,8 @@ impl InactivePageTable0 {
 impl Drop for InactivePageTable0 {
     fn drop(&mut self) {
         info!("PageTable dropping: {:?}", self);
+        // the p1 tables left after all areas are unmapped, e.g. the one of the temporary page
+        self.edit(|_| free_user_p1_tables());
         Self::dealloc_frame(self.p2_frame.start_address().as_u32() as usize);
     }
 }
@@ -22,11 +22,14 @@ pub const KERNEL_SIZE: usize = PML4_SIZE;
 /// Offset to kernel heap
 pub const KERNEL_HEAP_OFFSET: usize = KERNEL_OFFSET - PML4_SIZE;
 pub const KERNEL_HEAP_PML4: usize = (KERNEL_HEAP_OFFSET & PML4_MASK) / PML4_SIZE;
-/// Size of kernel heap
-pub const KERNEL_HEAP_SIZE: usize = 8 * 1024 * 1024; // 8 MB
+/// Size of kernel heap area, where pages for the heap are mapped
+pub const KERNEL_HEAP_AREA_SIZE: usize = 64 * 1024 * 1024; // 64 MB
 
 pub const MEMORY_OFFSET: usize = 0;
 
+/// Size of a huge page, mapped by a P2 entry
+pub const HUGE_PAGE_SIZE: usize = 1 << 21;
+
 /// Offset to kernel percpu variables
 //TODO: Use 64-bit fs offset to enable this pub const KERNEL_PERCPU_OFFSET: usize = KERNEL_HEAP_OFFSET - PML4_SIZE;
 pub const KERNEL_PERCPU_OFFSET: usize = 0xC000_0000;
@@ -40,6 +43,11 @@ pub const USER_PML4: usize = (USER_OFFSET & PML4_MASK) / PML4_SIZE;
 /// Offset to user TCB
 pub const USER_TCB_OFFSET: usize = 0xB000_0000;
 
+/// Offset and end of the area searched for mmap without a fixed address,
+/// below the user TCB and 2G since mmap returns the address in i32
+pub const USER_MMAP_OFFSET: usize = 0x4000_0000;
+pub const USER_MMAP_END: usize = 0x8000_0000;
+
 /// Offset to user arguments
 pub const USER_ARG_OFFSET: usize = USER_OFFSET + PML4_SIZE / 2;
 
@@ -1,5 +1,6 @@
 use super::apic::{LocalApic, XApic};
 use super::raw_cpuid::CpuId;
+use super::interrupt::consts::{T_IRQ0, IRQ_IPI};
 
 /// Exit qemu
 /// See: https://wiki.osdev.org/Shutdown
@@ -18,12 +19,13 @@ pub fn id() -> usize {
 
 pub fn send_ipi(cpu_id: usize) {
     let mut lapic = unsafe { XApic::new(0xffffff00_fee00000) };
-    unsafe { lapic.send_ipi(cpu_id as u8, 0x30); } // TODO: Find a IPI trap num
+    unsafe { lapic.send_ipi(cpu_id as u8, T_IRQ0 + IRQ_IPI); }
 }
 
 pub fn init() {
     let mut lapic = unsafe { XApic::new(0xffffff00_fee00000) };
     lapic.cpu_init();
+    super::paging::enable_pcid();
 }
 
 pub fn halt() {
@@ -15,15 +15,26 @@ pub struct IDE {
 
 impl IDE {
     pub fn new(num: u8) -> Self {
-        let ide = match num {
+        let ide = Self::at(num);
+        ide.init();
+        ide
+    }
+
+    /// Probe the drive, return it and its number of sectors if present
+    pub fn probe(num: u8) -> Option<(Self, usize)> {
+        let ide = Self::at(num);
+        let sectors = ide.init()?;
+        Some((ide, sectors))
+    }
+
+    fn at(num: u8) -> Self {
+        match num {
             0 => IDE { num: 0, base: 0x1f0, ctrl: 0x3f4 },
             1 => IDE { num: 1, base: 0x1f0, ctrl: 0x3f4 },
             2 => IDE { num: 2, base: 0x170, ctrl: 0x374 },
             3 => IDE { num: 3, base: 0x170, ctrl: 0x374 },
             _ => panic!("ide number should be 0,1,2,3"),
-        };
-        ide.init();
-        ide
+        }
     }
 
     /// Read ATA DMA. Block size = 512 bytes.
@@ -71,7 +82,8 @@ impl IDE {
         status & (IDE_DF | IDE_ERR) != 0
     }
 
-    fn init(&self) {
+    /// Identify the drive, return its number of sectors (LBA28), or None if absent
+    fn init(&self) -> Option<usize> {
         self.wait();
         unsafe {
             // step1: select drive
@@ -84,12 +96,14 @@ impl IDE {
 
             // step3: polling
             if port::inb(self.base + ISA_STATUS) == 0 || self.wait_error() {
-                return;
+                return None;
             }
 
-            // ???
-            let mut data = [0; SECTOR_SIZE];
+            // read the identify data
+            let mut data = [0u32; SECTOR_SIZE];
             asm!("rep insl" :: "{dx}"(self.base + ISA_DATA), "{rdi}"(data.as_ptr()), "{cx}"(SECTOR_SIZE) : "rdi" : "volatile");
+            // word 60-61: total number of user addressable sectors
+            Some(data[30] as usize)
         }
     }
 
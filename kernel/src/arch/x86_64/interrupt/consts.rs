@@ -24,6 +24,7 @@ pub const IRQ_KBD      : u8 =  1;
 pub const IRQ_COM2     : u8 =  3;
 pub const IRQ_COM1     : u8 =  4;
 pub const IRQ_IDE      : u8 = 14;
+pub const IRQ_IPI      : u8 = 16;       // inter-processor interrupt, see `cpu::send_ipi`
 pub const IRQ_ERROR    : u8 = 19;
 pub const IRQ_SPURIOUS : u8 = 31;
 pub const T_SYSCALL: u8 = 0x40;
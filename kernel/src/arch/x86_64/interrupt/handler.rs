@@ -87,6 +87,7 @@ pub extern fn rust_trap(tf: &mut TrapFrame) {
                 IRQ_COM1 => com1(),
                 IRQ_COM2 => com2(),
                 IRQ_IDE => ide(),
+                IRQ_IPI => ::trap::ipi(),
                 _ => panic!("Invalid IRQ number: {}", irq),
             }
         }
@@ -109,10 +110,23 @@ fn double_fault(tf: &TrapFrame) {
 }
 
 fn page_fault(tf: &mut TrapFrame) {
+    use memory::{PageFault, Access};
     let addr: usize;
     unsafe { asm!("mov %cr2, $0" : "=r" (addr)); }
 
-    if ::memory::page_fault_handler(addr) {
+    // bits of the error code: P(0) W/R(1) U/S(2) RSVD(3) I/D(4)
+    let code = tf.error_code;
+    let fault = PageFault {
+        addr,
+        access: match (code & 0x2 != 0, code & 0x10 != 0) {
+            (true, _) => Access::Write,
+            (false, true) => Access::Execute,
+            (false, false) => Access::Read,
+        },
+        user: code & 0x4 != 0,
+        present: code & 0x1 != 0,
+    };
+    if ::memory::page_fault_handler(fault) {
         return;
     }
     error!("\nEXCEPTION: Page Fault @ {:#x}, code: {:#x}", addr, tf.error_code);
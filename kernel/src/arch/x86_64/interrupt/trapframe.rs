@@ -188,4 +188,9 @@ impl Context {
     pub unsafe fn get_init_tf(&self) -> TrapFrame {
         (*(self.0 as *const InitStack)).tf.clone()
     }
+    /// Set the CR3 to load when switching to this context, on the current CPU
+    /// It should be called right before switching, see `InactivePageTable0::switch_token`
+    pub unsafe fn set_page_table(&mut self, cr3: usize) {
+        (*(self.0 as *mut ContextData)).cr3 = cr3;
+    }
 }
\ No newline at end of file
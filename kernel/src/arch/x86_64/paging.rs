@@ -1,4 +1,5 @@
 use bit_allocator::{BitAlloc, BitAlloc64K};
+use consts::{KERNEL_HEAP_OFFSET, KERNEL_HEAP_PML4, KERNEL_HEAP_AREA_SIZE, HUGE_PAGE_SIZE};
 // Depends on kernel
 use memory::{active_table, alloc_frame, dealloc_frame};
 use spin::{Mutex, MutexGuard};
@@ -8,10 +9,11 @@ use ucore_memory::PAGE_SIZE;
 use ucore_memory::paging::*;
 use x86_64::instructions::tlb;
 use x86_64::PhysAddr;
-use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::{Mapper, PageTable as x86PageTable, PageTableEntry, PageTableFlags as EF, RecursivePageTable};
-use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageRange, PhysFrame as Frame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageRange, PhysFrame as Frame, Size4KiB, Size2MiB};
 use x86_64::ux::u9;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT};
 
 pub trait PageExt {
     fn of_addr(address: usize) -> Self;
@@ -38,6 +40,77 @@ impl FrameExt for Frame {
     }
 }
 
+#[repr(align(4096))]
+#[derive(Copy, Clone)]
+struct PageData([u8; PAGE_SIZE]);
+
+// Each P1 table covers 2M of the kernel heap area
+const KERNEL_HEAP_P1_NUM: usize = KERNEL_HEAP_AREA_SIZE >> 21;
+
+// P3, P2 and P1 tables of the kernel heap area, shared by all page tables,
+// so a page mapped here is visible in every address space at once.
+static mut KERNEL_HEAP_TABLES: [PageData; KERNEL_HEAP_P1_NUM + 2] = [PageData([0; PAGE_SIZE]); KERNEL_HEAP_P1_NUM + 2];
+
+fn kernel_heap_table(i: usize) -> &'static mut x86PageTable {
+    unsafe { &mut *(&mut KERNEL_HEAP_TABLES[i] as *mut _ as *mut x86PageTable) }
+}
+
+/// Link the tables of the kernel heap area into the current page table.
+/// Other page tables get them by `InactivePageTable0::map_kernel`.
+pub fn init_kernel_heap_area() {
+    let mut active_table = active_table();
+    let mut phys_addr = |i: usize| {
+        let addr = kernel_heap_table(i) as *const _ as usize;
+        active_table.0.translate_page(Page::of_addr(addr)).unwrap().start_address()
+    };
+    let flags = EF::PRESENT | EF::WRITABLE;
+    let p4 = unsafe { &mut *(0xffffffff_fffff000 as *mut x86PageTable) };
+    assert!(p4[KERNEL_HEAP_PML4].is_unused(), "kernel heap area is already used");
+    p4[KERNEL_HEAP_PML4].set_addr(phys_addr(0), flags);
+    kernel_heap_table(0)[(KERNEL_HEAP_OFFSET >> 30) & 0o777].set_addr(phys_addr(1), flags);
+    for i in 0..KERNEL_HEAP_P1_NUM {
+        kernel_heap_table(1)[((KERNEL_HEAP_OFFSET >> 21) & 0o777) + i].set_addr(phys_addr(2 + i), flags);
+    }
+    tlb::flush_all();
+}
+
+fn kernel_heap_entry(addr: usize) -> &'static mut PageTableEntry {
+    assert!(addr >= KERNEL_HEAP_OFFSET && addr < KERNEL_HEAP_OFFSET + KERNEL_HEAP_AREA_SIZE,
+            "{:#x} is not in the kernel heap area", addr);
+    let page = (addr - KERNEL_HEAP_OFFSET) / PAGE_SIZE;
+    &mut kernel_heap_table(2 + page / 512)[page % 512]
+}
+
+/// Map a page of the kernel heap area to frame `target`.
+///
+/// It doesn't need the lock of the active table, so it can be used by the heap allocator.
+pub unsafe fn map_kernel_heap_page(addr: usize, target: usize) {
+    let entry = kernel_heap_entry(addr);
+    assert!(entry.is_unused(), "kernel heap page {:#x} is already mapped", addr);
+    entry.set_addr(PhysAddr::new(target as u64), EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE | EF::GLOBAL);
+    tlb::flush(::x86_64::VirtAddr::new(addr as u64));
+}
+
+/// Unmap a page of the kernel heap area, return the frame
+pub unsafe fn unmap_kernel_heap_page(addr: usize) -> usize {
+    let entry = kernel_heap_entry(addr);
+    assert!(!entry.is_unused(), "kernel heap page {:#x} is not mapped", addr);
+    let target = entry.addr().as_u64() as usize;
+    entry.set_unused();
+    tlb::flush(::x86_64::VirtAddr::new(addr as u64));
+    // the kernel heap area is shared by all page tables
+    ::tlb::invalidate(addr, true);
+    target
+}
+
+/// Flush TLB of other CPUs, after unmapping pages of the kernel heap area shared with them
+///
+/// The pages are recorded by `unmap_kernel_heap_page`, and shot down by IPI, see `tlb`.
+/// It waits for the other CPUs, so it should be called without holding locks but SpinNoIrqLocks.
+pub fn flush_kernel_heap_tlb_others() {
+    ::tlb::flush_others();
+}
+
 pub struct ActivePageTable(RecursivePageTable<'static>);
 
 pub struct PageEntry(PageTableEntry);
@@ -55,16 +128,74 @@ impl PageTable for ActivePageTable {
     fn unmap(&mut self, addr: usize) {
         let (frame, flush) = self.0.unmap(Page::of_addr(addr)).unwrap();
         flush.flush();
+        invalidate_others(addr);
+        free_empty_tables(addr, 1);
     }
 
     fn get_entry(&mut self, addr: usize) -> Option<&mut PageEntry> {
         for level in 0..3 {
             let entry = get_entry_ptr(addr, 4 - level);
             if unsafe { !(*entry).present() } { return None; }
+            // a huge page is mapped by the P2 entry
+            if level == 2 && unsafe { (*entry).huge() } {
+                return unsafe { Some(&mut *entry) };
+            }
         }
         unsafe { Some(&mut *(get_entry_ptr(addr, 1))) }
     }
 
+    fn huge_page_size(&self) -> usize {
+        HUGE_PAGE_SIZE
+    }
+
+    /// Map a 2M huge page, None if the P2 entry is in use, e.g. by a P1 table
+    fn map_huge(&mut self, addr: usize, target: usize) -> Option<&mut PageEntry> {
+        let flags = EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE | EF::HUGE_PAGE;
+        let page = Page::<Size2MiB>::containing_address(::x86_64::VirtAddr::new(addr as u64));
+        let frame = Frame::<Size2MiB>::containing_address(PhysAddr::new(target as u64));
+        self.0.map_to(page, frame, flags, &mut FrameAllocatorForX86)
+            .ok()?.flush();
+        unsafe { Some(&mut *(get_entry_ptr(addr, 2))) }
+    }
+
+    fn unmap_huge(&mut self, addr: usize) {
+        let page = Page::<Size2MiB>::containing_address(::x86_64::VirtAddr::new(addr as u64));
+        let (frame, flush) = self.0.unmap(page).unwrap();
+        flush.flush();
+        invalidate_others(addr);
+        free_empty_tables(addr, 2);
+    }
+
+    /// Find the first mapped page containing or after `addr`, skipping the recursive mapping,
+    /// return the start address of the page or the huge page
+    fn next_mapped(&mut self, addr: usize) -> Option<usize> {
+        let index = |level: usize| (addr >> (12 + 9 * level)) & 0o777;
+        // the tables are searched from the indexes of `addr` while on its path, from 0 after it
+        let from = |on_path: bool, level: usize| if on_path { index(level) } else { 0 };
+        let p4 = recursive_table(0o777, 0o777, 0o777, 0o777);
+        // the last P4 entry is the recursive mapping of the tables
+        for i in (index(3)..511).filter(|&i| !p4[i].is_unused()) {
+            let on_path = i == index(3);
+            let p3 = recursive_table(0o777, 0o777, 0o777, i);
+            // 1G pages are only mapped by the bootloader for the kernel, if any
+            for j in (from(on_path, 2)..512).filter(|&j| !p3[j].is_unused() && !p3[j].flags().contains(EF::HUGE_PAGE)) {
+                let on_path = on_path && j == index(2);
+                let p2 = recursive_table(0o777, 0o777, i, j);
+                for k in (from(on_path, 1)..512).filter(|&k| !p2[k].is_unused()) {
+                    let on_path = on_path && k == index(1);
+                    if p2[k].flags().contains(EF::HUGE_PAGE) {
+                        return Some(page_addr(i, j, k, 0));
+                    }
+                    let p1 = recursive_table(0o777, i, j, k);
+                    if let Some(l) = (from(on_path, 0)..512).find(|&l| !p1[l].is_unused()) {
+                        return Some(page_addr(i, j, k, l));
+                    }
+                }
+            }
+        }
+        None
+    }
+
     fn get_page_slice_mut<'a, 'b>(&'a mut self, addr: usize) -> &'b mut [u8] {
         use core::slice;
         unsafe { slice::from_raw_parts_mut((addr & !0xfffusize) as *mut u8, PAGE_SIZE) }
@@ -92,19 +223,165 @@ impl ActivePageTable {
         // Call f
         let table = unsafe { &mut *page.start_address().as_mut_ptr() };
         f(self, table);
-        // Unmap the page
-        self.unmap(0xcafebabe);
+        // Unmap the page, but keep its tables, since it is mapped again soon
+        let (_, flush) = self.0.unmap(page).unwrap();
+        flush.flush();
     }
     pub fn token() -> usize {
         Cr3::read().0.start_address().as_u64() as usize
     }
 }
 
+// Kernel stack at 0x0000_57ac_0000_0000 (defined in bootloader crate)
+const KERNEL_STACK_PML4: usize = 175;
+
+/// Whether the P4 entry is shared by all page tables, see `InactivePageTable0::map_kernel`.
+/// The tables under it are never freed.
+fn is_kernel_pml4(index: usize) -> bool {
+    // the kernel heap area, the kernel and the recursive mapping are at the top
+    index == KERNEL_STACK_PML4 || index >= KERNEL_HEAP_PML4
+}
+
+/// Get a table through the recursive mapping, by the indexes of the page mapping it
+fn recursive_table(p4: usize, p3: usize, p2: usize, p1: usize) -> &'static mut x86PageTable {
+    let addr = 0xffff_0000_0000_0000 | (p4 << 39) | (p3 << 30) | (p2 << 21) | (p1 << 12);
+    unsafe { &mut *(addr as *mut x86PageTable) }
+}
+
+/// Get the address of a page by its indexes, sign extended
+fn page_addr(p4: usize, p3: usize, p2: usize, p1: usize) -> usize {
+    let addr = (p4 << 39) | (p3 << 30) | (p2 << 21) | (p1 << 12);
+    match p4 < 256 {
+        true => addr,
+        false => addr | 0xffff_0000_0000_0000,
+    }
+}
+
+/// Flush the entry of `addr` from the TLB of other CPUs later, see `tlb`
+fn invalidate_others(addr: usize) {
+    ::tlb::invalidate(addr, is_kernel_pml4((addr >> 39) & 0o777));
+}
+
+/// Flush the entry of `addr` from the TLB of the current CPU
+pub fn local_flush_tlb(addr: usize) {
+    tlb::flush(::x86_64::VirtAddr::new_unchecked(addr as u64));
+}
+
+/// Flush the whole TLB of the current CPU, except global entries
+/// With PCID, only the entries of the current PCID are flushed
+pub fn local_flush_tlb_all() {
+    // `tlb::flush_all` would clear the PCID in CR3
+    unsafe { write_cr3(read_cr3()); }
+}
+
+/// Flush the whole TLB of the current CPU, for all PCIDs and global entries
+pub fn local_flush_tlb_all_asids() {
+    // changing CR4.PGE flushes all the entries
+    unsafe {
+        let cr4 = read_cr4();
+        write_cr4(cr4 ^ CR4_PGE);
+        write_cr4(cr4);
+    }
+}
+
+// PCID is enabled on all the CPUs if supported, see `enable_pcid`
+static PCID: AtomicBool = ATOMIC_BOOL_INIT;
+
+const CR4_PGE: usize = 1 << 7;
+const CR4_PCIDE: usize = 1 << 17;
+// keep the entries of the PCID when writing CR3
+const CR3_NOFLUSH: usize = 1 << 63;
+const MAX_PCID: usize = 0xfff;
+
+/// Enable PCID on the current CPU if supported, called by each CPU before switching page tables
+pub fn enable_pcid() {
+    use super::raw_cpuid::CpuId;
+    if !CpuId::new().get_feature_info().map_or(false, |info| info.has_pcid()) {
+        return;
+    }
+    // CR3 is the boot page table with PCID 0 now, as CR4.PCIDE requires
+    unsafe { write_cr4(read_cr4() | CR4_PCIDE); }
+    PCID.store(true, Ordering::SeqCst);
+}
+
+/// The largest PCID, 0 if PCID is not supported
+pub fn max_asid() -> usize {
+    match PCID.load(Ordering::SeqCst) {
+        true => MAX_PCID,
+        false => 0,
+    }
+}
+
+fn read_cr3() -> usize {
+    let value: usize;
+    unsafe { asm!("mov %cr3, $0" : "=r"(value)); }
+    value
+}
+
+unsafe fn write_cr3(value: usize) {
+    asm!("mov $0, %cr3" :: "r"(value) : "memory");
+}
+
+fn read_cr4() -> usize {
+    let value: usize;
+    unsafe { asm!("mov %cr4, $0" : "=r"(value)); }
+    value
+}
+
+unsafe fn write_cr4(value: usize) {
+    asm!("mov $0, %cr4" :: "r"(value) : "memory");
+}
+
+/// Free the tables of `addr` from `level` up to P3, if they are empty after unmapping it
+fn free_empty_tables(addr: usize, level: u8) {
+    if is_kernel_pml4((addr >> 39) & 0o777) {
+        return;
+    }
+    for level in level..4 {
+        // the table containing the entry of `addr` at this level
+        let table = unsafe { &*((get_entry_ptr(addr, level) as usize & !0xfff) as *const x86PageTable) };
+        if (0..512).any(|i| !table[i].is_unused()) {
+            return;
+        }
+        let parent = unsafe { &mut *(get_entry_ptr(addr, level + 1) as *mut PageTableEntry) };
+        let frame = parent.addr().as_u64() as usize;
+        parent.set_unused();
+        // flush the cached walk of `addr`, and the recursive mapping of the table
+        tlb::flush(::x86_64::VirtAddr::new(addr as u64));
+        tlb::flush(::x86_64::VirtAddr::new(table as *const _ as u64));
+        dealloc_frame(frame);
+    }
+}
+
+/// Free all tables under the P4 entries not shared with the kernel, of the table being edited
+fn free_user_tables() {
+    let p4 = recursive_table(0o777, 0o777, 0o777, 0o777);
+    for i in (0..512).filter(|&i| !is_kernel_pml4(i)) {
+        if p4[i].is_unused() {
+            continue;
+        }
+        let p3 = recursive_table(0o777, 0o777, 0o777, i);
+        for j in (0..512).filter(|&j| !p3[j].is_unused()) {
+            let p2 = recursive_table(0o777, 0o777, i, j);
+            // the frames of huge pages are freed by their handlers
+            for k in (0..512).filter(|&k| !p2[k].is_unused() && !p2[k].flags().contains(EF::HUGE_PAGE)) {
+                dealloc_frame(p2[k].addr().as_u64() as usize);
+            }
+            dealloc_frame(p3[j].addr().as_u64() as usize);
+        }
+        dealloc_frame(p4[i].addr().as_u64() as usize);
+        p4[i].set_unused();
+    }
+}
+
 impl Entry for PageEntry {
     fn update(&mut self) {
         use x86_64::{VirtAddr, instructions::tlb::flush};
-        let addr = VirtAddr::new_unchecked((self as *const _ as u64) << 9);
+        // the entry of a huge page is in the P2 table
+        let shift = if self.huge() { 18 } else { 9 };
+        let addr = VirtAddr::new_unchecked((self as *const _ as u64) << shift);
         flush(addr);
+        invalidate_others(addr.as_u64() as usize);
     }
     fn accessed(&self) -> bool { self.0.flags().contains(EF::ACCESSED) }
     fn dirty(&self) -> bool { self.0.flags().contains(EF::DIRTY) }
@@ -134,7 +411,9 @@ impl Entry for PageEntry {
         self.as_flags().set(EF::USER_ACCESSIBLE, value);
         if value {
             let mut addr = self as *const _ as usize;
-            for _ in 0..3 {
+            // the entry of a huge page has one less upper level
+            let levels = if self.huge() { 2 } else { 3 };
+            for _ in 0..levels {
                 // Upper level entry
                 addr = ((addr >> 9) & 0o777_777_777_7770) | 0xffffff80_00000000;
                 // set USER_ACCESSIBLE
@@ -144,6 +423,7 @@ impl Entry for PageEntry {
     }
     fn execute(&self) -> bool { !self.0.flags().contains(EF::NO_EXECUTE) }
     fn set_execute(&mut self, value: bool) { self.as_flags().set(EF::NO_EXECUTE, !value); }
+    fn huge(&self) -> bool { self.0.flags().contains(EF::HUGE_PAGE) }
 }
 
 fn get_entry_ptr(addr: usize, level: u8) -> *mut PageEntry {
@@ -161,6 +441,8 @@ impl PageEntry {
 #[derive(Debug)]
 pub struct InactivePageTable0 {
     p4_frame: Frame,
+    // the PCID with its generation, see `tlb::switch_asid`
+    asid: AtomicUsize,
 }
 
 impl InactivePageTable for InactivePageTable0 {
@@ -180,23 +462,26 @@ impl InactivePageTable for InactivePageTable0 {
             // set up recursive mapping for the table
             table[511].set_frame(frame.clone(), EF::PRESENT | EF::WRITABLE);
         });
-        InactivePageTable0 { p4_frame: frame }
+        InactivePageTable0 { p4_frame: frame, asid: AtomicUsize::new(0) }
     }
 
     fn edit(&mut self, f: impl FnOnce(&mut Self::Active)) {
-        active_table().with_temporary_map(&Cr3::read().0, |active_table, p4_table: &mut x86PageTable| {
-            let backup = p4_table[0o777].clone();
-
-            // overwrite recursive mapping
-            p4_table[0o777].set_frame(self.p4_frame.clone(), EF::PRESENT | EF::WRITABLE);
-            tlb::flush_all();
-
-            // execute f in the new context
-            f(active_table);
-
-            // restore recursive mapping to original p4 table
-            p4_table[0o777] = backup;
-            tlb::flush_all();
+        // the entries changed in f belong to this table, flush them on the CPUs running it
+        ::tlb::editing(self.token(), &self.asid, || {
+            active_table().with_temporary_map(&Cr3::read().0, |active_table, p4_table: &mut x86PageTable| {
+                let backup = p4_table[0o777].clone();
+
+                // overwrite recursive mapping
+                p4_table[0o777].set_frame(self.p4_frame.clone(), EF::PRESENT | EF::WRITABLE);
+                local_flush_tlb_all();
+
+                // execute f in the new context
+                f(active_table);
+
+                // restore recursive mapping to original p4 table
+                p4_table[0o777] = backup;
+                local_flush_tlb_all();
+            });
         });
     }
 
@@ -204,22 +489,21 @@ impl InactivePageTable for InactivePageTable0 {
         let old_frame = Cr3::read().0;
         let new_frame = self.p4_frame.clone();
         debug!("switch table {:?} -> {:?}", old_frame, new_frame);
-        if old_frame != new_frame {
-            Cr3::write(new_frame, Cr3Flags::empty());
-        }
+        write_cr3(self.switch_token());
     }
 
     unsafe fn with<T>(&self, f: impl FnOnce() -> T) -> T {
-        let old_frame = Cr3::read().0;
+        let old_cr3 = read_cr3();
         let new_frame = self.p4_frame.clone();
-        debug!("switch table {:?} -> {:?}", old_frame, new_frame);
-        if old_frame != new_frame {
-            Cr3::write(new_frame, Cr3Flags::empty());
-        }
+        debug!("switch table {:#x?} -> {:?}", old_cr3, new_frame);
+        write_cr3(self.switch_token());
         let ret = f();
-        debug!("switch table {:?} -> {:?}", new_frame, old_frame);
-        if old_frame != new_frame {
-            Cr3::write(old_frame, Cr3Flags::empty());
+        debug!("switch table {:?} -> {:#x?}", new_frame, old_cr3);
+        // switch back to the PCID of the old table, with its entries kept if they are not stale
+        let (token, pcid) = (old_cr3 & !MAX_PCID, old_cr3 & MAX_PCID);
+        match ::tlb::resume_asid(token, pcid) {
+            true => write_cr3(old_cr3),
+            false => write_cr3(old_cr3 | CR3_NOFLUSH),
         }
         ret
     }
@@ -238,15 +522,29 @@ impl InactivePageTable for InactivePageTable0 {
 }
 
 impl InactivePageTable0 {
+    /// The value of CR3 to switch to this table on the current CPU, with its PCID if enabled.
+    /// The entries of the PCID in the TLB are kept, unless they may be stale.
+    /// It records this table as running on the current CPU, see `tlb::switch_asid`.
+    pub fn switch_token(&self) -> usize {
+        match ::tlb::switch_asid(&self.asid, self.token()) {
+            (0, _) => self.token(),
+            (pcid, true) => self.token() | pcid,
+            (pcid, false) => self.token() | pcid | CR3_NOFLUSH,
+        }
+    }
+
     fn map_kernel(&mut self) {
         let mut table = unsafe { &mut *(0xffffffff_fffff000 as *mut x86PageTable) };
         // Kernel at 0xffff_ff00_0000_0000
         // Kernel stack at 0x0000_57ac_0000_0000 (defined in bootloader crate)
+        // Kernel heap area at 0xffff_fe80_0000_0000
         let e510 = table[510].clone();
-        let estack = table[175].clone();
+        let estack = table[KERNEL_STACK_PML4].clone();
+        let eheap = table[KERNEL_HEAP_PML4].clone();
         self.edit(|_| {
             table[510].set_addr(e510.addr(), e510.flags() | EF::GLOBAL);
-            table[175].set_addr(estack.addr(), estack.flags() | EF::GLOBAL);
+            table[KERNEL_STACK_PML4].set_addr(estack.addr(), estack.flags() | EF::GLOBAL);
+            table[KERNEL_HEAP_PML4].set_addr(eheap.addr(), eheap.flags() | EF::GLOBAL);
         });
     }
 }
@@ -254,6 +552,8 @@ impl InactivePageTable0 {
 impl Drop for InactivePageTable0 {
     fn drop(&mut self) {
         info!("PageTable dropping: {:?}", self);
+        // the tables left after all areas are unmapped, e.g. the ones of the temporary page
+        self.edit(|_| free_user_tables());
         Self::dealloc_frame(self.p4_frame.start_address().as_u64() as usize);
     }
 }
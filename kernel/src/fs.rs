@@ -157,4 +157,59 @@ impl INodeExt for INode {
         self.read_at(0, buf.as_mut_slice())?;
         Ok(buf)
     }
-}
\ No newline at end of file
+}
+
+/// An opened file, with the offset and the permission of the descriptor
+///
+/// Unlike `simple_filesystem::file::File`, it gives out its inode, so the file can be mapped by mmap.
+#[derive(Clone)]
+pub struct File {
+    inode: Arc<INode>,
+    offset: usize,
+    readable: bool,
+    writable: bool,
+}
+
+impl File {
+    pub fn new(inode: Arc<INode>, readable: bool, writable: bool) -> Self {
+        File { inode, offset: 0, readable, writable }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.readable {
+            return Err(());
+        }
+        let len = self.inode.read_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.writable {
+            return Err(());
+        }
+        let len = self.inode.write_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    pub fn info(&self) -> Result<FileInfo> {
+        self.inode.info()
+    }
+
+    pub fn get_entry(&self, id: usize) -> Result<String> {
+        self.inode.get_entry(id)
+    }
+
+    pub fn inode(&self) -> Arc<INode> {
+        self.inode.clone()
+    }
+
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+}
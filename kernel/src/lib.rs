@@ -18,16 +18,17 @@
 extern crate alloc;
 extern crate bit_allocator;
 extern crate bit_field;
+extern crate frame_allocator;
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
 extern crate lazy_static;
-extern crate linked_list_allocator;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate once;
 extern crate simple_filesystem;
+extern crate slab_allocator;
 extern crate spin;
 extern crate ucore_memory;
 extern crate ucore_process;
@@ -38,19 +39,23 @@ extern crate xmas_elf;
 
 pub use process::{processor, new_kernel_context};
 use ucore_process::thread;
-use linked_list_allocator::LockedHeap;
+use slab_allocator::SlabAllocator;
 
 #[macro_use]    // print!
 pub mod logging;
 mod memory;
+mod swap;
+mod shm;
 mod lang;
 mod util;
 mod consts;
 mod process;
 mod syscall;
+mod user_ptr;
 mod fs;
 mod sync;
 mod trap;
+mod tlb;
 mod shell;
 
 #[allow(dead_code)]
@@ -63,6 +68,7 @@ pub mod arch;
 pub mod arch;
 
 pub fn kmain() -> ! {
+    tlb::init();
     process::processor().run();
 
 //    thread::test::local_key();
@@ -78,4 +84,4 @@ pub fn kmain() -> ! {
 ///
 /// It should be defined in memory mod, but in Rust `global_allocator` must be in root mod.
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: SlabAllocator<memory::HeapSupport> = SlabAllocator::new();
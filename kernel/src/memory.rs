@@ -1,32 +1,280 @@
 pub use arch::paging::*;
-use bit_allocator::{BitAlloc, BitAlloc4K, BitAlloc64K};
-use consts::MEMORY_OFFSET;
+use bit_allocator::{BitAlloc4K, BitAlloc64K};
+use frame_allocator::*;
+use consts::{MEMORY_OFFSET, MAX_CPU_NUM, KERNEL_HEAP_OFFSET, KERNEL_HEAP_AREA_SIZE};
 use spin;
-use super::HEAP_ALLOCATOR;
-use ucore_memory::{*, paging::PageTable};
+use arch::{cpu, interrupt};
+use slab_allocator::SlabSupport;
+use ucore_memory::{*, paging::{PageTable, MappedEntry, MappedPages}};
 use ucore_memory::cow::CowExt;
-pub use ucore_memory::memory_set::{MemoryArea, MemoryAttr, MemorySet as MemorySet_, InactivePageTable, MemoryHandler};
-use ucore_memory::swap::{fifo, mock_swapper, SwapExt as SwapExt_};
+pub use ucore_memory::memory_set::{MemoryArea, MemoryAttr, MemorySet as MemorySet_, InactivePageTable, MemoryHandler, PageFault, Access};
+use ucore_memory::swap::{Swapper, SwapExt as SwapExt_};
 use process::{process};
 use sync::{SpinNoIrqLock, SpinNoIrq, MutexGuard};
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 use alloc::boxed::Box;
-use core::slice;
+use alloc::collections::BTreeMap;
+use simple_filesystem::INode;
+use shm::ShmSegment;
+use core::{slice, mem};
+use core::mem::ManuallyDrop;
+use core::ops::{Range, Deref, DerefMut};
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub type MemorySet = MemorySet_<InactivePageTable0>;
-pub type SwapExtType = SwapExt_<fifo::FifoSwapManager, mock_swapper::MockSwapper, InactivePageTable0>;
+// Swapper on the swap device if present, see `swap::swapper`
+pub type SwapExtType = SwapExt_<SwapManagerType, Box<Swapper + Send>, InactivePageTable0>;
 
-// x86_64 support up to 256M memory
+// Page replacement algorithm, chosen by feature `swap_manager_*`, default is FIFO
+#[cfg(feature = "swap_manager_enhanced_clock")]
+pub type SwapManagerType = ucore_memory::swap::enhanced_clock::EnhancedClockSwapManager<InactivePageTable0>;
+#[cfg(feature = "swap_manager_second_chance")]
+pub type SwapManagerType = ucore_memory::swap::second_chance::SecondChanceSwapManager<InactivePageTable0>;
+#[cfg(feature = "swap_manager_aging")]
+pub type SwapManagerType = ucore_memory::swap::aging::AgingSwapManager<InactivePageTable0>;
+#[cfg(feature = "swap_manager_working_set")]
+pub type SwapManagerType = ucore_memory::swap::working_set::WorkingSetSwapManager<InactivePageTable0>;
+#[cfg(not(any(feature = "swap_manager_enhanced_clock", feature = "swap_manager_second_chance",
+              feature = "swap_manager_aging", feature = "swap_manager_working_set")))]
+pub type SwapManagerType = ucore_memory::swap::fifo::FifoSwapManager;
+
+// Each zone of the frame allocator manages 256M memory on x86_64
 #[cfg(target_arch = "x86_64")]
-pub type FrameAlloc = BitAlloc64K;
+type FrameBitmap = BitAlloc64K;
 
-// RISCV only have 8M memory
+// Each zone of the frame allocator manages 16M memory on RISCV
 #[cfg(target_arch = "riscv32")]
-pub type FrameAlloc = BitAlloc4K;
+type FrameBitmap = BitAlloc4K;
+
+// Frame allocator algorithm in each zone, chosen by feature `frame_alloc_*`, default is the bitmap
+#[cfg(feature = "frame_alloc_buddy")]
+pub type FrameAlloc = BuddyAlloc<FrameBitmap>;
+#[cfg(feature = "frame_alloc_first_fit")]
+pub type FrameAlloc = FirstFit<FrameBitmap>;
+#[cfg(feature = "frame_alloc_best_fit")]
+pub type FrameAlloc = BestFit<FrameBitmap>;
+#[cfg(feature = "frame_alloc_worst_fit")]
+pub type FrameAlloc = WorstFit<FrameBitmap>;
+#[cfg(not(any(feature = "frame_alloc_buddy", feature = "frame_alloc_first_fit",
+              feature = "frame_alloc_best_fit", feature = "frame_alloc_worst_fit")))]
+pub type FrameAlloc = FrameBitmap;
+
+lazy_static! {
+    // the number of zones is decided by the memory map, see `init_frame_allocator`
+    pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<ZonedAlloc<FrameAlloc>> = SpinNoIrqLock::new(ZonedAlloc::default());
+}
+
+/// The number of frames managed by `FRAME_ALLOCATOR`, excluding reserved ones
+pub static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of times `FRAME_ALLOCATOR` is locked
+pub static FRAME_LOCK_ACQUIRED: AtomicUsize = AtomicUsize::new(0);
+/// The number of times `FRAME_ALLOCATOR` is found locked by others, and we have to spin
+pub static FRAME_LOCK_CONTENDED: AtomicUsize = AtomicUsize::new(0);
+
+/// Lock `FRAME_ALLOCATOR`, counting the contention
+fn lock_frame_allocator() -> MutexGuard<'static, ZonedAlloc<FrameAlloc>, SpinNoIrq> {
+    FRAME_LOCK_ACQUIRED.fetch_add(1, Ordering::Relaxed);
+    FRAME_ALLOCATOR.try_lock().unwrap_or_else(|| {
+        FRAME_LOCK_CONTENDED.fetch_add(1, Ordering::Relaxed);
+        FRAME_ALLOCATOR.lock()
+    })
+}
+
+const FRAME_MAGAZINE_SIZE: usize = 32;
+
+/// A per-CPU cache of free frames
+#[derive(Default)]
+struct FrameMagazine {
+    len: usize,
+    frames: [usize; FRAME_MAGAZINE_SIZE],
+}
+
+lazy_static! {
+    // per-CPU caches in front of FRAME_ALLOCATOR, they refill and drain it in batches of half size.
+    // each one is locked by its own CPU, only contended when all of them are drained.
+    static ref FRAME_MAGAZINES: [SpinNoIrqLock<FrameMagazine>; MAX_CPU_NUM] = Default::default();
+}
+
+/*
+* @brief:
+*   allocate a free frame from the magazine of the current CPU, refill it if empty
+* @retval:
+*   the frame number
+*/
+fn alloc_frame_cached() -> Option<usize> {
+    let mut magazine = FRAME_MAGAZINES[cpu::id()].lock();
+    if magazine.len == 0 {
+        let mut ba = lock_frame_allocator();
+        while magazine.len < FRAME_MAGAZINE_SIZE / 2 {
+            match ba.alloc() {
+                Some(frame) => {
+                    let len = magazine.len;
+                    magazine.frames[len] = frame;
+                    magazine.len += 1;
+                }
+                None => break,
+            }
+        }
+    }
+    if magazine.len == 0 {
+        return None;
+    }
+    magazine.len -= 1;
+    Some(magazine.frames[magazine.len])
+}
+
+/*
+* @param:
+*   frame: the frame number
+* @brief:
+*   free a frame to the magazine of the current CPU, drain it if full
+*/
+fn dealloc_frame_cached(frame: usize) {
+    let mut magazine = FRAME_MAGAZINES[cpu::id()].lock();
+    if magazine.len == FRAME_MAGAZINE_SIZE {
+        let mut ba = lock_frame_allocator();
+        while magazine.len > FRAME_MAGAZINE_SIZE / 2 {
+            magazine.len -= 1;
+            ba.dealloc(magazine.frames[magazine.len]);
+        }
+    }
+    let len = magazine.len;
+    magazine.frames[len] = frame;
+    magazine.len += 1;
+}
+
+/*
+* @brief:
+*   give back frames in the magazines of all CPUs to FRAME_ALLOCATOR, when running out of frames
+* @retval:
+*   whether any frame is given back
+*/
+fn drain_frame_magazines() -> bool {
+    let mut count = 0;
+    for magazine in FRAME_MAGAZINES.iter() {
+        let mut magazine = magazine.lock();
+        let mut ba = lock_frame_allocator();
+        for &frame in magazine.frames[..magazine.len].iter() {
+            ba.dealloc(frame);
+        }
+        count += magazine.len;
+        magazine.len = 0;
+    }
+    count != 0
+}
+
+/// The number of free frames in the magazines of all CPUs
+fn cached_frames() -> usize {
+    FRAME_MAGAZINES.iter().map(|magazine| magazine.lock().len).sum()
+}
+
+/*
+* @param:
+*   regions: usable memory regions, in frame numbers from MEMORY_OFFSET
+* @brief:
+*   init the frame allocator, with enough zones for all regions.
+*   zones are mapped in the kernel heap area, on frames taken from the first region large enough,
+*   since the heap is not available before the frame allocator.
+*/
+pub fn init_frame_allocator<I: Iterator<Item=Range<usize>> + Clone>(regions: I) {
+    let end = regions.clone().map(|region| region.end).max().expect("no usable memory");
+    let zone_num = ZonedAlloc::<FrameAlloc>::zone_num(end);
+    let pages = (zone_num * mem::size_of::<FrameAlloc>() + PAGE_SIZE - 1) / PAGE_SIZE;
+    let frames = regions.clone()
+        .find(|region| region.end - region.start >= pages)
+        .map(|region| region.start..region.start + pages)
+        .expect("no memory for the frame allocator");
+    let base = KERNEL_HEAP_AREA.lock().alloc_contiguous(pages, 0)
+        .expect("kernel heap area is too small for the frame allocator") * PAGE_SIZE + KERNEL_HEAP_OFFSET;
+    for (i, frame) in frames.clone().enumerate() {
+        unsafe { map_kernel_heap_page(base + i * PAGE_SIZE, frame * PAGE_SIZE + MEMORY_OFFSET); }
+    }
+
+    let mut ba = FRAME_ALLOCATOR.lock();
+    unsafe { ba.init(base as *mut FrameAlloc, zone_num); }
+    for region in regions {
+        ba.insert(region);
+    }
+    ba.remove(frames);
+    TOTAL_FRAMES.store(ba.count(), Ordering::Relaxed);
+    info!("frame allocator: {} zones in {} pages, {} frames", zone_num, pages, ba.count());
+}
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> = SpinNoIrqLock::new(FrameAlloc::default());
+    // the frames reserved by reserve_frames, which may be released
+    static ref RESERVED_FRAMES: SpinNoIrqLock<Vec<Range<usize>>> = SpinNoIrqLock::new(Vec::new());
+}
+
+/*
+* @param:
+*   start: the start physical address
+*   end: the end physical address
+* @brief:
+*   reserve free frames in the range after init, e.g. for firmware, so they are never allocated.
+*   all frames touched by the range are reserved, frames in use or reserved before must not be in it.
+*   the part out of the allocator, e.g. below MEMORY_OFFSET, is ignored, since it is never allocated.
+* @retval:
+*   the number of frames reserved
+*/
+pub fn reserve_frames(start: usize, end: usize) -> usize {
+    // the same order as release_frames
+    let mut reserved_frames = RESERVED_FRAMES.lock();
+    let mut ba = FRAME_ALLOCATOR.lock();
+    let range = phys_offset(start) / PAGE_SIZE
+        ..(phys_offset(end).saturating_add(PAGE_SIZE - 1) / PAGE_SIZE).min(ba.capacity());
+    if range.start >= range.end {
+        return 0;
+    }
+    let count = ba.count();
+    ba.remove(range.clone());
+    let reserved = count - ba.count();
+    TOTAL_FRAMES.fetch_sub(reserved, Ordering::Relaxed);
+    reserved_frames.push(range);
+    info!("reserve frames [{:#x}, {:#x}): {} frames", start, end, reserved);
+    reserved
+}
+
+/*
+* @param:
+*   start: the start physical address
+*   end: the end physical address
+* @brief:
+*   release frames in the range reserved by reserve_frames, e.g. for the initrd after it is loaded,
+*   so they can be allocated. only frames fully in the range are released.
+* @retval:
+*   the number of frames released, or Err if the frames are not in one range reserved
+*/
+pub fn release_frames(start: usize, end: usize) -> Result<usize, ()> {
+    let range = (phys_offset(start).saturating_add(PAGE_SIZE - 1) / PAGE_SIZE)..phys_offset(end) / PAGE_SIZE;
+    if range.start >= range.end {
+        return Ok(0);
+    }
+    let mut reserved_frames = RESERVED_FRAMES.lock();
+    let i = reserved_frames.iter()
+        .position(|reserved| reserved.start <= range.start && range.end <= reserved.end)
+        .ok_or(())?;
+    let reserved = reserved_frames.remove(i);
+    if reserved.start < range.start {
+        reserved_frames.push(reserved.start..range.start);
+    }
+    if range.end < reserved.end {
+        reserved_frames.push(range.end..reserved.end);
+    }
+    let mut ba = FRAME_ALLOCATOR.lock();
+    let count = ba.count();
+    ba.insert(range);
+    let released = ba.count() - count;
+    TOTAL_FRAMES.fetch_add(released, Ordering::Relaxed);
+    info!("release frames [{:#x}, {:#x}): {} frames", start, end, released);
+    Ok(released)
+}
+
+/// The offset of a physical address from MEMORY_OFFSET, 0 if it is below
+fn phys_offset(addr: usize) -> usize {
+    addr.saturating_sub(MEMORY_OFFSET)
 }
 
 lazy_static! {
@@ -36,19 +284,66 @@ lazy_static! {
 }
 
 /// The only way to get active page table
-pub fn active_table() -> MutexGuard<'static, ActivePageTable, SpinNoIrq> {
-    ACTIVE_TABLE.lock()
+pub fn active_table() -> ActiveTableGuard {
+    ActiveTableGuard(ManuallyDrop::new(ACTIVE_TABLE.lock()))
+}
+
+/// The locked active page table, the changes of its entries are flushed from the TLB
+/// of other CPUs after releasing it, see `tlb`. On x86_64, the kernel heap is shrunk then if requested,
+/// see `shrink_heap`.
+pub struct ActiveTableGuard(ManuallyDrop<MutexGuard<'static, ActivePageTable, SpinNoIrq>>);
+
+impl Deref for ActiveTableGuard {
+    type Target = ActivePageTable;
+    fn deref(&self) -> &ActivePageTable {
+        &self.0
+    }
+}
+
+impl DerefMut for ActiveTableGuard {
+    fn deref_mut(&mut self) -> &mut ActivePageTable {
+        &mut self.0
+    }
+}
+
+impl Drop for ActiveTableGuard {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.0); }
+        ::tlb::flush_others();
+        #[cfg(target_arch = "x86_64")]
+        {
+            if SHRINK_HEAP_PENDING.swap(false, Ordering::Relaxed) {
+                let count = ::HEAP_ALLOCATOR.shrink();
+                info!("shrink kernel heap: {} pages released", count);
+            }
+        }
+    }
 }
 
 lazy_static!{
     pub static ref SWAP_TABLE: Arc<spin::Mutex<SwapExtType>> = 
-        Arc::new(spin::Mutex::new(SwapExtType::new(fifo::FifoSwapManager::default(), mock_swapper::MockSwapper::default())));
+        Arc::new(spin::Mutex::new(SwapExtType::new(SwapManagerType::default(), ::swap::swapper())));
 }
 
 pub fn swap_table() -> spin::MutexGuard<'static, SwapExtType>{
     SWAP_TABLE.lock()
 }
 
+/*
+* @brief:
+*   update the swap manager on timer interrupt, e.g. to sample the accessed bits of swappable pages.
+*   the swap managers sample a bounded number of pages per tick, see ucore_memory::swap::TICK_SAMPLES.
+*   skip this tick if the active table or the swap table is in use,
+*   since the interrupted code may be holding them.
+*/
+pub fn swap_tick() {
+    if let Some(mut temp_table) = ACTIVE_TABLE.try_lock() {
+        if let Some(mut swap_table) = SWAP_TABLE.try_lock() {
+            swap_table.tick(temp_table.get_data_mut());
+        }
+    }
+}
+
 
 lazy_static!{
     pub static ref COW_TABLE: Arc<spin::Mutex<CowExt>> = 
@@ -67,21 +362,261 @@ pub fn cow_table() -> spin::MutexGuard<'static, CowExt>{
 */
 pub fn alloc_frame() -> Option<usize> {
     // get the real address of the alloc frame
-    let ret = FRAME_ALLOCATOR.lock().alloc().map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+    // before swapping, try to take frames back from other CPUs and the kernel heap
+    let ret = alloc_frame_cached()
+        .or_else(|| if drain_frame_magazines() || shrink_heap() { alloc_frame_cached() } else { None })
+        .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
     trace!("Allocate frame: {:x?}", ret);
     //do we need : unsafe { ACTIVE_TABLE_SWAP.force_unlock(); } ???
     Some(ret.unwrap_or_else(|| {
         // here we should get the active_table's lock before we get the swap_table since in memroy_set's map function
         // we get pagetable before we get the swap table lock
         // otherwise we may run into dead lock
+        // the swap table and cow table are released before the active table, which waits for the TLB shootdown
         let mut temp_table = active_table();
-        swap_table().swap_out_any(temp_table.get_data_mut()).ok().expect("fail to swap out page")
+        let mut swap_table = swap_table();
+        let mut cow_table = cow_table();
+        swap_table.swap_out_any(temp_table.get_data_mut(), &mut cow_table).ok().expect("fail to swap out page")
     }))
 }
 
 pub fn dealloc_frame(target: usize) {
     trace!("Deallocate frame: {:x}", target);
-    FRAME_ALLOCATOR.lock().dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+    dealloc_frame_cached((target - MEMORY_OFFSET) / PAGE_SIZE);
+}
+
+/*
+* @param:
+*   size: the number of frames to allocate
+*   align_log2: the first frame is aligned to (1 << align_log2) frames
+* @brief:
+*   allocate physically contiguous frames, e.g. for DMA buffers, huge pages or kernel stacks
+*   the free frames in the magazines of all CPUs are taken back before failing
+* @retval:
+*   the physical address for the first allocated frame
+*/
+pub fn alloc_frame_contiguous(size: usize, align_log2: usize) -> Option<usize> {
+    // the allocator is unlocked before draining the magazines
+    let ret = lock_frame_allocator().alloc_contiguous(size, align_log2);
+    let ret = ret.or_else(|| if drain_frame_magazines() { lock_frame_allocator().alloc_contiguous(size, align_log2) } else { None })
+        .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+    trace!("Allocate {} contiguous frames: {:x?}", size, ret);
+    ret
+}
+
+pub fn dealloc_frame_contiguous(target: usize, size: usize) {
+    trace!("Deallocate {} contiguous frames: {:x}", size, target);
+    lock_frame_allocator().dealloc_contiguous((target - MEMORY_OFFSET) / PAGE_SIZE, size);
+}
+
+// Kernel heap area has 16K pages on x86_64
+#[cfg(target_arch = "x86_64")]
+type HeapAreaBitmap = BitAlloc64K;
+
+// Kernel heap area has 4K pages on RISCV
+#[cfg(target_arch = "riscv32")]
+type HeapAreaBitmap = BitAlloc4K;
+
+lazy_static! {
+    // free virtual pages in the kernel heap area
+    static ref KERNEL_HEAP_AREA: SpinNoIrqLock<HeapAreaBitmap> = SpinNoIrqLock::new({
+        let mut ba = HeapAreaBitmap::default();
+        ba.insert(0..KERNEL_HEAP_AREA_SIZE / PAGE_SIZE);
+        ba
+    });
+}
+
+/*
+* @param:
+*   size: the number of pages to allocate
+*   align_log2: the first page is aligned to (1 << align_log2) pages
+* @brief:
+*   allocate virtual pages in the kernel heap area and map them to free frames
+*   it never swaps out pages, since it is called by the heap allocator, maybe with page tables locked,
+*   but takes the free frames back from the magazines of all CPUs before failing
+* @retval:
+*   the virtual address for the first allocated page
+*/
+pub fn alloc_kernel_pages(size: usize, align_log2: usize) -> Option<usize> {
+    let base = KERNEL_HEAP_AREA.lock().alloc_contiguous(size, align_log2)? * PAGE_SIZE + KERNEL_HEAP_OFFSET;
+    for i in 0..size {
+        // the allocator is unlocked before draining the magazines, or freeing the pages on failure
+        let frame = lock_frame_allocator().alloc();
+        let frame = frame.or_else(|| if drain_frame_magazines() { lock_frame_allocator().alloc() } else { None });
+        match frame {
+            Some(id) => unsafe { map_kernel_heap_page(base + i * PAGE_SIZE, id * PAGE_SIZE + MEMORY_OFFSET); },
+            None => {
+                dealloc_kernel_pages(base, i);
+                KERNEL_HEAP_AREA.lock().dealloc_contiguous((base - KERNEL_HEAP_OFFSET) / PAGE_SIZE + i, size - i);
+                return None;
+            }
+        }
+    }
+    trace!("Allocate {} kernel pages: {:#x}", size, base);
+    Some(base)
+}
+
+/*
+* @param:
+*   base: the virtual address of the first page
+*   size: the number of pages to deallocate
+* @brief:
+*   unmap pages in the kernel heap area, and free their frames and virtual pages
+*   the TLB of other CPUs is flushed before the frames and virtual pages can be reused
+*/
+pub fn dealloc_kernel_pages(base: usize, size: usize) {
+    trace!("Deallocate {} kernel pages: {:#x}", size, base);
+    const BATCH: usize = 16;
+    for start in (0..size).step_by(BATCH) {
+        let n = BATCH.min(size - start);
+        let mut frames = [0; BATCH];
+        for i in 0..n {
+            frames[i] = unsafe { unmap_kernel_heap_page(base + (start + i) * PAGE_SIZE) };
+        }
+        flush_kernel_heap_tlb_others();
+        let mut ba = lock_frame_allocator();
+        for &target in frames[..n].iter() {
+            ba.dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        }
+    }
+    KERNEL_HEAP_AREA.lock().dealloc_contiguous((base - KERNEL_HEAP_OFFSET) / PAGE_SIZE, size);
+}
+
+/*
+* @brief:
+*   give back free slab pages of the kernel heap, when running out of frames
+* @retval:
+*   whether any frame is released
+*/
+#[cfg(target_arch = "riscv32")]
+pub fn shrink_heap() -> bool {
+    let count = ::HEAP_ALLOCATOR.shrink();
+    info!("shrink kernel heap: {} pages released", count);
+    count != 0
+}
+
+/// Whether the kernel heap should be shrunk when the active table is released, see `shrink_heap`
+#[cfg(target_arch = "x86_64")]
+static SHRINK_HEAP_PENDING: ::core::sync::atomic::AtomicBool = ::core::sync::atomic::ATOMIC_BOOL_INIT;
+
+/// The heap is shrunk later on x86_64: it is reached from `alloc_frame` with other locks held,
+/// and the IPI shootdown of the heap pages would wait for CPUs spinning on them with interrupts
+/// disabled. So it is only requested here, and done when the active table is released, after the
+/// TLB shootdown of its own changes, see `ActiveTableGuard`. The pages are not available at once,
+/// so it returns false. On riscv32 the remote flush is done by the SBI, whatever the harts are waiting for.
+#[cfg(target_arch = "x86_64")]
+pub fn shrink_heap() -> bool {
+    SHRINK_HEAP_PENDING.store(true, Ordering::Relaxed);
+    false
+}
+
+/// Memory usage statistics, all in pages
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MemInfo {
+    /// frames managed by the frame allocator
+    pub total: usize,
+    /// free frames
+    pub free: usize,
+    /// frames mapped in the kernel heap area
+    pub kernel_heap: usize,
+    /// pages swapped out
+    pub swapped: usize,
+    /// frames mapped as copy-on-write
+    pub cow: usize,
+    /// copy-on-write frames shared by more than one page
+    pub cow_shared: usize,
+    /// free frames in the per-CPU caches, included in `free`
+    pub cached: usize,
+    /// times the global frame allocator is locked
+    pub frame_lock_acquired: usize,
+    /// times the global frame allocator is found locked by others
+    pub frame_lock_contended: usize,
+}
+
+/*
+* @brief:
+*   collect memory usage statistics of all categories
+* @retval:
+*   the MemInfo with all counters
+*/
+pub fn meminfo() -> MemInfo {
+    let (cow, cow_shared) = {
+        let cow_table = cow_table();
+        (cow_table.frame_count(), cow_table.shared_count())
+    };
+    let cached = cached_frames();
+    MemInfo {
+        total: TOTAL_FRAMES.load(Ordering::Relaxed),
+        free: FRAME_ALLOCATOR.lock().count() + cached,
+        kernel_heap: KERNEL_HEAP_AREA_SIZE / PAGE_SIZE - KERNEL_HEAP_AREA.lock().count(),
+        swapped: swap_table().swapped_count(),
+        cow,
+        cow_shared,
+        cached,
+        frame_lock_acquired: FRAME_LOCK_ACQUIRED.load(Ordering::Relaxed),
+        frame_lock_contended: FRAME_LOCK_CONTENDED.load(Ordering::Relaxed),
+    }
+}
+
+/*
+* @param:
+*   start: the start virtual address
+*   end: the end virtual address, not included
+* @brief:
+*   print the pages mapped in the active page table from start to end, one line for the pages
+*   next to each other with the same bits and contiguous targets, e.g.
+*   `0x00400000-0x00403000 -> 0x80a21000 pwus-` for present, writable, user, shared and swapped
+*/
+pub fn dump_page_table(start: usize, end: usize) {
+    let mut table = active_table();
+    let mut last: Option<MappedEntry> = None;
+    for entry in MappedPages::new(&mut *table, start, end) {
+        if last.as_mut().map_or(false, |pages| pages.merge(&entry)) {
+            continue;
+        }
+        if let Some(pages) = last {
+            println!("{}", pages);
+        }
+        last = Some(entry);
+    }
+    if let Some(pages) = last {
+        println!("{}", pages);
+    }
+}
+
+/// Support of the global slab allocator `HEAP_ALLOCATOR`
+///
+/// Slab pages and large objects are both pages in the kernel heap area,
+/// so the heap grows until running out of frames.
+pub struct HeapSupport;
+
+impl SlabSupport for HeapSupport {
+    fn cpu_id() -> usize {
+        cpu::id()
+    }
+    fn alloc_page() -> Option<usize> {
+        alloc_kernel_pages(1, 0)
+    }
+    fn dealloc_page(addr: usize) {
+        dealloc_kernel_pages(addr, 1)
+    }
+    unsafe fn alloc_large(layout: Layout) -> *mut u8 {
+        let size = (layout.size() + PAGE_SIZE - 1) / PAGE_SIZE;
+        let align_log2 = (layout.align().max(PAGE_SIZE) / PAGE_SIZE).trailing_zeros() as usize;
+        let base = alloc_kernel_pages(size, align_log2)
+            .or_else(|| if drain_frame_magazines() || shrink_heap() { alloc_kernel_pages(size, align_log2) } else { None });
+        base.unwrap_or(0) as *mut u8
+    }
+    unsafe fn dealloc_large(ptr: *mut u8, layout: Layout) {
+        dealloc_kernel_pages(ptr as usize, (layout.size() + PAGE_SIZE - 1) / PAGE_SIZE)
+    }
+    unsafe fn disable_and_store() -> usize {
+        interrupt::disable_and_store()
+    }
+    unsafe fn restore(flags: usize) {
+        interrupt::restore(flags)
+    }
 }
 
 pub struct KernelStack(usize);
@@ -108,13 +643,14 @@ impl Drop for KernelStack {
 
 /*
 * @param:
-*   addr: the virtual address of the page fault
+*   fault: the page fault described by the trap handler
 * @brief:
 *   handle page fault
 * @retval:
 *   Return true to continue, false to halt
 */
-pub fn page_fault_handler(addr: usize) -> bool {
+pub fn page_fault_handler(fault: PageFault) -> bool {
+    let addr = fault.addr;
     info!("start handling swap in/out page fault");
     //unsafe { ACTIVE_TABLE_SWAP.force_unlock(); }
     
@@ -125,13 +661,17 @@ pub fn page_fault_handler(addr: usize) -> bool {
         Some(area) => {
             let pt = process().get_memory_set_mut().get_page_table_mut();
             let mut temp_table = active_table();
-            if area.page_fault_handler(temp_table.get_data_mut(), pt as *mut InactivePageTable0 as usize, addr) {
+            if area.page_fault_handler(temp_table.get_data_mut(), pt as *mut InactivePageTable0 as usize, fault) {
                 return true;
             }
             //if swap_table().page_fault_handler(temp_table.get_data_mut(), pt as *mut InactivePageTable0, addr, true, || alloc_frame().expect("fail to alloc frame")){
             //    return true;
             //}
         },
+        // the stack is never executed
+        None if fault.access != Access::Execute => {
+            return grow_stack(addr);
+        },
         None => {
             return false;
         },
@@ -139,11 +679,39 @@ pub fn page_fault_handler(addr: usize) -> bool {
     false
 }
 
-pub fn init_heap() {
-    use consts::KERNEL_HEAP_SIZE;
-    static mut HEAP: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
-    unsafe { HEAP_ALLOCATOR.lock().init(HEAP.as_ptr() as usize, KERNEL_HEAP_SIZE); }
-    info!("heap init end");
+/*
+* @param:
+*   addr: the virtual address of the page fault, not in any memory area
+* @brief:
+*   grow the user stack of the current process down to the address, up to its limit,
+*   keeping an unmapped guard page below it. a fault in the guard page is reported as stack overflow
+* @retval:
+*   whether the stack is grown
+*/
+fn grow_stack(addr: usize) -> bool {
+    let context = process();
+    let (top, limit) = (context.stack_top, context.stack_limit);
+    // the lowest address of the stack, and its guard page, that may be reached
+    let (lowest, guard) = (top.saturating_sub(limit), top.saturating_sub(limit + PAGE_SIZE));
+    if top == 0 || addr >= top || addr < guard {
+        return false;
+    }
+    let memory_set = context.get_memory_set_mut();
+    let start = match memory_set.find_area(top - 1) {
+        Some(area) => area.get_start_addr(),
+        None => return false,
+    };
+    let new_start = Page::of_addr(addr).start_address();
+    // the guard page should be free too
+    let grown = addr >= lowest && new_start >= PAGE_SIZE
+        && memory_set.find_free_area(new_start - PAGE_SIZE, new_start, PAGE_SIZE) == Some(new_start - PAGE_SIZE)
+        && memory_set.extend_down(start, new_start);
+    if grown {
+        info!("grow user stack to [{:#x}, {:#x})", new_start, top);
+    } else {
+        error!("user stack overflow @ {:#x}, stack: [{:#x}, {:#x}), limit: {:#x}", addr, start, top, limit);
+    }
+    grown
 }
 
 //pub mod test {
@@ -179,7 +747,7 @@ impl MemoryHandler for SimpleMemoryHandler{
         pt.unmap(addr);
     }
     
-    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool{
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool{
         false
     }
 
@@ -200,7 +768,19 @@ impl MemoryHandler for SimpleMemoryHandler{
             });
         }
     }
-    
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(SimpleMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+    }
 }
 
 impl SimpleMemoryHandler{
@@ -236,7 +816,7 @@ impl MemoryHandler for NormalMemoryHandler{
         pt.unmap(addr);
     }
     
-    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool {
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
         false
     }
 
@@ -255,6 +835,19 @@ impl MemoryHandler for NormalMemoryHandler{
             });
         }
     }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(NormalMemoryHandler::new(flags))
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+    }
 }
 
 impl NormalMemoryHandler{
@@ -265,145 +858,278 @@ impl NormalMemoryHandler{
     }
 }
 
-pub struct SwapMemoryHandler{
-    swap_ext: Arc<spin::Mutex<SwapExtType>>,
+/// MemoryHandler mapping the big, aligned parts of an area with huge pages, and the rest with 4K pages
+///
+/// The memory is anonymous and filled with zero.
+/// A huge page is demoted to 4K pages by `split` before the area is split across it.
+/// Anonymous huge pages are not shared with copy-on-write on fork: each one is copied at once
+/// to newly allocated contiguous frames, which costs a whole huge page of memory and copying per page.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct HugePageMemoryHandler {
     flags: MemoryAttr,
-    delay_alloc: Vec<VirtAddr>,
 }
 
-impl MemoryHandler for SwapMemoryHandler{
-    fn box_clone(&self) -> Box<MemoryHandler>{
+impl MemoryHandler for HugePageMemoryHandler {
+    fn box_clone(&self) -> Box<MemoryHandler> {
         Box::new((*self).clone())
     }
 
-    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr){
-        //info!("COME into Swap MemoryHandler, addr is {:x?}", addr);
-        let id = self.delay_alloc.iter().position(|x|*x == addr);
-        if id.is_some(){
-            info!("delay allocated addr: {:x?}", addr);
-            {
-                let entry = pt.map(addr,0);
-                self.flags.apply(entry);
-            }
-            let entry = pt.get_entry(addr).expect("fail to get entry");
-            entry.set_present(false);
-            entry.update();
-        }
-        else{
-            info!("no delay allocated addr: {:x?}", addr);
-            let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
-            self.flags.apply(pt.map(addr, target));
-            unsafe{self.swap_ext.lock().set_swappable(pt, inpt as *mut InactivePageTable0, addr);}
-        }
+    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
+        zero_frames(target, 1);
+        self.flags.apply(pt.map(addr, target));
     }
 
-    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr){
-        info!("COME into Swap unmap, addr");
-        unsafe{
-            self.swap_ext.lock().remove_from_swappable(pt, inpt as *mut InactivePageTable0, addr, || InactivePageTable0::alloc_frame().expect("alloc frame failed"));
-        }
-        if pt.get_entry(addr).expect("fail to get entry").present(){
-            let target = pt.get_entry(addr).expect("fail to get entry").target();
-            InactivePageTable0::dealloc_frame(target);
-            pt.unmap(addr);
-        }
-        else{
-            // set valid for pt.unmap function
-            pt.get_entry(addr).expect("fail to get entry").set_present(true);
-            // infact we need to unmap the addr here as well however since pt.unmap won't deallocate the p1-table page now ,we don't need to do it just now
-        }
-        //pt.unmap(addr);
-    }
-    
-    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool {
-        // check whether need to handle page delayed allocating
-        let id = self.delay_alloc.iter().position(|x|*x == addr);
-        if id.is_some(){
-            info!("try handling delayed frame allocator");
-            let need_alloc ={
-                let entry = page_table.get_entry(addr).expect("fail to get entry");
-                //info!("got entry!");
-                !entry.present() && !entry.swapped()
-            };
-            info!("need_alloc got");
-            if need_alloc {
-                info!("need_alloc!");
-                let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
-                {
-                    let entry = page_table.get_entry(addr).expect("fail to get entry");
-                    entry.set_target(frame);
-                    //let new_entry = self.page_table.map(addr, frame);
-                    self.flags.apply(entry);
+    fn map_range(&self, pt: &mut PageTable, inpt: usize, start: VirtAddr, end: VirtAddr) {
+        let huge_size = pt.huge_page_size();
+        let mut addr = start;
+        while addr < end {
+            match self.huge_target(huge_size, addr, end) {
+                Some(target) => {
+                    Self::map_frames(&self.flags, pt, addr, target, huge_size);
+                    addr += huge_size;
+                }
+                None => {
+                    self.map(pt, inpt, addr);
+                    addr += PAGE_SIZE;
                 }
-                unsafe{self.swap_ext.lock().set_swappable(page_table, inpt as *mut InactivePageTable0, Page::of_addr(addr).start_address())};
-                //area.get_flags().apply(new_entry); this instruction may be used when hide attr is used
-                info!("allocated successfully");
-                return true;
             }
-            info!("not need alloc!");
         }
-        // handle the swap out page fault            
-        // now we didn't attach the cow so the present will be false when swapped(), to enable the cow some changes will be needed
-        match page_table.get_entry(addr) {
-            // infact the get_entry(addr) should not be None here
-            None => return false,
-            Some(entry) => if !(entry.swapped() && !entry.present())  { return false; },
+    }
+
+    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        info!("COME into HugePage unmap, addr is {:x?}", addr);
+        // the other pages inside a huge page are unmapped with it at its base
+        let (huge, target) = match pt.get_entry(addr) {
+            Some(entry) => (entry.huge(), entry.target()),
+            None => return,
+        };
+        if huge {
+            let huge_size = pt.huge_page_size();
+            if addr % huge_size != 0 {
+                // the huge page begins before the pages to unmap, demote it to unmap them one by one
+                self.split(pt, inpt, addr);
+                return self.unmap(pt, inpt, addr);
+            }
+            pt.unmap_huge(addr);
+            dealloc_frame_contiguous(target, huge_size / PAGE_SIZE);
+            return;
         }
-        // Allocate a frame, if failed, swap out a page
-        let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
-        self.swap_ext.lock().swap_in(page_table, inpt as *mut InactivePageTable0, addr, frame).ok().unwrap();
-        true
-        
+        InactivePageTable0::dealloc_frame(target);
+        pt.unmap(addr);
     }
-    
-    fn map_clone(&mut self, inpt: usize, addr: VirtAddr){
-        info!("Come into SwapMemoryHandler map_clone, the addr is {:x?}", addr);
-        let Self {ref swap_ext, ref flags, ref mut delay_alloc} = self;
-        let mut allocated = {
+
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        false
+    }
+
+    fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+        info!("come into HugePageMemoryHandler map_clone, the addr is {:x?}", addr);
+        let (huge_size, huge) = {
             let mut temp_table = active_table();
-            let entry = temp_table.get_entry(addr).expect("fail to get entry");
-            entry.present() || entry.swapped()
-            // infact 0 frame is being allocated, it is dangerous to check allcated by entry.target() == 0
+            let huge = temp_table.get_entry(addr).expect("fail to get entry").huge();
+            (temp_table.huge_page_size(), huge)
         };
-        unsafe{
+        if huge && addr % huge_size != 0 {
+            return;
+        }
+        let size = if huge { huge_size } else { PAGE_SIZE };
+        let target = match huge {
+            true => alloc_frame_contiguous(size / PAGE_SIZE, (size / PAGE_SIZE).trailing_zeros() as usize)
+                .expect("failed to allocate huge page"),
+            false => InactivePageTable0::alloc_frame().expect("failed to allocate frame"),
+        };
+        let flags = &self.flags;
+        unsafe {
             let mut page_table = &mut *(inpt as *mut InactivePageTable0);
-            //allocated = true; // for test
-            if !allocated {
-                delay_alloc.push(addr);
-                page_table.edit(|pt|{
-                    {
-                        let entry = pt.map(addr,0);
-                        flags.apply(entry);
-                    }
-                    let entry = pt.get_entry(addr).expect("fail to get entry");
-                    entry.set_present(false);
-                    entry.update();
-                });
-            }
-            else{
-                page_table.edit(|pt|{
-                    let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
-                    flags.apply(pt.map(addr, target));
-                    swap_ext.lock().set_swappable(pt, inpt as *mut InactivePageTable0, addr);
-                });
-                let data: Vec<u8> = Vec::from(slice::from_raw_parts(addr as *const u8, PAGE_SIZE));
-                page_table.with(||{
-                    let page_mut = slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE);
+            page_table.edit(|pt| Self::map_frames(flags, pt, addr, target, size));
+            // copy page by page, not to take a huge buffer from the kernel heap
+            for page_addr in (addr..addr + size).step_by(PAGE_SIZE) {
+                let data: Vec<u8> = Vec::from(slice::from_raw_parts(page_addr as *const u8, PAGE_SIZE));
+                page_table.with(|| {
+                    let page_mut = slice::from_raw_parts_mut(page_addr as *mut u8, PAGE_SIZE);
                     page_mut.copy_from_slice(data.as_slice());
                 });
             }
         }
     }
 
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(HugePageMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let huge_size = pt.huge_page_size();
+        if let Some(entry) = pt.get_entry(addr) {
+            // the entry of a huge page is shared by all the pages inside it
+            if !entry.huge() || addr % huge_size == 0 {
+                self.flags.apply_protection(entry);
+            }
+        }
+    }
+
+    fn split(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let huge_size = pt.huge_page_size();
+        if huge_size == 0 || addr % huge_size == 0 {
+            return;
+        }
+        // demote the huge page across `addr` to 4K pages of the same frames
+        let base = addr & !(huge_size - 1);
+        let target = match pt.get_entry(base) {
+            Some(entry) if entry.huge() => entry.target(),
+            _ => return,
+        };
+        pt.unmap_huge(base);
+        for offset in (0..huge_size).step_by(PAGE_SIZE) {
+            self.flags.apply(pt.map(base + offset, target + offset));
+        }
+    }
+}
+
+impl HugePageMemoryHandler {
+    /// Anonymous memory, filled with zero
+    pub fn new(flags: MemoryAttr) -> Self {
+        HugePageMemoryHandler {
+            flags,
+        }
+    }
+
+    /*
+    * @param:
+    *   flags: the flags of the pages
+    *   pt: the page table to map in
+    *   addr: the virtual address to map
+    *   target: the physical address of the frames
+    *   size: the size to map, a huge page if it is larger than PAGE_SIZE
+    * @brief:
+    *   map the frames with a huge page, or with 4K pages if it is 4K,
+    *   or the huge page can not be mapped, e.g. a table of 4K pages is in the way
+    */
+    fn map_frames(flags: &MemoryAttr, pt: &mut PageTable, addr: VirtAddr, target: PhysAddr, size: usize) {
+        let mapped = size > PAGE_SIZE && match pt.map_huge(addr, target) {
+            Some(entry) => {
+                flags.apply(entry);
+                true
+            }
+            None => false,
+        };
+        if !mapped {
+            for offset in (0..size).step_by(PAGE_SIZE) {
+                flags.apply(pt.map(addr + offset, target + offset));
+            }
+        }
+    }
+
+    /*
+    * @param:
+    *   huge_size: the size of huge pages of the page table, 0 if not supported
+    *   addr: the virtual address to map
+    *   end: the end of the range to map
+    * @brief:
+    *   get the frames for a huge page at addr, if the huge page is aligned and inside [addr, end)
+    *   the frames are filled with zero
+    * @retval:
+    *   the physical address of the huge page, None if it should be mapped with 4K pages
+    */
+    fn huge_target(&self, huge_size: usize, addr: VirtAddr, end: VirtAddr) -> Option<PhysAddr> {
+        if huge_size == 0 || addr % huge_size != 0 || end - addr < huge_size {
+            return None;
+        }
+        // fall back to 4K pages if physical memory is too fragmented
+        let target = alloc_frame_contiguous(huge_size / PAGE_SIZE, (huge_size / PAGE_SIZE).trailing_zeros() as usize)?;
+        zero_frames(target, huge_size / PAGE_SIZE);
+        Some(target)
+    }
+}
+
+pub struct SwapMemoryHandler{
+    swap_ext: Arc<spin::Mutex<SwapExtType>>,
+    flags: MemoryAttr,
+}
+
+impl MemoryHandler for SwapMemoryHandler{
+    fn box_clone(&self) -> Box<MemoryHandler>{
+        Box::new((*self).clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr){
+        //info!("COME into Swap MemoryHandler, addr is {:x?}", addr);
+        // pages are allocated when mapped, use ZeroMemoryHandler for lazily allocated memory
+        let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
+        self.flags.apply(pt.map(addr, target));
+        unsafe{self.swap_ext.lock().set_swappable(pt, inpt as *mut InactivePageTable0, addr);}
+    }
+
+    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr){
+        info!("COME into Swap unmap, addr");
+        unsafe{
+            self.swap_ext.lock().remove_from_swappable(pt, inpt as *mut InactivePageTable0, addr, || InactivePageTable0::alloc_frame().expect("alloc frame failed"));
+        }
+        let target = pt.get_entry(addr).expect("fail to get entry").target();
+        InactivePageTable0::dealloc_frame(target);
+        pt.unmap(addr);
+    }
+    
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        // handle the swap out page fault
+        if fault.present {
+            return false;
+        }
+        let addr = fault.addr;
+        match page_table.get_entry(addr) {
+            // infact the get_entry(addr) should not be None here
+            None => return false,
+            Some(entry) => if !entry.swapped() { return false; },
+        }
+        // Allocate a frame, if failed, swap out a page
+        let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
+        self.swap_ext.lock().swap_in(page_table, inpt as *mut InactivePageTable0, addr, frame).ok().unwrap();
+        true
+    }
+    
+    fn map_clone(&mut self, inpt: usize, addr: VirtAddr){
+        info!("Come into SwapMemoryHandler map_clone, the addr is {:x?}", addr);
+        let Self {ref swap_ext, ref flags} = self;
+        unsafe{
+            let mut page_table = &mut *(inpt as *mut InactivePageTable0);
+            page_table.edit(|pt|{
+                let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
+                flags.apply(pt.map(addr, target));
+                swap_ext.lock().set_swappable(pt, inpt as *mut InactivePageTable0, addr);
+            });
+            // a swapped out page is swapped in by the page fault on reading it
+            let data: Vec<u8> = Vec::from(slice::from_raw_parts(addr as *const u8, PAGE_SIZE));
+            page_table.with(||{
+                let page_mut = slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE);
+                page_mut.copy_from_slice(data.as_slice());
+            });
+        }
+    }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(SwapMemoryHandler::new(self.swap_ext.clone(), flags))
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+    }
 }
 
 
 impl SwapMemoryHandler{
-    pub fn new(swap_ext: Arc<spin::Mutex<SwapExtType>>, flags: MemoryAttr, delay_alloc: Vec<VirtAddr>) -> Self {
+    pub fn new(swap_ext: Arc<spin::Mutex<SwapExtType>>, flags: MemoryAttr) -> Self {
         SwapMemoryHandler{
             swap_ext,
             flags,
-            delay_alloc,
         }
     }
 }
@@ -411,14 +1137,14 @@ impl SwapMemoryHandler{
 
 impl Clone for SwapMemoryHandler{
     fn clone(&self) -> Self{
-        // when we fork a new process, all the page need to be map with physical phrame immediately
-        SwapMemoryHandler::new(self.swap_ext.clone(), self.flags.clone(), Vec::<VirtAddr>::new())
+        SwapMemoryHandler::new(self.swap_ext.clone(), self.flags.clone())
     }
 }
 
 
 pub struct CowMemoryHandler{
     cow_ext: Arc<spin::Mutex<CowExt>>,
+    swap_ext: Arc<spin::Mutex<SwapExtType>>,
     flags: MemoryAttr,
 }
 
@@ -438,27 +1164,55 @@ impl MemoryHandler for CowMemoryHandler{
         //entry.set_writable(false);
         entry.set_shared(!self.flags.is_readonly());
         entry.update();
-        self.cow_ext.lock().map_to_shared(target, !self.flags.is_readonly());
+        unsafe{
+            self.swap_ext.lock().set_swappable_shared(pt, inpt as *mut InactivePageTable0, addr, !self.flags.is_readonly(), &mut self.cow_ext.lock());
+        }
     }
 
     fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr){
         info!("COME INTO COW UNMAP. addr is {:x?}", addr);
-        let target = pt.get_entry(addr).expect("fail to get entry").target();
+        // the flags may be changed by mprotect, so use the shared bit of the entry to decrease the reference
+        let writable = pt.get_entry(addr).expect("fail to get entry").writable_shared();
+        let last = unsafe{
+            self.swap_ext.lock().remove_from_swappable_shared(pt, inpt as *mut InactivePageTable0, addr, writable, &mut self.cow_ext.lock())
+        };
+        // if the shared frame is swapped out, the entry is left with no target, set valid for pt.unmap function
+        {
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            if !entry.present() {
+                entry.set_present(true);
+                entry.update();
+            }
+        }
         pt.unmap(addr);
         //info!("finish pt.unmap");
-        if self.cow_ext.lock().unmap_shared(target, !self.flags.is_readonly()){
-            //info!("finish unmap_shared");
+        if let Some(target) = last {
             InactivePageTable0::dealloc_frame(target);
         }
         //info!("COME OUT OF COW UNMAP.");
     }
     
-    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, addr: VirtAddr) -> bool {
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
         //info!("COME INTO COW PAGEFAULT HANDLER.");
-        if self.flags.is_readonly() {
+        let addr = fault.addr;
+        let page_addr = Page::of_addr(addr).start_address();
+        if !fault.present {
+            if !page_table.get_entry(addr).expect("fail to get entry").swapped() {
+                return false;
+            }
+            // the shared frame is swapped out, swap it in for all pages sharing it
+            let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
+            self.swap_ext.lock().swap_in_shared(page_table, page_addr, frame, &mut self.cow_ext.lock()).ok().unwrap();
+            return true;
+        }
+        // only a write to a shared page makes a copy
+        if fault.access != Access::Write || self.flags.is_readonly() {
             return false;
         }
-        let target = page_table.get_entry(addr).expect("fail to get entry").target();
+        let (target, writable) = {
+            let entry = page_table.get_entry(addr).expect("fail to get entry");
+            (entry.target(), entry.writable_shared())
+        };
         if self.cow_ext.lock().is_one_shared(target){
             let entry = page_table.get_entry(addr).expect("fail to get entry");
             entry.set_writable(true);
@@ -466,15 +1220,17 @@ impl MemoryHandler for CowMemoryHandler{
         }
         else{
             unsafe{
-                let page_addr = Page::of_addr(addr).start_address();
                 let data: Vec<u8> = Vec::from(slice::from_raw_parts(page_addr as *const u8, PAGE_SIZE));
-                self.cow_ext.lock().unmap_shared(target, true);
+                self.swap_ext.lock().remove_from_swappable_shared(page_table, inpt as *mut InactivePageTable0, page_addr, writable, &mut self.cow_ext.lock());
                 let new_target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
-                let entry = page_table.get_entry(addr).expect("fail to get entry");
-                entry.set_writable(true);
-                entry.set_target(new_target);
-                entry.update();
-                self.cow_ext.lock().map_to_shared(new_target, !self.flags.is_readonly());
+                {
+                    let entry = page_table.get_entry(addr).expect("fail to get entry");
+                    entry.set_writable(true);
+                    entry.set_shared(true);
+                    entry.set_target(new_target);
+                    entry.update();
+                }
+                self.swap_ext.lock().set_swappable_shared(page_table, inpt as *mut InactivePageTable0, page_addr, true, &mut self.cow_ext.lock());
                 let page_mut = slice::from_raw_parts_mut(page_addr as *mut u8, PAGE_SIZE);
                 page_mut.copy_from_slice(data.as_slice());
             }
@@ -485,8 +1241,15 @@ impl MemoryHandler for CowMemoryHandler{
     fn map_clone(&mut self, inpt: usize, addr: VirtAddr){
         //info!("COME INTO COW MAP CLONE.");
         unsafe{
-            let Self {ref mut cow_ext, ref flags} = self;
+            let Self {ref mut cow_ext, ref swap_ext, ref flags} = self;
             let mut page_table = &mut *(inpt as *mut InactivePageTable0);
+            // the new page will share the frame, so swap it in first
+            let swapped = active_table().get_entry(addr).expect("fail to get entry").swapped();
+            if swapped {
+                let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
+                let mut temp_table = active_table();
+                swap_ext.lock().swap_in_shared(temp_table.get_data_mut(), addr, frame, &mut cow_ext.lock()).ok().unwrap();
+            }
             let target = {
                 let mut temp_table = active_table();
                 let entry = temp_table.get_entry(addr).expect("fail to get entry");
@@ -501,16 +1264,36 @@ impl MemoryHandler for CowMemoryHandler{
                 entry.set_writable(false);
                 entry.set_shared(!flags.is_readonly());
                 entry.update();
-                cow_ext.lock().map_to_shared(target, !flags.is_readonly());
+                swap_ext.lock().set_swappable_shared(pt, inpt as *mut InactivePageTable0, addr, !flags.is_readonly(), &mut cow_ext.lock());
             });
         }
     }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(CowMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+        // keep shared pages readonly, a write makes a private copy of it in page_fault_handler.
+        // a swapped out page may be shared as well, it is made writable by the write after swapping in
+        if !entry.present() || !self.cow_ext.lock().is_one_shared(entry.target()) {
+            entry.set_writable(false);
+            entry.update();
+        }
+    }
 }
 
 impl CowMemoryHandler{
-    pub fn new(cow_ext: Arc<spin::Mutex<CowExt>>, flags: MemoryAttr) -> Self {
+    pub fn new(cow_ext: Arc<spin::Mutex<CowExt>>, swap_ext: Arc<spin::Mutex<SwapExtType>>, flags: MemoryAttr) -> Self {
         CowMemoryHandler{
             cow_ext,
+            swap_ext,
             flags,
         }
     }
@@ -520,6 +1303,593 @@ impl CowMemoryHandler{
 impl Clone for CowMemoryHandler{
     fn clone(&self) -> Self{
         // when we fork a new process, all the page need to be map with physical phrame immediately
-        CowMemoryHandler::new(self.cow_ext.clone(), self.flags.clone())
+        CowMemoryHandler::new(self.cow_ext.clone(), self.swap_ext.clone(), self.flags.clone())
+    }
+}
+
+lazy_static! {
+    // the frame filled with zero, mapped readonly by the pages of ZeroMemoryHandler not written yet
+    static ref ZERO_FRAME: PhysAddr = {
+        // fill it through a page of the kernel heap area, which is never freed
+        // it does not lock the active table, since it may be called with the table locked
+        let target = lock_frame_allocator().alloc().expect("failed to allocate the zero frame") * PAGE_SIZE + MEMORY_OFFSET;
+        let base = KERNEL_HEAP_AREA.lock().alloc_contiguous(1, 0).expect("kernel heap area is full") * PAGE_SIZE + KERNEL_HEAP_OFFSET;
+        unsafe {
+            map_kernel_heap_page(base, target);
+            slice::from_raw_parts_mut(base as *mut u8, PAGE_SIZE).iter_mut().for_each(|byte| *byte = 0);
+        }
+        // the reference of the zero frame itself, so it is never freed by unmapping
+        COW_TABLE.lock().map_to_shared(target, false);
+        target
+    };
+}
+
+/*
+* @param:
+*   target: the physical address of the first frame
+*   size: the number of frames
+* @brief:
+*   fill frames with zero before they are mapped, through a page of the kernel heap area like ZERO_FRAME,
+*   so it works when editing an inactive page table, and for pages mapped readonly
+*/
+fn zero_frames(target: PhysAddr, size: usize) {
+    let base = KERNEL_HEAP_AREA.lock().alloc_contiguous(1, 0).expect("kernel heap area is full") * PAGE_SIZE + KERNEL_HEAP_OFFSET;
+    for i in 0..size {
+        unsafe {
+            map_kernel_heap_page(base, target + i * PAGE_SIZE);
+            slice::from_raw_parts_mut(base as *mut u8, PAGE_SIZE).iter_mut().for_each(|byte| *byte = 0);
+            unmap_kernel_heap_page(base);
+        }
+    }
+    flush_kernel_heap_tlb_others();
+    KERNEL_HEAP_AREA.lock().dealloc_contiguous((base - KERNEL_HEAP_OFFSET) / PAGE_SIZE, 1);
+}
+
+/// MemoryHandler for demand-zero anonymous memory, e.g. BSS and the heap
+///
+/// Pages not written yet are mapped readonly to `ZERO_FRAME`, as readonly shared pages in `CowExt`,
+/// so reading them costs no memory. On the first write, the page gets its own frame filled with zero,
+/// which is swappable like the pages of `SwapMemoryHandler`.
+#[derive(Clone)]
+pub struct ZeroMemoryHandler {
+    cow_ext: Arc<spin::Mutex<CowExt>>,
+    swap_ext: Arc<spin::Mutex<SwapExtType>>,
+    flags: MemoryAttr,
+}
+
+impl MemoryHandler for ZeroMemoryHandler {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new((*self).clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let zero = *ZERO_FRAME;
+        self.cow_ext.lock().map_to_shared(zero, false);
+        self.flags.apply(pt.map(addr, zero));
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        entry.set_writable(false);
+        entry.set_shared(false);
+        entry.update();
+    }
+
+    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        info!("COME into Zero unmap, addr is {:x?}", addr);
+        if self.is_zero(pt, addr) {
+            let zero = *ZERO_FRAME;
+            self.cow_ext.lock().unmap_shared(zero, false);
+            pt.unmap(addr);
+            return;
+        }
+        // the page is written, it may be swapped out
+        unsafe {
+            self.swap_ext.lock().remove_from_swappable(pt, inpt as *mut InactivePageTable0, addr, || InactivePageTable0::alloc_frame().expect("alloc frame failed"));
+        }
+        let target = pt.get_entry(addr).expect("fail to get entry").target();
+        InactivePageTable0::dealloc_frame(target);
+        pt.unmap(addr);
+    }
+
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        let page_addr = Page::of_addr(fault.addr).start_address();
+        if !fault.present {
+            if !page_table.get_entry(page_addr).expect("fail to get entry").swapped() {
+                return false;
+            }
+            let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
+            self.swap_ext.lock().swap_in(page_table, inpt as *mut InactivePageTable0, page_addr, frame).ok().unwrap();
+            return true;
+        }
+        // only the first write to a page allocates a frame for it
+        if fault.access != Access::Write || self.flags.is_readonly() || !self.is_zero(page_table, page_addr) {
+            return false;
+        }
+        let (zero, frame) = (*ZERO_FRAME, InactivePageTable0::alloc_frame().expect("alloc frame failed"));
+        self.cow_ext.lock().unmap_shared(zero, false);
+        {
+            let entry = page_table.get_entry(page_addr).expect("fail to get entry");
+            entry.set_target(frame);
+            entry.clear_shared();
+            entry.set_writable(true);
+            entry.update();
+        }
+        unsafe { slice::from_raw_parts_mut(page_addr as *mut u8, PAGE_SIZE) }.iter_mut().for_each(|byte| *byte = 0);
+        unsafe { self.swap_ext.lock().set_swappable(page_table, inpt as *mut InactivePageTable0, page_addr); }
+        true
+    }
+
+    fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+        info!("Come into ZeroMemoryHandler map_clone, the addr is {:x?}", addr);
+        let zero = {
+            let mut temp_table = active_table();
+            self.is_zero(&mut *temp_table, addr)
+        };
+        let handler = &*self;
+        unsafe {
+            let mut page_table = &mut *(inpt as *mut InactivePageTable0);
+            if zero {
+                page_table.edit(|pt| handler.map(pt, inpt, addr));
+                return;
+            }
+            page_table.edit(|pt| {
+                let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
+                handler.flags.apply(pt.map(addr, target));
+                handler.swap_ext.lock().set_swappable(pt, inpt as *mut InactivePageTable0, addr);
+            });
+            // a swapped out page is swapped in by the page fault on reading it
+            let data: Vec<u8> = Vec::from(slice::from_raw_parts(addr as *const u8, PAGE_SIZE));
+            page_table.with(|| {
+                let page_mut = slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE);
+                page_mut.copy_from_slice(data.as_slice());
+            });
+        }
+    }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        Box::new(ZeroMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let zero = self.is_zero(pt, addr);
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+        // keep the zero frame readonly, a write allocates a frame in page_fault_handler
+        if zero {
+            entry.set_writable(false);
+            entry.update();
+        }
+    }
+}
+
+impl ZeroMemoryHandler {
+    pub fn new(cow_ext: Arc<spin::Mutex<CowExt>>, swap_ext: Arc<spin::Mutex<SwapExtType>>, flags: MemoryAttr) -> Self {
+        ZeroMemoryHandler {
+            cow_ext,
+            swap_ext,
+            flags,
+        }
+    }
+
+    /// Whether the page at `addr` is not written yet, and mapped to the zero frame
+    fn is_zero(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        // the readonly shared bit is not available on riscv32, but a written page never maps the zero frame
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        entry.present() && entry.target() == *ZERO_FRAME
+    }
+}
+
+/// The frames of a shared mapping, by the offset in the file, with the number of pages mapping them
+type SharedPages = BTreeMap<usize, (PhysAddr, usize)>;
+
+/// MemoryHandler for mmap, of a file or anonymous memory
+///
+/// Pages are read from the file, or filled with zero if anonymous, on the first access. Pages of a private mapping are never written back,
+/// pages of a shared mapping are written back when unmapped or synced if they are dirty.
+/// The dirty pages are copied with the page table locked, and written to the file in `flush` after it is released.
+/// There is no page cache, so a shared mapping is coherent with the file and other mappings of it
+/// only through these write-backs, except for the mappings forked from it, which map the same frames.
+#[derive(Clone)]
+pub struct MmapMemoryHandler {
+    // None for anonymous memory
+    file: Option<Arc<INode>>,
+    // the virtual address mapped to `offset` of the file
+    start_addr: VirtAddr,
+    offset: usize,
+    flags: MemoryAttr,
+    // frames shared with the mappings forked from this one, None for a private mapping
+    shared: Option<Arc<spin::Mutex<SharedPages>>>,
+    // the contents of the dirty pages to write back by `flush`, by the offset in the file
+    dirty_pages: Arc<spin::Mutex<Vec<(usize, Vec<u8>)>>>,
+}
+
+impl MemoryHandler for MmapMemoryHandler {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new((*self).clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        // map it lazily, the page is read in page_fault_handler
+        {
+            let entry = pt.map(addr, 0);
+            self.flags.apply(entry);
+        }
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        entry.set_present(false);
+        entry.update();
+    }
+
+    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        info!("COME into Mmap unmap, addr is {:x?}", addr);
+        let (present, dirty, target) = {
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            (entry.present(), entry.dirty(), entry.target())
+        };
+        if !present {
+            // the page is not read in yet, set valid for pt.unmap function
+            pt.get_entry(addr).expect("fail to get entry").set_present(true);
+            pt.unmap(addr);
+            return;
+        }
+        match self.shared {
+            Some(ref shared) => {
+                if dirty {
+                    unsafe { (*(inpt as *mut InactivePageTable0)).with(|| self.save_dirty_page(addr)); }
+                }
+                let offset = self.file_offset(addr);
+                let last = {
+                    let mut pages = shared.lock();
+                    let last = match pages.get_mut(&offset) {
+                        Some(&mut (_, ref mut count)) => { *count -= 1; *count == 0 }
+                        None => true,
+                    };
+                    if last {
+                        pages.remove(&offset);
+                    }
+                    last
+                };
+                if last {
+                    InactivePageTable0::dealloc_frame(target);
+                }
+            }
+            None => InactivePageTable0::dealloc_frame(target),
+        }
+        pt.unmap(addr);
+    }
+
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        if fault.present {
+            return false;
+        }
+        let page_addr = Page::of_addr(fault.addr).start_address();
+        let offset = self.file_offset(page_addr);
+        // the frame read in by another mapping forked from this one
+        if let Some(ref shared) = self.shared {
+            if let Some(&mut (target, ref mut count)) = shared.lock().get_mut(&offset) {
+                *count += 1;
+                let entry = page_table.get_entry(page_addr).expect("fail to get entry");
+                entry.set_target(target);
+                self.flags.apply(entry);
+                return true;
+            }
+        }
+        let frame = InactivePageTable0::alloc_frame().expect("alloc frame failed");
+        {
+            // writable for the kernel to fill the page
+            let entry = page_table.get_entry(page_addr).expect("fail to get entry");
+            entry.set_target(frame);
+            entry.set_present(true);
+            entry.set_writable(true);
+            entry.update();
+        }
+        self.read_in(page_addr);
+        {
+            let entry = page_table.get_entry(page_addr).expect("fail to get entry");
+            self.flags.apply(entry);
+            entry.clear_dirty();
+            entry.update();
+        }
+        if let Some(ref shared) = self.shared {
+            shared.lock().insert(offset, (frame, 1));
+        }
+        true
+    }
+
+    fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+        info!("Come into MmapMemoryHandler map_clone, the addr is {:x?}", addr);
+        let (present, target) = {
+            let mut temp_table = active_table();
+            let entry = temp_table.get_entry(addr).expect("fail to get entry");
+            (entry.present(), entry.target())
+        };
+        let offset = self.file_offset(addr);
+        let Self { ref shared, ref flags, .. } = self;
+        unsafe {
+            let mut page_table = &mut *(inpt as *mut InactivePageTable0);
+            if !present {
+                page_table.edit(|pt| {
+                    {
+                        let entry = pt.map(addr, 0);
+                        flags.apply(entry);
+                    }
+                    let entry = pt.get_entry(addr).expect("fail to get entry");
+                    entry.set_present(false);
+                    entry.update();
+                });
+                return;
+            }
+            match shared {
+                Some(shared) => {
+                    if let Some(&mut (_, ref mut count)) = shared.lock().get_mut(&offset) {
+                        *count += 1;
+                    }
+                    page_table.edit(|pt| flags.apply(pt.map(addr, target)));
+                }
+                None => {
+                    page_table.edit(|pt| {
+                        let target = InactivePageTable0::alloc_frame().expect("failed to allocate frame");
+                        flags.apply(pt.map(addr, target));
+                    });
+                    let data: Vec<u8> = Vec::from(slice::from_raw_parts(addr as *const u8, PAGE_SIZE));
+                    page_table.with(|| {
+                        let page_mut = slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE);
+                        page_mut.copy_from_slice(data.as_slice());
+                    });
+                }
+            }
+        }
+    }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        // both parts keep the same start_addr, so the file offset of each page is unchanged
+        Box::new(MmapMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+    }
+
+    fn sync(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        if self.shared.is_none() {
+            return;
+        }
+        let need_write = {
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            entry.present() && entry.dirty()
+        };
+        if need_write {
+            unsafe { (*(inpt as *mut InactivePageTable0)).with(|| self.save_dirty_page(addr)); }
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            entry.clear_dirty();
+            entry.update();
+        }
+    }
+
+    fn flush(&self) {
+        let pages = mem::replace(&mut *self.dirty_pages.lock(), Vec::new());
+        for (offset, data) in pages {
+            self.write_back(offset, &data);
+        }
+    }
+
+    fn mergeable(&self) -> bool {
+        // the areas of different mappings are not told apart by their attributes
+        false
+    }
+}
+
+impl MmapMemoryHandler {
+    /*
+    * @param:
+    *   file: the inode of the file to map, None for anonymous memory
+    *   start_addr: the virtual address of the beginning of the mapping
+    *   offset: the page aligned offset in the file mapped at `start_addr`
+    *   flags: the attributes of the mapping
+    *   shared: whether the changes are written back to the file, and shared with forked processes
+    * @retval:
+    *   the handler of the mapping
+    */
+    pub fn new(file: Option<Arc<INode>>, start_addr: VirtAddr, offset: usize, flags: MemoryAttr, shared: bool) -> Self {
+        MmapMemoryHandler {
+            file,
+            start_addr,
+            offset,
+            flags,
+            shared: if shared { Some(Arc::new(spin::Mutex::new(SharedPages::new()))) } else { None },
+            dirty_pages: Arc::new(spin::Mutex::new(Vec::new())),
+        }
+    }
+
+    fn file_offset(&self, addr: VirtAddr) -> usize {
+        addr - self.start_addr + self.offset
+    }
+
+    /// The part of the page at `offset` of the file inside the file
+    fn file_len(&self, offset: usize) -> usize {
+        let size = match self.file {
+            Some(ref file) => file.info().map(|info| info.size).unwrap_or(0),
+            None => 0,
+        };
+        size.saturating_sub(offset).min(PAGE_SIZE)
+    }
+
+    /*
+    * @param:
+    *   addr: the virtual address of the page, mapped writable in the current page table
+    * @brief:
+    *   read the page from the file, the part beyond the end of the file is filled with zero
+    */
+    fn read_in(&self, addr: VirtAddr) {
+        let len = self.file_len(self.file_offset(addr));
+        let page_mut = unsafe { slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) };
+        let read = match self.file {
+            Some(ref file) if len != 0 => file.read_at(self.file_offset(addr), &mut page_mut[..len]).unwrap_or(0),
+            _ => 0,
+        };
+        for byte in page_mut[read..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    /*
+    * @param:
+    *   addr: the virtual address of the page, mapped in the current page table
+    * @brief:
+    *   copy the page to write it back by `flush`, since the file can not be written with the page table locked
+    */
+    fn save_dirty_page(&self, addr: VirtAddr) {
+        if self.file.is_none() {
+            return;
+        }
+        let page = unsafe { slice::from_raw_parts(addr as *const u8, PAGE_SIZE) };
+        self.dirty_pages.lock().push((self.file_offset(addr), page.to_vec()));
+    }
+
+    /*
+    * @param:
+    *   offset: the offset of the page in the file
+    *   data: the content of the page
+    * @brief:
+    *   write the page back to the file, the file is never extended
+    */
+    fn write_back(&self, offset: usize, data: &[u8]) {
+        let len = self.file_len(offset);
+        if len == 0 {
+            return;
+        }
+        let file = self.file.as_ref().expect("no file to write back");
+        if file.write_at(offset, &data[..len]).is_err() {
+            warn!("fail to write back the page at offset {:#x} to the mapped file", offset);
+        }
+    }
+}
+
+/// MemoryHandler for shared memory segments
+///
+/// The pages are mapped to the frames of the segment, in all processes mapping it and forked from them.
+/// A page is mapped lazily, as the frame may be allocated by the first access from another process.
+#[derive(Clone)]
+pub struct SharedMemoryHandler {
+    segment: Arc<ShmSegment>,
+    // the virtual address mapped to the beginning of the segment
+    start_addr: VirtAddr,
+    flags: MemoryAttr,
+}
+
+impl MemoryHandler for SharedMemoryHandler {
+    fn box_clone(&self) -> Box<MemoryHandler> {
+        Box::new((*self).clone())
+    }
+
+    fn map(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        {
+            let entry = pt.map(addr, 0);
+            self.flags.apply(entry);
+        }
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        entry.set_present(false);
+        entry.update();
+    }
+
+    fn unmap(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        info!("COME into Shared unmap, addr is {:x?}", addr);
+        let (present, target) = {
+            let entry = pt.get_entry(addr).expect("fail to get entry");
+            (entry.present(), entry.target())
+        };
+        if !present {
+            // the page is not accessed yet, set valid for pt.unmap function
+            pt.get_entry(addr).expect("fail to get entry").set_present(true);
+        } else if self.segment.unmap_frame(target) {
+            InactivePageTable0::dealloc_frame(target);
+        }
+        pt.unmap(addr);
+    }
+
+    fn page_fault_handler(&self, page_table: &mut PageTable, inpt: usize, fault: PageFault) -> bool {
+        if fault.present {
+            return false;
+        }
+        let page_addr = Page::of_addr(fault.addr).start_address();
+        let index = (page_addr - self.start_addr) / PAGE_SIZE;
+        let (target, new) = self.segment.map_frame(index, || InactivePageTable0::alloc_frame().expect("alloc frame failed"));
+        {
+            // writable for the kernel to clear a new frame
+            let entry = page_table.get_entry(page_addr).expect("fail to get entry");
+            entry.set_target(target);
+            entry.set_present(true);
+            entry.set_writable(true);
+            entry.update();
+        }
+        if new {
+            unsafe { slice::from_raw_parts_mut(page_addr as *mut u8, PAGE_SIZE) }.iter_mut().for_each(|byte| *byte = 0);
+        }
+        self.flags.apply(page_table.get_entry(page_addr).expect("fail to get entry"));
+        true
+    }
+
+    fn map_clone(&mut self, inpt: usize, addr: VirtAddr) {
+        info!("Come into SharedMemoryHandler map_clone, the addr is {:x?}", addr);
+        let (present, target) = {
+            let mut temp_table = active_table();
+            let entry = temp_table.get_entry(addr).expect("fail to get entry");
+            (entry.present(), entry.target())
+        };
+        if present {
+            self.segment.share_frame(target);
+        }
+        let Self { ref flags, .. } = self;
+        let page_table = unsafe { &mut *(inpt as *mut InactivePageTable0) };
+        page_table.edit(|pt| {
+            {
+                let entry = pt.map(addr, target);
+                flags.apply(entry);
+            }
+            if !present {
+                let entry = pt.get_entry(addr).expect("fail to get entry");
+                entry.set_present(false);
+                entry.update();
+            }
+        });
+    }
+
+    fn get_flags(&self) -> MemoryAttr {
+        self.flags
+    }
+
+    fn box_split(&self, flags: MemoryAttr) -> Box<MemoryHandler> {
+        // both parts keep the same start_addr, so the index of each page is unchanged
+        Box::new(SharedMemoryHandler { flags, ..self.clone() })
+    }
+
+    fn protect(&self, pt: &mut PageTable, inpt: usize, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("fail to get entry");
+        self.flags.apply_protection(entry);
+    }
+
+    fn mergeable(&self) -> bool {
+        // the areas of different segments are not told apart by their attributes
+        false
+    }
+}
+
+impl SharedMemoryHandler {
+    /*
+    * @param:
+    *   segment: the shared memory segment to map
+    *   start_addr: the virtual address of the beginning of the segment
+    *   flags: the attributes of the mapping
+    * @retval:
+    *   the handler of the mapping
+    */
+    pub fn new(segment: Arc<ShmSegment>, start_addr: VirtAddr, flags: MemoryAttr) -> Self {
+        SharedMemoryHandler { segment, start_addr, flags }
     }
 }
@@ -1,13 +1,14 @@
 use arch::interrupt::{TrapFrame, Context as ArchContext};
-use memory::{MemoryArea, MemoryAttr, MemorySet, KernelStack, swap_table, alloc_frame, active_table, NormalMemoryHandler, SwapMemoryHandler, CowMemoryHandler, SWAP_TABLE, COW_TABLE};
+use memory::{MemoryArea, MemoryAttr, MemorySet, KernelStack, swap_table, alloc_frame, active_table, NormalMemoryHandler, SwapMemoryHandler, CowMemoryHandler, ZeroMemoryHandler, SWAP_TABLE, COW_TABLE};
 use xmas_elf::{ElfFile, header, program::{Flags, ProgramHeader, Type}};
 use core::fmt::{Debug, Error, Formatter};
+use core::mem::size_of;
 use ucore_process::Context;
 use alloc::{boxed::Box, collections::BTreeMap, vec::Vec, sync::Arc, string::String};
-use ucore_memory::{Page, VirtAddr};
+use ucore_memory::{Page, VirtAddr, PAGE_SIZE};
 use ::memory::{InactivePageTable0};
 use ucore_memory::memory_set::*;
-use simple_filesystem::file::File;
+use fs::File;
 use spin::Mutex;
 
 
@@ -19,12 +20,23 @@ pub struct ContextImpl {
     pub kstack: KernelStack,
     pub files: BTreeMap<usize, Arc<Mutex<File>>>,
     pub cwd: String,
+    /// the beginning of the user heap, after the highest loaded segment
+    pub heap_start: VirtAddr,
+    /// the end of the user heap, changed by brk
+    pub brk: VirtAddr,
+    /// the top of the user stack, 0 if no user stack
+    pub stack_top: VirtAddr,
+    /// the maximum size of the user stack, it grows down on page fault until then
+    pub stack_limit: usize,
 }
 
 impl Context for ContextImpl {
     unsafe fn switch_to(&mut self, target: &mut Context) {
         use core::mem::transmute;
         let (target, _): (&mut ContextImpl, *const ()) = transmute(target);
+        // the page table is switched in `switch`, with the ASID allocated for it now
+        let token = target.memory_set.get_page_table_mut().switch_token();
+        target.arch.set_page_table(token);
         self.arch.switch(&mut target.arch);
     }
 }
@@ -37,6 +49,10 @@ impl ContextImpl {
             kstack: KernelStack::new(),
             files: BTreeMap::default(),
             cwd: String::new(),
+            heap_start: 0,
+            brk: 0,
+            stack_top: 0,
+            stack_limit: 0,
         })
     }
 
@@ -49,6 +65,10 @@ impl ContextImpl {
             kstack,
             files: BTreeMap::default(),
             cwd: String::new(),
+            heap_start: 0,
+            brk: 0,
+            stack_top: 0,
+            stack_limit: 0,
         })
     }
 
@@ -75,15 +95,20 @@ impl ContextImpl {
         };
         assert_eq!(elf.header.pt2.type_().as_type(), header::Type::Executable, "ELF is not executable");
 
-        // User stack
+        // User stack, growing down from the top on page fault, up to USER_STACK_SIZE
         use consts::{USER_STACK_OFFSET, USER_STACK_SIZE, USER32_STACK_OFFSET};
-        let (ustack_buttom, mut ustack_top) = match is32 {
-            true => (USER32_STACK_OFFSET, USER32_STACK_OFFSET + USER_STACK_SIZE),
-            false => (USER_STACK_OFFSET, USER_STACK_OFFSET + USER_STACK_SIZE),
+        let stack_top = match is32 {
+            true => USER32_STACK_OFFSET + USER_STACK_SIZE,
+            false => USER_STACK_OFFSET + USER_STACK_SIZE,
         };
+        let mut ustack_top = stack_top;
+        // the arguments are pushed before the process runs, so their pages are mapped at first
+        let args: Vec<&'a str> = args.collect();
+        let args_size = args.iter().map(|arg| arg.len() + 1).sum::<usize>() + (args.len() + 2) * size_of::<usize>();
+        let ustack_buttom = stack_top - (args_size / PAGE_SIZE + 1) * PAGE_SIZE;
 
         // Make page table
-        let mut memory_set = memory_set_from(&elf);
+        let (mut memory_set, heap_start) = memory_set_from(&elf);
 
         // add the new memory set to the recorder
         //let mmset_ptr = Box::leak(memory_set) as *mut MemorySet as usize;
@@ -91,21 +116,10 @@ impl ContextImpl {
         //let id = memory_set_record().iter()
         //    .position(|x| unsafe { info!("current memory set record include {:x?}, {:x?}", x, (*(x.clone() as *mut MemorySet)).get_page_table_mut().token()); false });
 
-        // add the pages for memory delay allocated
-        let mut delay_vec = Vec::<VirtAddr>::new();
-        //delay_vec.push(ustack_top & & 0xfffff000);
-        for page in Page::range_of(ustack_buttom, ustack_top) {
-            let addr = page.start_address();
-            if addr != Page::of_addr(ustack_top - 1).start_address(){
-                //info!("delay_vec addr {:x?}", addr);
-                delay_vec.push(addr);
-            }
-        }
-        //info!("ustack_top is {:x?} start_address is {:x?}", ustack_top, Page::of_addr(ustack_top - 1).start_address());
-        // for SwapMemoryHandler
-        memory_set.push(MemoryArea::new(ustack_buttom, ustack_top, Box::new(SwapMemoryHandler::new(SWAP_TABLE.clone(), MemoryAttr::default().user(), delay_vec)), "user_stack"));
+        // for SwapMemoryHandler, pages are allocated when mapped, including the ones the stack grows to
+        memory_set.push(MemoryArea::new(ustack_buttom, ustack_top, Box::new(SwapMemoryHandler::new(SWAP_TABLE.clone(), MemoryAttr::default().user())), "user_stack"));
         // for CowMemoryHandler
-        //memory_set.push(MemoryArea::new(ustack_buttom, ustack_top, Box::new(CowMemoryHandler::new(COW_TABLE.clone(), MemoryAttr::default().user())), "user_stack"));
+        //memory_set.push(MemoryArea::new(ustack_buttom, ustack_top, Box::new(CowMemoryHandler::new(COW_TABLE.clone(), SWAP_TABLE.clone(), MemoryAttr::default().user())), "user_stack"));
         //trace!("{:#x?}", memory_set);
 
         let entry_addr = elf.header.pt2.entry_point() as usize;
@@ -119,13 +133,15 @@ impl ContextImpl {
                     let file_size = ph.file_size() as usize;
                     let mem_size = ph.mem_size() as usize;
 
-                    let target = unsafe { ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, mem_size) };
+                    // the rest is mapped to the zero frame, and must not be written here
+                    let size = file_backed_size(virt_addr, file_size, mem_size);
+                    let target = unsafe { ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, size) };
                     if file_size != 0 {
                         target[..file_size].copy_from_slice(&data[offset..offset + file_size]);
                     }
                     target[file_size..].iter_mut().for_each(|x| *x = 0);
                 }
-                ustack_top = push_args_at_stack(args, ustack_top);
+                ustack_top = push_args_at_stack(args.into_iter(), ustack_top);
             });
         }
 
@@ -147,6 +163,10 @@ impl ContextImpl {
             kstack,
             files: BTreeMap::default(),
             cwd: String::new(),
+            heap_start,
+            brk: heap_start,
+            stack_top,
+            stack_limit: USER_STACK_SIZE,
         });
         //set the user Memory pages in the memory set swappable
         //memory_set_map_swappable(ret.get_memory_set_mut());
@@ -205,6 +225,10 @@ impl ContextImpl {
             kstack,
             files: BTreeMap::default(),
             cwd: String::new(),
+            heap_start: self.heap_start,
+            brk: self.brk,
+            stack_top: self.stack_top,
+            stack_limit: self.stack_limit,
         });
 
         //memory_set_map_swappable(ret.get_memory_set_mut());
@@ -283,28 +307,52 @@ unsafe fn push_args_at_stack<'a, Iter>(args: Iter, stack_top: usize) -> usize
 * @brief:
 *   generate a memory set according to the elf file
 * @retval:
-*   the new memory set
+*   the new memory set, and the page aligned end of the highest loaded segment
 */
-fn memory_set_from<'a>(elf: &'a ElfFile<'a>) -> Box<MemorySet> {
+fn memory_set_from<'a>(elf: &'a ElfFile<'a>) -> (Box<MemorySet>, VirtAddr) {
     debug!("come in to memory_set_from");
     let mut set = Box::new(MemorySet::new());
+    let mut end = 0;
     //let pt_ptr = set.get_page_table_mut() as *mut InactivePageTable0;
     for ph in elf.program_iter() {
         if ph.get_type() != Ok(Type::Load) {
             continue;
         }
-        let (virt_addr, mem_size, flags) = match ph {
-            ProgramHeader::Ph32(ph) => (ph.virtual_addr as usize, ph.mem_size as usize, ph.flags),
-            ProgramHeader::Ph64(ph) => (ph.virtual_addr as usize, ph.mem_size as usize, ph.flags),
+        let (virt_addr, file_size, mem_size, flags) = match ph {
+            ProgramHeader::Ph32(ph) => (ph.virtual_addr as usize, ph.file_size as usize, ph.mem_size as usize, ph.flags),
+            ProgramHeader::Ph64(ph) => (ph.virtual_addr as usize, ph.file_size as usize, ph.mem_size as usize, ph.flags),
         };
         info!("virtaddr: {:x?}, memory size: {:x?}, flags: {}", virt_addr, mem_size, flags);
         // for SwapMemoryHandler
-        //set.push(MemoryArea::new(virt_addr, virt_addr + mem_size, Box::new(SwapMemoryHandler::new(SWAP_TABLE.clone(), memory_attr_from(flags), Vec::<VirtAddr>::new())), ""));
+        //set.push(MemoryArea::new(virt_addr, virt_addr + mem_size, Box::new(SwapMemoryHandler::new(SWAP_TABLE.clone(), memory_attr_from(flags))), ""));
         // for CowMemoryHandler
-        set.push(MemoryArea::new(virt_addr, virt_addr + mem_size, Box::new(CowMemoryHandler::new(COW_TABLE.clone(), memory_attr_from(flags))), ""));
-
+        let size = file_backed_size(virt_addr, file_size, mem_size);
+        if size != 0 {
+            set.push(MemoryArea::new(virt_addr, virt_addr + size, Box::new(CowMemoryHandler::new(COW_TABLE.clone(), SWAP_TABLE.clone(), memory_attr_from(flags))), ""));
+        }
+        // BSS costs no memory until written
+        if size != mem_size {
+            set.push(MemoryArea::new(virt_addr + size, virt_addr + mem_size, Box::new(ZeroMemoryHandler::new(COW_TABLE.clone(), SWAP_TABLE.clone(), memory_attr_from(flags))), "bss"));
+        }
+        end = end.max(virt_addr + mem_size);
     }
-    set
+    (set, Page::of_addr(end + PAGE_SIZE - 1).start_address())
+}
+
+/*
+* @param:
+*   virt_addr: the virtual address of the segment
+*   file_size: the size of the segment in the ELF file
+*   mem_size: the size of the segment in memory
+* @brief:
+*   get the size of the part of the segment loaded from the file, up to the end of its last page,
+*   the rest is demand-zero memory
+* @retval:
+*   the size of the part loaded from the file
+*/
+fn file_backed_size(virt_addr: VirtAddr, file_size: usize, mem_size: usize) -> usize {
+    let end = Page::of_addr(virt_addr + file_size + PAGE_SIZE - 1).start_address();
+    (end - virt_addr).min(mem_size)
 }
 
 fn memory_attr_from(elf_flags: Flags) -> MemoryAttr {
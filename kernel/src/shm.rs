@@ -0,0 +1,125 @@
+//! Shared memory segments
+//!
+//! A segment is a list of frames named by a key, mapped into processes by `SharedMemoryHandler`.
+//! Frames are allocated on the first access from any process, and counted in `CowExt` as
+//! writable shared frames: one reference for each page mapping it, and one for the segment itself.
+//! A segment lives as long as it is mapped somewhere, including in forked processes,
+//! then its frames are freed and the key can name a new segment.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin;
+use ucore_memory::cow::CowExt;
+use ucore_memory::{PhysAddr, PAGE_SIZE};
+use memory::{InactivePageTable0, InactivePageTable, COW_TABLE};
+use sync::SpinNoIrqLock;
+
+/// A shared memory segment
+pub struct ShmSegment {
+    key: usize,
+    /// the frame of each page, None if not accessed yet
+    frames: spin::Mutex<Vec<Option<PhysAddr>>>,
+    cow_ext: Arc<spin::Mutex<CowExt>>,
+}
+
+lazy_static! {
+    // segments by key, dead ones are replaced when the key is used again
+    static ref SEGMENTS: SpinNoIrqLock<BTreeMap<usize, Weak<ShmSegment>>> = SpinNoIrqLock::new(BTreeMap::new());
+}
+
+/*
+* @param:
+*   key: the name of the segment
+*   len: the page aligned length of the segment to create
+* @brief:
+*   get the segment named `key`, or create it with `len` bytes if it does not exist
+* @retval:
+*   the segment, None if the existing one is shorter than `len`
+*/
+pub fn get_or_create(key: usize, len: usize) -> Option<Arc<ShmSegment>> {
+    let mut segments = SEGMENTS.lock();
+    if let Some(segment) = segments.get(&key).and_then(|segment| segment.upgrade()) {
+        return match segment.len() >= len {
+            true => Some(segment),
+            false => None,
+        };
+    }
+    let segment = Arc::new(ShmSegment {
+        key,
+        frames: spin::Mutex::new(vec![None; len / PAGE_SIZE]),
+        cow_ext: COW_TABLE.clone(),
+    });
+    segments.insert(key, Arc::downgrade(&segment));
+    info!("shmem: create segment {:#x} of {:#x} bytes", key, len);
+    Some(segment)
+}
+
+impl ShmSegment {
+    /// The length of the segment in bytes
+    pub fn len(&self) -> usize {
+        self.frames.lock().len() * PAGE_SIZE
+    }
+
+    /*
+    * @param:
+    *   index: the index of the page in the segment
+    *   alloc: called to allocate the frame if the page is not accessed yet
+    * @brief:
+    *   get the frame of a page, and add a reference to it for the page mapping it
+    * @retval:
+    *   the frame, and whether it is newly allocated by `alloc`
+    */
+    pub fn map_frame(&self, index: usize, alloc: impl FnOnce() -> PhysAddr) -> (PhysAddr, bool) {
+        let mut frames = self.frames.lock();
+        if let Some(target) = frames[index] {
+            self.cow_ext.lock().map_to_shared(target, true);
+            return (target, false);
+        }
+        // allocate without locking CowExt, since it may swap out a shared frame
+        let target = alloc();
+        {
+            // one reference for the segment, one for the page
+            let mut cow_ext = self.cow_ext.lock();
+            cow_ext.map_to_shared(target, true);
+            cow_ext.map_to_shared(target, true);
+        }
+        frames[index] = Some(target);
+        (target, true)
+    }
+
+    /*
+    * @param:
+    *   target: the frame of a page in the segment, mapped by a page
+    * @brief:
+    *   add a reference to the frame for another page mapping it, e.g. in a forked process
+    */
+    pub fn share_frame(&self, target: PhysAddr) {
+        self.cow_ext.lock().map_to_shared(target, true);
+    }
+
+    /*
+    * @param:
+    *   target: the frame of a page in the segment
+    * @brief:
+    *   remove the reference of a page unmapping the frame
+    * @retval:
+    *   whether it is the last reference, then the frame should be freed
+    */
+    pub fn unmap_frame(&self, target: PhysAddr) -> bool {
+        self.cow_ext.lock().unmap_shared(target, true)
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        info!("shmem: free segment {:#x}", self.key);
+        let frames = self.frames.lock();
+        let mut cow_ext = self.cow_ext.lock();
+        for &target in frames.iter().filter_map(|frame| frame.as_ref()) {
+            if cow_ext.unmap_shared(target, true) {
+                InactivePageTable0::dealloc_frame(target);
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Swap device
+//!
+//! Swapped out pages are written to a block device, such as the third IDE drive on x86_64,
+//! or a RAM disk on riscv32. Each page takes one slot of `PAGE_SIZE` bytes on the device,
+//! and the free slots are kept in a bitmap.
+//!
+//! If no such device is present, fall back to `MockSwapper` which keeps pages on the heap.
+
+use alloc::boxed::Box;
+use bit_allocator::{BitAlloc, BitAlloc64K};
+use simple_filesystem::BlockedDevice;
+use ucore_memory::swap::{Swapper, mock_swapper::MockSwapper};
+use ucore_memory::PAGE_SIZE;
+#[cfg(target_arch = "x86_64")]
+use arch::driver::ide;
+
+/// The IDE drive used for swap: the master of the secondary channel
+#[cfg(target_arch = "x86_64")]
+const SWAP_IDE: u8 = 2;
+
+/// The number of pages of the RAM disk used for swap
+#[cfg(target_arch = "riscv32")]
+const SWAP_RAMDISK_PAGES: usize = 256;
+
+/*
+* @brief:
+*   probe the swap device, create the swapper on it
+* @retval:
+*   the swapper on the swap device if present, otherwise the mock swapper
+*/
+pub fn swapper() -> Box<Swapper + Send> {
+    #[cfg(target_arch = "x86_64")]
+    let device = ide::IDE::probe(SWAP_IDE).map(|(ide, sectors)| (ide, sectors / (PAGE_SIZE / ide::BLOCK_SIZE)));
+    #[cfg(target_arch = "riscv32")]
+    let device = RamDisk::new(SWAP_RAMDISK_PAGES).map(|disk| (disk, SWAP_RAMDISK_PAGES));
+
+    match device {
+        Some((device, slot_num)) if slot_num != 0 => {
+            info!("swap device: {} pages", slot_num);
+            Box::new(DiskSwapper::new(device, slot_num))
+        }
+        _ => {
+            warn!("no swap device, swap to the heap");
+            Box::new(MockSwapper::default())
+        }
+    }
+}
+
+/// Swapper writing pages to slots of a block device
+pub struct DiskSwapper<D: BlockedDevice> {
+    device: D,
+    /// free slots
+    slots: Box<BitAlloc64K>,
+}
+
+impl<D: BlockedDevice> DiskSwapper<D> {
+    /*
+    * @param:
+    *   device: the block device
+    *   slot_num: the number of pages the device can hold
+    * @retval:
+    *   the swapper using the first `slot_num` pages of the device
+    */
+    pub fn new(device: D, slot_num: usize) -> Self {
+        assert!(PAGE_SIZE >> D::BLOCK_SIZE_LOG2 != 0, "block size should not exceed the page size");
+        let mut slots = Box::new(BitAlloc64K::default());
+        slots.insert(0..slot_num.min(BitAlloc64K::CAP));
+        DiskSwapper { device, slots }
+    }
+
+    /// Write a page to the slot `slot`
+    fn write_slot(&mut self, slot: usize, data: &[u8]) -> Result<(), ()> {
+        assert_eq!(data.len(), PAGE_SIZE);
+        let block_size = 1 << D::BLOCK_SIZE_LOG2;
+        let first = slot * (PAGE_SIZE / block_size);
+        for (i, block) in data.chunks(block_size).enumerate() {
+            if !self.device.write_at(first + i, block) {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a page from the slot `slot`
+    fn read_slot(&mut self, slot: usize, data: &mut [u8]) -> Result<(), ()> {
+        assert_eq!(data.len(), PAGE_SIZE);
+        let block_size = 1 << D::BLOCK_SIZE_LOG2;
+        let first = slot * (PAGE_SIZE / block_size);
+        for (i, block) in data.chunks_mut(block_size).enumerate() {
+            if !self.device.read_at(first + i, block) {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the slot `slot` is in use
+    fn used(&self, slot: usize) -> bool {
+        slot < BitAlloc64K::CAP && !self.slots.test(slot)
+    }
+}
+
+impl<D: BlockedDevice> Swapper for DiskSwapper<D> {
+    fn swap_out(&mut self, data: &[u8]) -> Result<usize, ()> {
+        let slot = self.slots.alloc().ok_or(())?;
+        if self.write_slot(slot, data).is_err() {
+            self.slots.dealloc(slot);
+            return Err(());
+        }
+        Ok(slot)
+    }
+    fn swap_update(&mut self, token: usize, data: &[u8]) -> Result<(), ()> {
+        if !self.used(token) {
+            return Err(());
+        }
+        self.write_slot(token, data)
+    }
+    fn swap_in(&mut self, token: usize, data: &mut [u8]) -> Result<(), ()> {
+        if !self.used(token) {
+            return Err(());
+        }
+        self.read_slot(token, data)?;
+        self.slots.dealloc(token);
+        Ok(())
+    }
+    fn swap_discard(&mut self, token: usize) -> Result<(), ()> {
+        if !self.used(token) {
+            return Err(());
+        }
+        self.slots.dealloc(token);
+        Ok(())
+    }
+}
+
+/// RAM disk on pages of the kernel heap area, one block per page
+#[cfg(target_arch = "riscv32")]
+pub struct RamDisk {
+    base: usize,
+    pages: usize,
+}
+
+#[cfg(target_arch = "riscv32")]
+impl RamDisk {
+    /// Allocate a RAM disk of `pages` pages
+    pub fn new(pages: usize) -> Option<Self> {
+        let base = ::memory::alloc_kernel_pages(pages, 0)?;
+        Some(RamDisk { base, pages })
+    }
+    fn block(&self, block_id: usize) -> Option<&'static mut [u8]> {
+        use core::slice;
+        if block_id >= self.pages {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts_mut((self.base + block_id * PAGE_SIZE) as *mut u8, PAGE_SIZE) })
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+impl BlockedDevice for RamDisk {
+    const BLOCK_SIZE_LOG2: u8 = 12;
+    fn read_at(&mut self, block_id: usize, buf: &mut [u8]) -> bool {
+        match self.block(block_id) {
+            Some(block) => { buf[..PAGE_SIZE].copy_from_slice(block); true }
+            None => false,
+        }
+    }
+    fn write_at(&mut self, block_id: usize, buf: &[u8]) -> bool {
+        match self.block(block_id) {
+            Some(block) => { block.copy_from_slice(&buf[..PAGE_SIZE]); true }
+            None => false,
+        }
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+impl Drop for RamDisk {
+    fn drop(&mut self) {
+        ::memory::dealloc_kernel_pages(self.base, self.pages);
+    }
+}
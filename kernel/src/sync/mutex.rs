@@ -247,6 +247,8 @@ impl MutexSupport for SpinNoIrq {
         SpinNoIrq
     }
     fn cpu_relax(&self) {
+        // interrupts are disabled here, flush the TLB for a shootdown which may wait for the lock holder
+        ::tlb::poll();
         unsafe {
             #[cfg(target_arch = "x86_64")]
                 asm!("pause" :::: "volatile");
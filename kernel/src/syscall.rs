@@ -3,10 +3,14 @@
 use arch::interrupt::TrapFrame;
 use process::*;
 use thread;
-use util;
-use simple_filesystem::{INode, file::File, FileInfo, FileType};
-use core::{slice, str};
+use memory::{self, MemInfo, MemoryArea, MemoryAttr, MemoryHandler, HugePageMemoryHandler, MmapMemoryHandler, SharedMemoryHandler, ZeroMemoryHandler, COW_TABLE, SWAP_TABLE};
+use consts::{USER_MMAP_OFFSET, USER_MMAP_END, HUGE_PAGE_SIZE};
+use ucore_memory::PAGE_SIZE;
+use simple_filesystem::{INode, FileInfo, FileType};
+use fs::File;
+use user_ptr::{UserPtr, UserSlice, UserCStr};
 use alloc::sync::Arc;
+use alloc::boxed::Box;
 use spin::Mutex;
 use alloc::vec::Vec;
 use alloc::string::String;
@@ -41,10 +45,14 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> i32 {
         255 => sys_lab6_set_priority(args[0]),
 
         // memory
-//        020 => sys_mmap(),
-//        021 => sys_munmap(),
-//        022 => sys_shmem(),
-//        031 => sys_pgdir(),
+        020 => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        021 => sys_munmap(args[0], args[1]),
+        022 => sys_shmem(args[0], args[1], args[2]),
+        023 => sys_meminfo(args[0] as *mut MemInfo),
+        024 => sys_mprotect(args[0], args[1], args[2]),
+        025 => sys_msync(args[0], args[1]),
+        026 => sys_brk(args[0]),
+        031 => sys_pgdir(),
 
         _ => {
             error!("unknown syscall id: {:#x?}, args: {:x?}", id, args);
@@ -58,24 +66,21 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> i32 {
 }
 
 fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    // TODO: check ptr
     info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
-    let slice = unsafe { slice::from_raw_parts_mut(base, len) };
+    let slice = UserSlice::new(base, len).as_mut_slice()?;
     let len = get_file(fd)?.lock().read(slice)?;
     Ok(len as i32)
 }
 
 fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    // TODO: check ptr
     info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
-    let slice = unsafe { slice::from_raw_parts(base, len) };
+    let slice = UserSlice::new(base, len).as_slice()?;
     let len = get_file(fd)?.lock().write(slice)?;
     Ok(len as i32)
 }
 
 fn sys_open(path: *const u8, flags: usize) -> SysResult {
-    // TODO: check ptr
-    let path = unsafe { util::from_cstr(path) };
+    let path = UserCStr::new(path).as_str()?;
     let flags = VfsFlags::from_ucore_flags(flags);
     info!("open: path: {:?}, flags: {:?}", path, flags);
     let (fd, inode) = match path {
@@ -101,11 +106,10 @@ fn sys_close(fd: usize) -> SysResult {
 }
 
 fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
-    // TODO: check ptr
     info!("fstat: {}", fd);
     let file = get_file(fd)?;
     let stat = Stat::from(file.lock().info()?);
-    unsafe { stat_ptr.write(stat); }
+    UserPtr::new(stat_ptr).write(stat)?;
     Ok(0)
 }
 
@@ -113,10 +117,9 @@ fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
 /// dentry.name = entry_name
 /// dentry.offset += 256
 fn sys_getdirentry(fd: usize, dentry_ptr: *mut DirEntry) -> SysResult {
-    // TODO: check ptr
     info!("getdirentry: {}", fd);
     let file = get_file(fd)?;
-    let dentry = unsafe { &mut *dentry_ptr };
+    let dentry = UserPtr::new(dentry_ptr).as_mut()?;
     if !dentry.check() {
         return Err(SysError::InvalidArgument);
     }
@@ -150,7 +153,6 @@ fn sys_fork(tf: &TrapFrame) -> SysResult {
 /// Wait the process exit.
 /// Return the PID. Store exit code to `code` if it's not null.
 fn sys_wait(pid: usize, code: *mut i32) -> SysResult {
-    // TODO: check ptr
     loop {
         let wait_procs = match pid {
             0 => processor().manager().get_children(thread::current().id()),
@@ -163,7 +165,7 @@ fn sys_wait(pid: usize, code: *mut i32) -> SysResult {
             match processor().manager().get_status(pid) {
                 Some(Status::Exited(exit_code)) => {
                     if !code.is_null() {
-                        unsafe { code.write(exit_code as i32); }
+                        UserPtr::new(code).write(exit_code as i32)?;
                     }
                     processor().manager().remove(pid);
                     info!("wait: {} -> {}", thread::current().id(), pid);
@@ -185,15 +187,16 @@ fn sys_wait(pid: usize, code: *mut i32) -> SysResult {
 }
 
 fn sys_exec(name: *const u8, argc: usize, argv: *const *const u8, tf: &mut TrapFrame) -> SysResult {
-    // TODO: check ptr
-    let name = if name.is_null() { "" } else { unsafe { util::from_cstr(name) } };
+    let name = if name.is_null() { "" } else { UserCStr::new(name).as_str()? };
     info!("exec: {:?}, argc: {}, argv: {:?}", name, argc, argv);
     // Copy args to kernel
-    let args: Vec<String> = unsafe {
-        slice::from_raw_parts(argv, argc).iter()
-            .map(|&arg| String::from(util::from_cstr(arg)))
-            .collect()
-    };
+    let mut args = Vec::new();
+    for &arg in UserSlice::new(argv, argc).as_slice()? {
+        args.push(String::from(UserCStr::new(arg).as_str()?));
+    }
+    if args.is_empty() {
+        return Err(SysError::InvalidArgument);
+    }
 
     // Read program file
     let path = args[0].as_str();
@@ -274,6 +277,175 @@ fn sys_putc(c: char) -> SysResult {
     Ok(0)
 }
 
+fn sys_meminfo(info_ptr: *mut MemInfo) -> SysResult {
+    let info = memory::meminfo();
+    trace!("meminfo: {:?}", info);
+    UserPtr::new(info_ptr).write(info)?;
+    Ok(0)
+}
+
+fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    let (start, end) = page_range(addr, len)?;
+    info!("munmap: [{:#x}, {:#x})", start, end);
+    process().get_memory_set_mut().unmap(start, end);
+    Ok(0)
+}
+
+/// Map `len` bytes of the file `fd` from `offset`, or anonymous memory, with `prot` made of `ProtFlags`.
+/// Return the address of the mapping.
+fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> SysResult {
+    let prot = ProtFlags::from_bits(prot).ok_or(SysError::InvalidArgument)?;
+    let flags = MmapFlags::from_bits(flags).ok_or(SysError::InvalidArgument)?;
+    info!("mmap: addr: {:#x}, len: {:#x}, prot: {:?}, flags: {:?}, fd: {}, offset: {:#x}", addr, len, prot, flags, fd, offset);
+    let shared = flags.contains(MmapFlags::SHARED);
+    if shared == flags.contains(MmapFlags::PRIVATE) || offset % PAGE_SIZE != 0 {
+        return Err(SysError::InvalidArgument);
+    }
+    let huge = flags.contains(MmapFlags::HUGETLB);
+    if huge && (shared || !flags.contains(MmapFlags::ANONYMOUS)) {
+        return Err(SysError::InvalidArgument);
+    }
+    let file = match flags.contains(MmapFlags::ANONYMOUS) {
+        true => None,
+        false => {
+            let file = get_file(fd)?.lock().clone();
+            // changes of a shared mapping are written to the file
+            if !file.readable() || (shared && prot.contains(ProtFlags::WRITE) && !file.writable()) {
+                return Err(SysError::InvalidFile);
+            }
+            Some(file.inode())
+        }
+    };
+    let attr = prot_to_attr(prot)?;
+    let memory_set = process().get_memory_set_mut();
+    let (start, end) = match flags.contains(MmapFlags::FIXED) {
+        true => {
+            let (start, end) = page_range(addr, len)?;
+            // the address is returned in i32, see USER_MMAP_END
+            if end > USER_MMAP_END {
+                return Err(SysError::InvalidArgument);
+            }
+            memory_set.unmap(start, end);
+            (start, end)
+        }
+        false => {
+            let (_, len) = page_range(0, len)?;
+            // leave room to align the start to a huge page
+            let align = if huge { HUGE_PAGE_SIZE } else { PAGE_SIZE };
+            let start = memory_set.find_free_area(USER_MMAP_OFFSET, USER_MMAP_END, len + align - PAGE_SIZE).ok_or(SysError::InvalidArgument)?;
+            let start = (start + align - 1) & !(align - 1);
+            (start, start + len)
+        }
+    };
+    // private anonymous memory is demand-zero, unless mapped with huge pages
+    let handler: Box<MemoryHandler> = match (file.is_none() && !shared, huge) {
+        (true, true) => Box::new(HugePageMemoryHandler::new(attr)),
+        (true, false) => Box::new(ZeroMemoryHandler::new(COW_TABLE.clone(), SWAP_TABLE.clone(), attr)),
+        (false, _) => Box::new(MmapMemoryHandler::new(file, start, offset, attr, shared)),
+    };
+    memory_set.push(MemoryArea::new(start, end, handler, "mmap"));
+    Ok(start as i32)
+}
+
+/// Map the shared memory segment named `key` with `prot` made of `ProtFlags`,
+/// create it with `len` bytes if it does not exist. Return the address of the mapping.
+fn sys_shmem(key: usize, len: usize, prot: usize) -> SysResult {
+    let prot = ProtFlags::from_bits(prot).ok_or(SysError::InvalidArgument)?;
+    info!("shmem: key: {:#x}, len: {:#x}, prot: {:?}", key, len, prot);
+    let attr = prot_to_attr(prot)?;
+    let (_, len) = page_range(0, len)?;
+    let segment = ::shm::get_or_create(key, len).ok_or(SysError::InvalidArgument)?;
+    // map the whole segment, it may be longer than `len` if created by others
+    let len = segment.len();
+    let memory_set = process().get_memory_set_mut();
+    let start = memory_set.find_free_area(USER_MMAP_OFFSET, USER_MMAP_END, len).ok_or(SysError::InvalidArgument)?;
+    let handler = SharedMemoryHandler::new(segment, start, attr);
+    memory_set.push(MemoryArea::new(start, start + len, Box::new(handler), "shmem"));
+    Ok(start as i32)
+}
+
+fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+    let (start, end) = page_range(addr, len)?;
+    let prot = ProtFlags::from_bits(prot).ok_or(SysError::InvalidArgument)?;
+    info!("mprotect: [{:#x}, {:#x}) {:?}", start, end, prot);
+    let flags = prot_to_attr(prot)?;
+    match process().get_memory_set_mut().protect(start, end, flags) {
+        true => Ok(0),
+        false => Err(SysError::InvalidArgument),
+    }
+}
+
+/// Write the modified pages of shared file mappings in [addr, addr + len) back to the files
+fn sys_msync(addr: usize, len: usize) -> SysResult {
+    let (start, end) = page_range(addr, len)?;
+    info!("msync: [{:#x}, {:#x})", start, end);
+    match process().get_memory_set_mut().sync(start, end) {
+        true => Ok(0),
+        false => Err(SysError::InvalidArgument),
+    }
+}
+
+/// Print the pages mapped in the address space of the current process, for debugging
+fn sys_pgdir() -> SysResult {
+    info!("pgdir");
+    memory::dump_page_table(0, usize::max_value());
+    Ok(0)
+}
+
+/// Set the end of the user heap to `addr`, the new pages are demand-zero.
+/// Return the new end, or the current one if `addr` is 0 or it fails.
+fn sys_brk(addr: usize) -> SysResult {
+    let context = process();
+    info!("brk: {:#x} -> {:#x}", context.brk, addr);
+    let (heap_start, brk) = (context.heap_start, context.brk);
+    if addr < heap_start || addr > USER_MMAP_OFFSET {
+        return Ok(brk as i32);
+    }
+    let end = (brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let new_end = (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    {
+        let memory_set = context.get_memory_set_mut();
+        if new_end > end {
+            // the heap area may be missing, if the heap is empty or its end is unmapped by munmap
+            let extended = end != heap_start && memory_set.extend(end, new_end);
+            if !extended {
+                if memory_set.find_free_area(end, new_end, new_end - end) != Some(end) {
+                    return Ok(brk as i32);
+                }
+                let handler = ZeroMemoryHandler::new(COW_TABLE.clone(), SWAP_TABLE.clone(), MemoryAttr::default().user());
+                memory_set.push(MemoryArea::new(end, new_end, Box::new(handler), "user_heap"));
+            }
+        } else if new_end < end {
+            memory_set.unmap(new_end, end);
+        }
+    }
+    context.brk = addr;
+    Ok(addr as i32)
+}
+
+/// Convert `ProtFlags` of mmap and mprotect to the attributes of user pages
+fn prot_to_attr(prot: ProtFlags) -> Result<MemoryAttr, SysError> {
+    // pages can't be made inaccessible yet
+    if !prot.contains(ProtFlags::READ) {
+        return Err(SysError::InvalidArgument);
+    }
+    let mut flags = MemoryAttr::default().user();
+    if !prot.contains(ProtFlags::WRITE) { flags = flags.readonly(); }
+    if prot.contains(ProtFlags::EXEC) { flags = flags.execute(); }
+    Ok(flags)
+}
+
+/// Check the address range for mmap, shmem, munmap, mprotect and msync, and round its end up to page boundary
+fn page_range(addr: usize, len: usize) -> Result<(usize, usize), SysError> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(SysError::InvalidArgument);
+    }
+    let end = addr.checked_add(len)
+        .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+        .ok_or(SysError::InvalidArgument)? & !(PAGE_SIZE - 1);
+    Ok((addr, end))
+}
+
 fn get_file(fd: usize) -> Result<&'static Arc<Mutex<File>>, SysError> {
     process().files.get(&fd).ok_or(SysError::InvalidFile)
 }
@@ -285,6 +457,8 @@ pub enum SysError {
     VfsError,
     InvalidFile,
     InvalidArgument,
+    /// bad address of user memory
+    Fault,
 }
 
 impl From<()> for SysError {
@@ -316,6 +490,29 @@ impl VfsFlags {
     }
 }
 
+bitflags! {
+    struct ProtFlags: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    struct MmapFlags: usize {
+        /// changes are written to the file, and shared with forked processes
+        const SHARED = 1 << 0;
+        /// changes are private to the process
+        const PRIVATE = 1 << 1;
+        /// map at the address exactly, replacing the pages there
+        const FIXED = 1 << 4;
+        /// not backed by a file, filled with zero
+        const ANONYMOUS = 1 << 5;
+        /// map private anonymous memory with huge pages where aligned, allocated when mapped
+        const HUGETLB = 0x40000;
+    }
+}
+
 #[repr(C)]
 struct DirEntry {
     offset: u32,
@@ -0,0 +1,295 @@
+//! TLB shootdown
+//!
+//! The arch code flushes a changed page table entry from the local TLB right away, and records
+//! its address by `invalidate` in a batch of the current CPU. The batch is sent to the other CPUs
+//! which may cache the entry by `flush_others`, when the active table is released, so a whole
+//! munmap or fork costs one round of IPIs. The CPUs running the changed page table are found
+//! by the tokens recorded on switching page tables, entries shared by all page tables go to
+//! all the CPUs.
+//!
+//! A CPU waiting for the others to flush keeps flushing for them, in case they wait for it too.
+//! CPUs spinning on a `SpinNoIrqLock` with interrupts disabled flush by `poll`, so the IPI
+//! is not needed for them, see `SpinNoIrq::cpu_relax`.
+//!
+//! Page tables are tagged with ASIDs (PCIDs on x86_64) if supported, so switching page tables
+//! keeps their entries in the TLB. A CPU not running a page table may still cache its entries,
+//! so it flushes the ASID when switching to it after a shootdown, see `switch_asid`.
+//! ASIDs are allocated from 1 to page tables on switching to them. When they run out, a new
+//! generation begins, and each CPU flushes its whole TLB before using the ASIDs again.
+//! With the feature `no_asid`, they are not used, as if not supported.
+
+use arch::cpu;
+use consts::MAX_CPU_NUM;
+use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
+use memory::{ActivePageTable, local_flush_tlb, local_flush_tlb_all, local_flush_tlb_all_asids, max_asid};
+use sync::SpinNoIrqLock;
+use alloc::vec::Vec;
+
+// more addresses in a batch are flushed with the whole TLB
+const BATCH_SIZE: usize = 16;
+// an ASID is kept with its generation as `generation << ASID_BITS | asid`
+const ASID_BITS: usize = 16;
+
+/// The addresses to flush from the TLB of the CPUs running a page table
+#[derive(Default, Clone, Copy)]
+struct Batch {
+    /// the token of the page table, 0 for all the CPUs
+    token: usize,
+    /// the ASID of the page table, 0 if none
+    asid: usize,
+    addrs: [usize; BATCH_SIZE],
+    len: usize,
+    /// too many addresses, flush the whole TLB
+    all: bool,
+    /// flush the entries of all ASIDs, for the entries shared by all page tables
+    all_asids: bool,
+    used: bool,
+}
+
+impl Batch {
+    fn push(&mut self, token: usize, asid: usize, addr: usize) {
+        // addresses of different page tables are flushed on all the CPUs
+        if !self.used {
+            self.token = token;
+            self.asid = asid;
+            self.used = true;
+        } else if self.token != token {
+            self.token = 0;
+        }
+        match self.len < BATCH_SIZE {
+            true => { self.addrs[self.len] = addr; self.len += 1; }
+            false => self.all = true,
+        }
+    }
+
+    fn flush_local(&self) {
+        match (self.all_asids, self.all) {
+            (true, _) => local_flush_tlb_all_asids(),
+            (false, true) => local_flush_tlb_all(),
+            (false, false) => self.addrs[..self.len].iter().for_each(|&addr| local_flush_tlb(addr)),
+        }
+    }
+}
+
+lazy_static! {
+    static ref BATCHES: [SpinNoIrqLock<Batch>; MAX_CPU_NUM] = Default::default();
+    // the page table each CPU is running, 0 if not online, and its ASID
+    static ref ACTIVE_TOKENS: [AtomicUsize; MAX_CPU_NUM] = Default::default();
+    static ref ACTIVE_ASIDS: [AtomicUsize; MAX_CPU_NUM] = Default::default();
+    // the page table each CPU is editing through `InactivePageTable::edit`, 0 if none, and its ASID
+    static ref EDITING_TOKENS: [AtomicUsize; MAX_CPU_NUM] = Default::default();
+    static ref EDITING_ASIDS: [AtomicUsize; MAX_CPU_NUM] = Default::default();
+    // one shootdown at a time, its request is in REQUEST
+    static ref SHOOTDOWN: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+    static ref ASID_ALLOCATOR: SpinNoIrqLock<AsidAllocator> = SpinNoIrqLock::new(AsidAllocator { generation: 1, next: 1 });
+    // the CPUs which should flush each ASID before switching to it
+    static ref STALE_ASIDS: Vec<AtomicUsize> = (0..=MAX_ASID.load(Ordering::SeqCst)).map(|_| AtomicUsize::new(0)).collect();
+}
+
+static mut REQUEST: Batch = Batch { token: 0, asid: 0, addrs: [0; BATCH_SIZE], len: 0, all: false, all_asids: false, used: false };
+// the CPUs which have not flushed the REQUEST yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+// the largest ASID, 0 if ASIDs are not supported or not initialized yet
+static MAX_ASID: AtomicUsize = AtomicUsize::new(0);
+// the CPUs which should flush the whole TLB before switching to an ASID of the current generation
+static ROLLOVER: AtomicUsize = AtomicUsize::new(0);
+
+struct AsidAllocator {
+    generation: usize,
+    next: usize,
+}
+
+/// Take part in TLB shootdowns, called on each CPU before running processes
+pub fn init() {
+    // without ASIDs, the whole TLB is flushed on switching page tables
+    let max = if cfg!(feature = "no_asid") { 0 } else { max_asid() };
+    MAX_ASID.store(max, Ordering::SeqCst);
+    set_active(ActivePageTable::token(), 0);
+}
+
+/// Record the page table the current CPU is switching to, with its ASID
+fn set_active(token: usize, asid: usize) {
+    let id = cpu::id();
+    ACTIVE_ASIDS[id].store(asid, Ordering::SeqCst);
+    ACTIVE_TOKENS[id].store(token, Ordering::SeqCst);
+}
+
+/// The ASID in `slot` of a page table, 0 if it is not allocated in the current generation
+fn current_asid(slot: &AtomicUsize) -> usize {
+    let value = slot.load(Ordering::SeqCst);
+    match value >> ASID_BITS == ASID_ALLOCATOR.lock().generation {
+        true => value & ((1 << ASID_BITS) - 1),
+        false => 0,
+    }
+}
+
+/*
+* @param:
+*   slot: the ASID of the page table with its generation, 0 if never allocated
+*   token: the token of the page table
+* @brief:
+*   get the ASID to switch to the page table on the current CPU, allocate one if it is from an old generation.
+*   the page table is recorded as running on the current CPU, so it is called right before switching.
+* @retval:
+*   the ASID, 0 if ASIDs are not supported,
+*   and whether the entries of the ASID in the TLB of the current CPU should be flushed
+*/
+pub fn switch_asid(slot: &AtomicUsize, token: usize) -> (usize, bool) {
+    if MAX_ASID.load(Ordering::SeqCst) == 0 {
+        set_active(token, 0);
+        return (0, true);
+    }
+    let bit = 1 << cpu::id();
+    let asid = {
+        let mut allocator = ASID_ALLOCATOR.lock();
+        let mut value = slot.load(Ordering::SeqCst);
+        if value >> ASID_BITS != allocator.generation {
+            if allocator.next > MAX_ASID.load(Ordering::SeqCst) {
+                // the generation is kept in the rest bits, and never 0
+                allocator.generation = allocator.generation % (usize::max_value() >> ASID_BITS) + 1;
+                allocator.next = 1;
+                ROLLOVER.store(!0, Ordering::SeqCst);
+            }
+            value = allocator.generation << ASID_BITS | allocator.next;
+            allocator.next += 1;
+            slot.store(value, Ordering::SeqCst);
+        }
+        value & ((1 << ASID_BITS) - 1)
+    };
+    // recorded before checking for stale entries, see `flush_others`
+    set_active(token, asid);
+    let stale = STALE_ASIDS[asid].fetch_and(!bit, Ordering::SeqCst) & bit != 0;
+    if ROLLOVER.fetch_and(!bit, Ordering::SeqCst) & bit != 0 {
+        local_flush_tlb_all_asids();
+        return (asid, false);
+    }
+    (asid, stale)
+}
+
+/*
+* @param:
+*   token: the token of the page table
+*   asid: its ASID, which was in use on the current CPU before switching to another page table
+* @brief:
+*   switch back to the page table after running another one for a while, e.g. in `InactivePageTable::with`
+* @retval:
+*   whether the entries of the ASID in the TLB of the current CPU should be flushed
+*/
+pub fn resume_asid(token: usize, asid: usize) -> bool {
+    set_active(token, asid);
+    let bit = 1 << cpu::id();
+    asid == 0 || STALE_ASIDS[asid].fetch_and(!bit, Ordering::SeqCst) & bit != 0
+}
+
+/*
+* @param:
+*   token: the token of the inactive page table
+*   asid_slot: its ASID, see `switch_asid`
+*   f: the function editing it
+* @brief:
+*   record the entries changed in f as entries of the inactive page table, not the active one
+*/
+pub fn editing<T>(token: usize, asid_slot: &AtomicUsize, f: impl FnOnce() -> T) -> T {
+    let id = cpu::id();
+    let asid = match MAX_ASID.load(Ordering::SeqCst) {
+        0 => 0,
+        _ => current_asid(asid_slot),
+    };
+    let old_token = EDITING_TOKENS[id].swap(token, Ordering::SeqCst);
+    let old_asid = EDITING_ASIDS[id].swap(asid, Ordering::SeqCst);
+    let ret = f();
+    EDITING_ASIDS[id].store(old_asid, Ordering::SeqCst);
+    EDITING_TOKENS[id].store(old_token, Ordering::SeqCst);
+    ret
+}
+
+/*
+* @param:
+*   addr: the virtual address whose entry is changed, already flushed from the local TLB
+*   global: whether the entry is shared by all page tables, e.g. kernel mappings
+* @brief:
+*   record the address to flush from the TLB of other CPUs by `flush_others`
+*/
+pub fn invalidate(addr: usize, global: bool) {
+    let id = cpu::id();
+    let (token, asid) = match (global, EDITING_TOKENS[id].load(Ordering::SeqCst)) {
+        (true, _) => (0, 0),
+        (false, 0) => (ACTIVE_TOKENS[id].load(Ordering::SeqCst), ACTIVE_ASIDS[id].load(Ordering::SeqCst)),
+        (false, token) => (token, EDITING_ASIDS[id].load(Ordering::SeqCst)),
+    };
+    BATCHES[id].lock().push(token, asid, addr);
+}
+
+/*
+* @brief:
+*   flush the addresses recorded on the current CPU from the TLB of the other CPUs, and wait for them.
+*   it should be called without holding any lock but SpinNoIrqLocks, whose waiters flush by `poll`,
+*   since the waiters of other locks may spin with interrupts disabled and never handle the IPI.
+*/
+pub fn flush_others() {
+    let mut batch = {
+        let mut batch = BATCHES[cpu::id()].lock();
+        let taken = *batch;
+        *batch = Batch::default();
+        taken
+    };
+    if !batch.used {
+        return;
+    }
+    // waiting for the lock also polls, since it is a SpinNoIrqLock
+    let _guard = SHOOTDOWN.lock();
+    let id = cpu::id();
+    let cpus = |f: &Fn(usize) -> bool| (0..MAX_CPU_NUM).filter(|&i| f(ACTIVE_TOKENS[i].load(Ordering::SeqCst)))
+        .fold(0, |mask, i| mask | (1 << i));
+    let online = cpus(&|token| token != 0);
+    if MAX_ASID.load(Ordering::SeqCst) != 0 {
+        match batch.token {
+            // the entries shared by all page tables are cached for each ASID
+            0 => {
+                batch.all_asids = true;
+                local_flush_tlb_all_asids();
+            }
+            // the CPUs not running the page table may cache the entries of its ASID, they flush it when
+            // switching to it. it is marked before finding the CPUs running the page table, see `switch_asid`
+            token if batch.asid != 0 => {
+                let me = match ACTIVE_TOKENS[id].load(Ordering::SeqCst) == token {
+                    true => 1 << id,
+                    false => 0,
+                };
+                STALE_ASIDS[batch.asid].fetch_or(online & !me, Ordering::SeqCst);
+            }
+            _ => {}
+        }
+    }
+    let targets = match batch.token {
+        0 => online,
+        token => cpus(&|running| running == token),
+    } & !(1 << id);
+    if targets == 0 {
+        return;
+    }
+    unsafe { REQUEST = batch; }
+    PENDING.store(targets, Ordering::SeqCst);
+    for i in (0..MAX_CPU_NUM).filter(|&i| targets & (1 << i) != 0) {
+        cpu::send_ipi(i);
+    }
+    // keep serving shootdowns for the CPUs we wait for, e.g. one waiting for SHOOTDOWN with interrupts disabled
+    while PENDING.load(Ordering::SeqCst) != 0 {
+        poll();
+        spin_loop_hint();
+    }
+}
+
+/*
+* @brief:
+*   flush the TLB of the current CPU if it is asked to by a shootdown, called by the IPI handler
+*   and when spinning with interrupts disabled
+*/
+pub fn poll() {
+    let bit = 1 << cpu::id();
+    if PENDING.load(Ordering::SeqCst) & bit == 0 {
+        return;
+    }
+    unsafe { REQUEST.flush_local(); }
+    PENDING.fetch_and(!bit, Ordering::SeqCst);
+}
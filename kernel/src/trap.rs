@@ -7,6 +7,7 @@ pub static mut TICK: usize = 0;
 pub fn timer() {
     if cpu::id() == 0 {
         unsafe { TICK += 1; }
+        ::memory::swap_tick();
     }
     processor().tick();
 }
@@ -21,6 +22,10 @@ pub fn error(tf: &TrapFrame) -> ! {
     unreachable!();
 }
 
+pub fn ipi() {
+    ::tlb::poll();
+}
+
 pub fn serial(c: char) {
     ::fs::STDIN.push(c);
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+//! Checked access to user memory for syscalls
+//!
+//! Pointers given by user programs are checked against the memory areas of the current process
+//! and their attributes. Their pages are faulted in before the kernel touches them,
+//! e.g. if they are allocated lazily, swapped out or copy-on-write.
+//! A bad pointer fails the syscall with `SysError::Fault`, instead of crashing the kernel.
+
+use core::{mem, slice, str};
+use memory::{self, Access, PageFault, active_table};
+use process::process;
+use syscall::SysError;
+use ucore_memory::{Page, PAGE_SIZE};
+use ucore_memory::paging::{PageTable, Entry};
+
+/// The maximum length of a C string from user programs, including the ending NUL
+const MAX_CSTR_LEN: usize = 4096;
+
+/// Pointer to a value in user memory
+pub struct UserPtr<T> {
+    ptr: *mut T,
+}
+
+impl<T> UserPtr<T> {
+    pub fn new(ptr: *mut T) -> Self {
+        UserPtr { ptr }
+    }
+
+    fn check(&self, access: Access) -> Result<(), SysError> {
+        if self.ptr as usize % mem::align_of::<T>() != 0 {
+            return Err(SysError::Fault);
+        }
+        check(self.ptr as usize, mem::size_of::<T>(), access)
+    }
+
+    pub fn read(&self) -> Result<T, SysError> where T: Copy {
+        self.check(Access::Read)?;
+        Ok(unsafe { self.ptr.read() })
+    }
+
+    pub fn write(&self, value: T) -> Result<(), SysError> {
+        self.check(Access::Write)?;
+        unsafe { self.ptr.write(value); }
+        Ok(())
+    }
+
+    /// Get a mutable reference to the value, to read and modify it in place
+    pub fn as_mut(&self) -> Result<&'static mut T, SysError> {
+        self.check(Access::Write)?;
+        Ok(unsafe { &mut *self.ptr })
+    }
+}
+
+/// Slice of values in user memory
+pub struct UserSlice<T> {
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T> UserSlice<T> {
+    pub fn new(ptr: *const T, len: usize) -> Self {
+        UserSlice { ptr: ptr as *mut T, len }
+    }
+
+    fn check(&self, access: Access) -> Result<(), SysError> {
+        if self.ptr as usize % mem::align_of::<T>() != 0 {
+            return Err(SysError::Fault);
+        }
+        let size = self.len.checked_mul(mem::size_of::<T>()).ok_or(SysError::Fault)?;
+        check(self.ptr as usize, size, access)
+    }
+
+    pub fn as_slice(&self) -> Result<&'static [T], SysError> {
+        self.check(Access::Read)?;
+        Ok(unsafe { slice::from_raw_parts(self.ptr, self.len) })
+    }
+
+    pub fn as_mut_slice(&self) -> Result<&'static mut [T], SysError> {
+        self.check(Access::Write)?;
+        Ok(unsafe { slice::from_raw_parts_mut(self.ptr, self.len) })
+    }
+}
+
+/// NUL terminated string in user memory
+pub struct UserCStr {
+    ptr: *const u8,
+}
+
+impl UserCStr {
+    pub fn new(ptr: *const u8) -> Self {
+        UserCStr { ptr }
+    }
+
+    /*
+    * @brief:
+    *   find the ending NUL page by page, checking each page before reading it
+    * @retval:
+    *   the string without the ending NUL, Err(SysError::InvalidArgument) if too long or not UTF-8
+    */
+    pub fn as_str(&self) -> Result<&'static str, SysError> {
+        let start = self.ptr as usize;
+        let mut len = 0;
+        loop {
+            if len == MAX_CSTR_LEN {
+                return Err(SysError::InvalidArgument);
+            }
+            let addr = start.checked_add(len).ok_or(SysError::Fault)?;
+            let chunk = (Page::of_addr(addr).start_address() + PAGE_SIZE - addr).min(MAX_CSTR_LEN - len);
+            check(addr, chunk, Access::Read)?;
+            let bytes = unsafe { slice::from_raw_parts(addr as *const u8, chunk) };
+            match bytes.iter().position(|&byte| byte == 0) {
+                Some(i) => { len += i; break; }
+                None => len += chunk,
+            }
+        }
+        str::from_utf8(unsafe { slice::from_raw_parts(self.ptr, len) }).map_err(|_| SysError::InvalidArgument)
+    }
+}
+
+/*
+* @param:
+*   addr: the beginning of the user memory
+*   len: the length of the user memory
+*   access: the kind of the access from the kernel
+* @brief:
+*   check that [addr, addr + len) is in the memory areas of the current process allowing the access,
+*   and fault in its pages
+* @retval:
+*   Err(SysError::Fault) if any page is not accessible
+*/
+pub fn check(addr: usize, len: usize, access: Access) -> Result<(), SysError> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = addr.checked_add(len).ok_or(SysError::Fault)?;
+    for page in Page::range_of(addr, end) {
+        // the first page may begin before addr, and it may be in another area
+        check_page(page.start_address().max(addr), access)?;
+    }
+    Ok(())
+}
+
+/*
+* @param:
+*   addr: the virtual address in the page to check
+*   access: the kind of the access from the kernel
+* @brief:
+*   check the page like the CPU does for the user, and handle the page fault it would cause
+* @retval:
+*   Err(SysError::Fault) if the page is not accessible
+*/
+fn check_page(addr: usize, access: Access) -> Result<(), SysError> {
+    let allowed = process().get_memory_set_mut().find_area(addr)
+        .map(|area| area.get_flags().is_user() && area.get_flags().allows(access));
+    let (present, writable) = match active_table().get_entry(addr) {
+        Some(entry) => (entry.present(), entry.writable()),
+        None => (false, false),
+    };
+    match allowed {
+        Some(false) => return Err(SysError::Fault),
+        // mapped but not in any area, e.g. the kernel
+        None if present => return Err(SysError::Fault),
+        _ => {}
+    }
+    if present && (access != Access::Write || writable) {
+        return Ok(());
+    }
+    // an address not in any area may be below the stack, which grows on page fault
+    let fault = PageFault { addr, access, user: true, present };
+    match memory::page_fault_handler(fault) {
+        true => Ok(()),
+        false => Err(SysError::Fault),
+    }
+}
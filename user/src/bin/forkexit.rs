@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::*;
+use core::ptr;
+
+const ROUNDS: usize = 100;
+// 4M apart, so each mapping needs its own page tables on both x86_64 and riscv32
+const MAP_BASE: usize = 0x5000_0000;
+const MAP_STRIDE: usize = 0x40_0000;
+const MAP_NUM: usize = 4;
+
+/// Frames in use by the kernel heap or free, the rest are used by processes and their page tables
+fn frames_left() -> usize {
+    let mut info = MemInfo::default();
+    sys_meminfo(&mut info);
+    info.free + info.kernel_heap
+}
+
+/// Map and touch pages far apart, unmap half of them, and leave the rest to exit
+fn child() -> ! {
+    for i in 0..MAP_NUM {
+        let addr = MAP_BASE + i * MAP_STRIDE;
+        if sys_mmap(addr, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED, 0, 0) == -1 {
+            sys_exit(1);
+        }
+        unsafe { ptr::write_volatile(addr as *mut usize, i); }
+    }
+    for i in (0..MAP_NUM).step_by(2) {
+        sys_munmap(MAP_BASE + i * MAP_STRIDE, 4096);
+    }
+    sys_exit(0);
+}
+
+fn fork_and_wait() -> bool {
+    let pid = sys_fork();
+    if pid == 0 {
+        child();
+    }
+    let mut code = 0;
+    sys_wait(pid as usize, &mut code);
+    code == 0
+}
+
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    // the first round may grow the kernel heap, which is not given back
+    if !fork_and_wait() {
+        println!("forkexit: fail, the child failed to map memory");
+        return;
+    }
+    let before = frames_left();
+    for _ in 0..ROUNDS {
+        if !fork_and_wait() {
+            println!("forkexit: fail, the child failed to map memory");
+            return;
+        }
+    }
+    let after = frames_left();
+    match after == before {
+        true => println!("forkexit: pass"),
+        false => println!("forkexit: fail, {} frames leaked in {} rounds", before as isize - after as isize, ROUNDS),
+    }
+}
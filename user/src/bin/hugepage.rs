@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::*;
+use core::{ptr, mem};
+
+// two huge pages on both x86_64 (2M) and riscv32 (4M)
+const LEN: usize = 8 << 20;
+const WORDS: usize = LEN / mem::size_of::<usize>();
+const STEP: usize = 4096 / mem::size_of::<usize>();
+
+fn check(base: *mut usize, from: usize, to: usize) -> bool {
+    (from..to).step_by(STEP).all(|i| unsafe { ptr::read_volatile(base.add(i)) } == i)
+}
+
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    let addr = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_HUGETLB, 0, 0);
+    if addr == -1 {
+        println!("hugepage: fail, failed to map memory");
+        return;
+    }
+    let base = addr as usize as *mut usize;
+    if unsafe { ptr::read_volatile(base.add(WORDS - 1)) } != 0 {
+        println!("hugepage: fail, the memory is not zeroed");
+        return;
+    }
+    for i in (0..WORDS).step_by(STEP) {
+        unsafe { ptr::write_volatile(base.add(i), i); }
+    }
+    // the child gets its own copy of the huge pages
+    let pid = sys_fork();
+    if pid == 0 {
+        let ok = check(base, 0, WORDS);
+        unsafe { ptr::write_volatile(base, 1); }
+        sys_exit(if ok { 0 } else { 1 });
+    }
+    let mut code = 0;
+    sys_wait(pid as usize, &mut code);
+    if code != 0 || !check(base, 0, WORDS) {
+        println!("hugepage: fail, the memory is not copied on fork");
+        return;
+    }
+    // unmapping a part in the middle splits the huge pages across it
+    sys_munmap(addr as usize + LEN / 4, LEN / 2);
+    match check(base, 0, WORDS / 4) && check(base, WORDS * 3 / 4, WORDS) {
+        true => println!("hugepage: pass"),
+        false => println!("hugepage: fail, the memory is lost after a partial munmap"),
+    }
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::{sys_meminfo, MemInfo};
+
+const PAGE_SIZE_KB: usize = 4;
+
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    let mut info = MemInfo::default();
+    if sys_meminfo(&mut info) != 0 {
+        println!("meminfo: failed to get memory info");
+        return;
+    }
+    println!("MemTotal:    {:>8} kB", info.total * PAGE_SIZE_KB);
+    println!("MemFree:     {:>8} kB", info.free * PAGE_SIZE_KB);
+    println!("MemUsed:     {:>8} kB", (info.total - info.free) * PAGE_SIZE_KB);
+    println!("KernelHeap:  {:>8} kB", info.kernel_heap * PAGE_SIZE_KB);
+    println!("Swapped:     {:>8} kB", info.swapped * PAGE_SIZE_KB);
+    println!("Cow:         {:>8} kB", info.cow * PAGE_SIZE_KB);
+    println!("CowShared:   {:>8} kB", info.cow_shared * PAGE_SIZE_KB);
+    println!("FrameCached: {:>8} kB", info.cached * PAGE_SIZE_KB);
+    println!("FrameLock:   {:>8} acquired, {} contended", info.frame_lock_acquired, info.frame_lock_contended);
+}
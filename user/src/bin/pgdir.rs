@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::*;
+use core::ptr;
+
+const LEN: usize = 4 * 4096;
+
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    let addr = sys_mmap(0, LEN, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, 0, 0);
+    if addr == -1 {
+        println!("pgdir: fail, failed to map memory");
+        return;
+    }
+    // the first half is written before fork, so it is shared for copy-on-write after it
+    for i in (0..LEN / 2).step_by(4096) {
+        unsafe { ptr::write_volatile((addr as usize + i) as *mut usize, i); }
+    }
+    let pid = sys_fork();
+    if pid == 0 {
+        println!("pgdir: child, {:#x} mapped", addr);
+        sys_pgdir();
+        sys_exit(0);
+    }
+    sys_wait(pid as usize, ptr::null_mut());
+    println!("pgdir: parent, {:#x} mapped", addr);
+    sys_pgdir();
+}
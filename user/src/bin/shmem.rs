@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::*;
+use core::ptr;
+
+const KEY: usize = 0x5348;
+const SLOTS: usize = 16;
+const COUNT: u32 = 100;
+
+/// A ring buffer in the shared memory segment
+#[repr(C)]
+struct Ring {
+    head: u32,
+    tail: u32,
+    slots: [u32; SLOTS],
+}
+
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    let addr = sys_shmem(KEY, 4096, PROT_READ | PROT_WRITE);
+    if addr == -1 {
+        println!("shmem: failed to map the segment");
+        return;
+    }
+    let ring = addr as usize as *mut Ring;
+    let pid = sys_fork();
+    if pid == 0 {
+        // consumer
+        let mut sum = 0;
+        for _ in 0..COUNT {
+            unsafe {
+                while ptr::read_volatile(&(*ring).head) == ptr::read_volatile(&(*ring).tail) {
+                    sys_yield();
+                }
+                let head = (*ring).head;
+                sum += ptr::read_volatile(&(*ring).slots[head as usize % SLOTS]);
+                ptr::write_volatile(&mut (*ring).head, head + 1);
+            }
+        }
+        println!("consumer: sum {}", sum);
+        sys_exit(sum as usize);
+    }
+    // producer
+    for i in 1..=COUNT {
+        unsafe {
+            while ptr::read_volatile(&(*ring).tail) - ptr::read_volatile(&(*ring).head) == SLOTS as u32 {
+                sys_yield();
+            }
+            let tail = (*ring).tail;
+            ptr::write_volatile(&mut (*ring).slots[tail as usize % SLOTS], i);
+            ptr::write_volatile(&mut (*ring).tail, tail + 1);
+        }
+    }
+    let mut code = 0;
+    sys_wait(pid as usize, &mut code);
+    match code as u32 == COUNT * (COUNT + 1) / 2 {
+        true => println!("shmem: pass"),
+        false => println!("shmem: fail, consumer got {}", code),
+    }
+    sys_munmap(addr as usize, 4096);
+}
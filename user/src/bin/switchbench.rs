@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate ucore_ulib;
+use ucore_ulib::syscall::*;
+use core::ptr;
+
+const ROUNDS: usize = 1000;
+const MAX_PAGES: usize = 64;
+const STEP: usize = 4096;
+
+static mut BUF: [u8; MAX_PAGES * STEP] = [0; MAX_PAGES * STEP];
+
+/// Touch the first `pages` pages of the buffer, so they are cached in the TLB
+fn touch(pages: usize) {
+    for i in 0..pages {
+        unsafe { ptr::write_volatile(&mut BUF[i * STEP], i as u8); }
+    }
+}
+
+/// Switch between two processes touching `pages` pages each time, in timer ticks
+fn bench(pages: usize) -> Option<i32> {
+    touch(pages);
+    let pid = sys_fork();
+    if pid == 0 {
+        for _ in 0..ROUNDS {
+            touch(pages);
+            sys_yield();
+        }
+        sys_exit(0);
+    }
+    if pid < 0 {
+        return None;
+    }
+    let start = sys_get_time();
+    for _ in 0..ROUNDS {
+        touch(pages);
+        sys_yield();
+    }
+    let mut code = 0;
+    sys_wait(pid as usize, &mut code);
+    match code {
+        0 => Some(sys_get_time() - start),
+        _ => None,
+    }
+}
+
+// Run it on the kernel built with and without `no_asid`, to compare switching page tables
+// with a full TLB flush and with ASIDs (PCIDs on x86_64)
+// IMPORTANT: Must define main() like this
+#[no_mangle]
+pub fn main() {
+    // the more pages touched, the more TLB misses after a switch flushing the TLB
+    for &pages in [0, 16, MAX_PAGES].iter() {
+        match bench(pages) {
+            Some(ticks) => println!("switchbench: {} pages, {} rounds, {} ticks", pages, ROUNDS, ticks),
+            None => println!("switchbench: fail, {} pages", pages),
+        }
+    }
+}
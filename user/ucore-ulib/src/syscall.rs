@@ -112,6 +112,95 @@ pub fn sys_putc(c: char) -> i32 {
     sys_call(SYS_PUTC, c as usize, 0, 0, 0, 0, 0)
 }
 
+/// Memory usage statistics, all in pages
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MemInfo {
+    /// frames managed by the frame allocator
+    pub total: usize,
+    /// free frames
+    pub free: usize,
+    /// frames mapped in the kernel heap area
+    pub kernel_heap: usize,
+    /// pages swapped out
+    pub swapped: usize,
+    /// frames mapped as copy-on-write
+    pub cow: usize,
+    /// copy-on-write frames shared by more than one page
+    pub cow_shared: usize,
+    /// free frames in the per-CPU caches, included in `free`
+    pub cached: usize,
+    /// times the global frame allocator is locked
+    pub frame_lock_acquired: usize,
+    /// times the global frame allocator is found locked by others
+    pub frame_lock_contended: usize,
+}
+
+/// Get memory usage statistics of the system
+pub fn sys_meminfo(info: &mut MemInfo) -> i32 {
+    sys_call(SYS_MEMINFO, info as *mut MemInfo as usize, 0, 0, 0, 0, 0)
+}
+
+/// Unmap the pages in [addr, addr + len), `addr` should be page aligned
+pub fn sys_munmap(addr: usize, len: usize) -> i32 {
+    sys_call(SYS_MUNMAP, addr, len, 0, 0, 0, 0)
+}
+
+pub const MAP_SHARED: usize = 1 << 0;
+pub const MAP_PRIVATE: usize = 1 << 1;
+pub const MAP_FIXED: usize = 1 << 4;
+pub const MAP_ANONYMOUS: usize = 1 << 5;
+pub const MAP_HUGETLB: usize = 0x40000;
+
+/// Map `len` bytes of the file `fd` from `offset`, or anonymous memory with `MAP_ANONYMOUS`.
+/// `prot` is made of `PROT_*`, `flags` is made of `MAP_*`, with exactly one of `MAP_SHARED` and `MAP_PRIVATE`.
+/// `addr` is used only with `MAP_FIXED`. Return the address of the mapping, or -1
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> i32 {
+    sys_call(SYS_MMAP, addr, len, prot, flags, fd, offset)
+}
+
+/// Write the modified pages of shared file mappings in [addr, addr + len) back to the files
+pub fn sys_msync(addr: usize, len: usize) -> i32 {
+    sys_call(SYS_MSYNC, addr, len, 0, 0, 0, 0)
+}
+
+/// Map the shared memory segment named `key` with `prot` made of `PROT_*`,
+/// create it with `len` bytes if it does not exist. The segment is shared with forked processes too.
+/// Return the address of the mapping, or -1. Unmap it with `sys_munmap`
+pub fn sys_shmem(key: usize, len: usize, prot: usize) -> i32 {
+    sys_call(SYS_SHMEM, key, len, prot, 0, 0, 0)
+}
+
+/// Print the pages mapped in the address space of the current process to the console
+pub fn sys_pgdir() -> i32 {
+    sys_call(SYS_PGDIR, 0, 0, 0, 0, 0, 0)
+}
+
+/// Set the end of the heap to `addr`. Return the new end, or the current one if `addr` is 0 or it fails
+pub fn sys_brk(addr: usize) -> i32 {
+    sys_call(SYS_BRK, addr, 0, 0, 0, 0, 0)
+}
+
+/// Grow the heap by `increment` bytes, or shrink it if negative. Return the old end, or -1
+pub fn sys_sbrk(increment: isize) -> i32 {
+    let old = sys_brk(0);
+    let new = (old as isize + increment) as usize;
+    match increment == 0 || sys_brk(new) == new as i32 {
+        true => old,
+        false => -1,
+    }
+}
+
+pub const PROT_READ: usize = 1 << 0;
+pub const PROT_WRITE: usize = 1 << 1;
+pub const PROT_EXEC: usize = 1 << 2;
+
+/// Change the protection of the pages in [addr, addr + len) to `prot`, made of `PROT_*`.
+/// `addr` should be page aligned, and all the pages should be mapped
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> i32 {
+    sys_call(SYS_MPROTECT, addr, len, prot, 0, 0, 0)
+}
+
 const SYS_EXIT: usize = 1;
 const SYS_FORK: usize = 2;
 const SYS_WAIT: usize = 3;
@@ -125,6 +214,10 @@ const SYS_GETPID: usize = 18;
 const SYS_MMAP: usize = 20;
 const SYS_MUNMAP: usize = 21;
 const SYS_SHMEM: usize = 22;
+const SYS_MEMINFO: usize = 23;
+const SYS_MPROTECT: usize = 24;
+const SYS_MSYNC: usize = 25;
+const SYS_BRK: usize = 26;
 const SYS_PUTC: usize = 30;
 const SYS_PGDIR: usize = 31;
 const SYS_OPEN: usize = 100;